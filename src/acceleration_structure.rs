@@ -1,17 +1,34 @@
 use std::sync::Arc;
-use cgmath::{ElementWise, EuclideanSpace, MetricSpace, Vector2};
-use crate::material::{Material, PhysicalMaterial};
+use cgmath::{ElementWise, EuclideanSpace, InnerSpace, MetricSpace, Rotation, Vector2, Zero};
+use crate::camera::Eye;
+use crate::material::Material;
+use crate::medium::Medium;
+use crate::renderable::{HitDetail, Renderable, RenderShape};
 use crate::scene::Scene;
-use crate::transform::{Point, Vector};
+use crate::transform::{Point, Transform, Vector};
 
 const MIN_OBJECTS_PER_BOX: usize = 2;
+const MEDIUM_EPSILON: f64 = 1e-6;
+const MAX_MEDIUM_CROSSINGS: usize = 16;
 
 #[derive(Debug, Clone)]
 pub struct AccelerationStructure {
-    bounding_boxes: Vec<BoundingBox>,
+    objects: Bvh,
+    // One hierarchy per prototype, in the prototype's local space, shared by all of its instances.
+    prototypes: Vec<Bvh>,
     scene: Arc<Scene>,
 }
 
+// Hierarchy of boxes around indexed items, used for scene objects, prototypes, and curves.
+#[derive(Debug, Clone)]
+pub struct Bvh {
+    bounding_boxes: Vec<BoundingBox>,
+    // Center and half size of each item's box, by item index.
+    aabbs: Vec<(Point, Vector)>,
+    // Items without a box, such as infinite planes, which every ray is tested against.
+    unbounded: Vec<usize>,
+}
+
 #[derive(Debug, Clone)]
 struct BoundingBox {
     center: Point,
@@ -31,13 +48,275 @@ enum BoxAxis {
 
 impl AccelerationStructure {
     pub fn new(scene: Arc<Scene>) -> Self {
-        let initial_box = BoundingBox::new((0..scene.get_object_count()).collect(), scene.clone());
         Self {
-            bounding_boxes: vec![initial_box],
+            objects: Bvh::new(Vec::new()),
+            prototypes: Vec::new(),
             scene,
         }
     }
     
+    pub fn generate(&mut self) {
+        let aabbs = (0..self.scene.get_object_count()).map(|i| get_object_aabb(self.scene.get_object(i))).collect();
+        self.objects = Bvh::new(aabbs);
+        self.objects.generate();
+        
+        self.prototypes = (0..self.scene.get_prototype_count()).map(|i| {
+            let mut bvh = Bvh::new(self.scene.get_prototype(i).objects.iter().map(get_object_aabb).collect());
+            bvh.generate();
+            bvh
+        }).collect();
+    }
+    
+    pub fn trace_pixel(&self, x_coord: f64, y_coord: f64, aspect: f64, eye: Eye, num_bounces: usize) -> Vector {
+        //return self.scene.trace_pixel(x_coord, y_coord, aspect, eye, num_bounces); // For testing performance improvement.
+        
+        let time = self.scene.camera.sample_time();
+        let (mut ray_orig, mut ray_dir) = match self.scene.camera.get_ray(Vector2::new(x_coord, y_coord), aspect, eye, time) {
+            Some(ray) => ray,
+            None => return Vector::zero(),
+        };
+
+        let mut diffuse = Vector::new(1.0, 1.0, 1.0);
+        let mut lighting = Vector::new(0.0, 0.0, 0.0);
+        
+        // Objects whose interior medium the ray is currently travelling through, innermost last.
+        let mut media = self.scene.get_media_at(ray_orig, time);
+        let mut scattered = false;
+        
+        let mut bounce = 0;
+        while bounce < num_bounces {
+            let hit = self.trace_structure(ray_orig, ray_dir, &media, time);
+            let (medium, segment_dist, exit_point) = self.next_segment(ray_orig, ray_dir, &media, &hit, time);
+            
+            if let Some((medium, transform)) = medium {
+                let (scatter_dist, weight, emitted) = medium.sample_distance(ray_orig, ray_dir, segment_dist, &transform);
+                lighting.add_assign_element_wise(emitted.mul_element_wise(diffuse));
+                diffuse.mul_assign_element_wise(weight);
+                if let Some(scatter_dist) = scatter_dist {
+                    ray_orig += ray_dir * scatter_dist;
+                    
+                    let (sun_dir, sun_light) = self.scene.sky.sample_sun();
+                    let sun_transmittance = self.transmittance(ray_orig, sun_dir, &media, time);
+                    lighting.add_assign_element_wise(
+                        sun_light.mul_element_wise(sun_transmittance).mul_element_wise(diffuse) * medium.phase(ray_dir, sun_dir)
+                    );
+                    
+                    ray_dir = medium.sample_phase(ray_dir);
+                    scattered = true;
+                    bounce += 1;
+                    continue;
+                }
+            }
+            
+            if let Some(exit_point) = exit_point {
+                media.pop();
+                ray_orig = exit_point + ray_dir * MEDIUM_EPSILON;
+                continue;
+            }
+            
+            if let Some((hit_point, normal, detail, object_ind, hit_object)) = hit {
+                let object = self.scene.get_object(object_ind);
+                if object.medium.is_some() {
+                    if ray_dir.dot(normal) < 0.0 {
+                        media.push(object_ind);
+                    }
+                    ray_orig = hit_point + ray_dir * MEDIUM_EPSILON;
+                    continue;
+                }
+                
+                //return normal;
+                let mut material = *hit_object.get_material();
+                // Vertex colors and textures tint the material's diffuse color where the shape has them.
+                if material.vertex_colors || hit_object.texture.is_some() {
+                    if let Some(color) = self.get_color(object, hit_object, hit_point, detail, time) {
+                        material.diffuse.mul_assign_element_wise(color);
+                    }
+                }
+                let material = &material;
+                let (hit_diffuse, hit_emissive) = match &material.hair {
+                    Some(hair) => {
+                        let tangent = self.get_tangent(object, hit_object, detail, time);
+                        hair.hit_surface(material, &mut ray_orig, &mut ray_dir, hit_point, normal, tangent)
+                    }
+                    None => material.hit_surface(&mut ray_orig, &mut ray_dir, hit_point, normal),
+                };
+                diffuse.mul_assign_element_wise(hit_diffuse);
+                lighting.add_assign_element_wise(hit_emissive.mul_element_wise(diffuse));
+                if material.emissive >= 1.0 {
+                    break;
+                }
+                scattered = false;
+                bounce += 1;
+            } else {
+                let sky_color = if scattered {
+                    self.scene.sky.get_background_color(ray_dir)
+                } else {
+                    self.scene.sky.get_sky_color(ray_dir)
+                };
+                lighting.add_assign_element_wise(sky_color.mul_element_wise(diffuse));
+                break;
+            }
+        }
+
+        return lighting * self.scene.camera.get_exposure();
+    }
+    
+    // Finds the medium the ray is currently in, how far it travels through it, and where it exits the enclosing object, if at all.
+    fn next_segment(&self, ray_orig: Point, ray_dir: Vector, media: &[usize], hit: &Option<(Point, Vector, HitDetail, usize, &Renderable)>, time: f64) -> (Option<(&Medium, Transform)>, f64, Option<Point>) {
+        let mut segment_dist = hit.map_or(f64::INFINITY, |(hit_point, ..)| hit_point.distance(ray_orig));
+        
+        if let Some(object_ind) = media.last() {
+            let object = self.scene.get_object(*object_ind);
+            let transform = object.get_transform(time);
+            if let Some((exit_point, _)) = object.trace_exit(ray_orig, ray_dir, time) {
+                let exit_dist = exit_point.distance(ray_orig);
+                if exit_dist < segment_dist {
+                    return (object.medium.as_ref().map(|m| (m, transform)), exit_dist, Some(exit_point));
+                }
+            }
+            (object.medium.as_ref().map(|m| (m, transform)), segment_dist, None)
+        } else {
+            if hit.is_none() {
+                segment_dist = self.scene.sky.atmosphere_extent;
+            }
+            (self.scene.sky.atmosphere.as_ref().map(|m| (m, Transform::default())), segment_dist, None)
+        }
+    }
+    
+    // Fraction of light that reaches ray_orig from along ray_dir, passing through media but blocked by solid objects.
+    fn transmittance(&self, mut ray_orig: Point, ray_dir: Vector, media: &[usize], time: f64) -> Vector {
+        let mut media = media.to_vec();
+        let mut transmittance = Vector::new(1.0, 1.0, 1.0);
+        
+        for _i in 0..MAX_MEDIUM_CROSSINGS {
+            let hit = self.trace_structure(ray_orig, ray_dir, &media, time);
+            let (medium, segment_dist, exit_point) = self.next_segment(ray_orig, ray_dir, &media, &hit, time);
+            
+            if let Some((medium, transform)) = medium {
+                transmittance.mul_assign_element_wise(medium.transmittance(ray_orig, ray_dir, segment_dist, &transform));
+            }
+            
+            if let Some(exit_point) = exit_point {
+                media.pop();
+                ray_orig = exit_point + ray_dir * MEDIUM_EPSILON;
+                continue;
+            }
+            
+            match hit {
+                Some((hit_point, normal, _, object_ind, _)) => {
+                    if self.scene.get_object(object_ind).medium.is_none() {
+                        return Vector::zero();
+                    }
+                    if ray_dir.dot(normal) < 0.0 {
+                        media.push(object_ind);
+                    }
+                    ray_orig = hit_point + ray_dir * MEDIUM_EPSILON;
+                }
+                None => return transmittance,
+            }
+        }
+        
+        transmittance
+    }
+    
+    // Distance from the camera to the object's surface, aimed at the center of its box.
+    // Rays that miss the surface give the distance to that center instead, as an error.
+    pub fn get_target_distance(&self, object_ind: usize) -> Result<f64, f64> {
+        let time = self.scene.camera.shutter_open;
+        let ray_orig = self.scene.camera.get_transform(time).get_position();
+        let object = self.scene.get_object(object_ind);
+        let center = object.get_aabb().0;
+        match object.trace(ray_orig, (center - ray_orig).normalize(), time) {
+            Some((hit_point, ..)) => Ok(hit_point.distance(ray_orig)),
+            None => Err(center.distance(ray_orig)),
+        }
+    }
+    
+    // Distance from the camera to the surface at the center of the image, if there is one.
+    pub fn get_focus_distance(&self) -> Option<f64> {
+        let time = self.scene.camera.shutter_open;
+        let camera_transform = self.scene.camera.get_transform(time);
+        let ray_orig = camera_transform.get_position();
+        
+        // Medium boundaries, such as fog volumes, are seen through rather than focused on.
+        let ray_dir = camera_transform.get_basis().rotate_vector(Vector::unit_z());
+        let mut ignore = Vec::new();
+        loop {
+            let (hit_point, _, _, ind, hit_object) = self.trace_structure(ray_orig, ray_dir, &ignore, time)?;
+            if hit_object.medium.is_none() {
+                return Some(hit_point.distance(ray_orig));
+            }
+            ignore.push(ind);
+        }
+    }
+    
+    // Returns hit position, hit normal, the shape's details of the hit, the index of the object hit,
+    // and the renderable hit, which is part of a prototype for instances. Objects in the ignore list are skipped.
+    pub fn trace_structure(&self, ray_orig: Point, ray_dir: Vector, ignore: &[usize], time: f64) -> Option<(Point, Vector, HitDetail, usize, &Renderable)> {
+        self.objects.trace(ray_orig, ray_dir, |ind| {
+            if ignore.contains(&ind) {
+                return None;
+            }
+            let object = self.scene.get_object(ind);
+            let (hit_point, hit_normal, detail, hit_object) = match object.shape {
+                RenderShape::Instance(prototype, _, _) => self.trace_instance(object, prototype, ray_orig, ray_dir, time)?,
+                _ => {
+                    let (hit_point, hit_normal, detail) = object.trace(ray_orig, ray_dir, time)?;
+                    (hit_point, hit_normal, detail, object)
+                }
+            };
+            Some((hit_point.distance(ray_orig), (hit_point, hit_normal, detail, ind, hit_object)))
+        })
+    }
+    
+    // Tangent of the surface hit, turned out of the prototype's local space for instances.
+    fn get_tangent(&self, object: &Renderable, hit_object: &Renderable, detail: HitDetail, time: f64) -> Option<Vector> {
+        let tangent = hit_object.get_tangent(detail, time)?;
+        if let RenderShape::Instance(..) = object.shape {
+            return Some(object.get_transform(time).to_global_vector(tangent));
+        }
+        Some(tangent)
+    }
+    
+    // Color of the surface hit, found in the prototype's local space for instances.
+    fn get_color(&self, object: &Renderable, hit_object: &Renderable, hit_point: Point, detail: HitDetail, time: f64) -> Option<Vector> {
+        if let RenderShape::Instance(..) = object.shape {
+            return hit_object.get_color(object.get_transform(time).to_local_point(hit_point), detail, time);
+        }
+        hit_object.get_color(hit_point, detail, time)
+    }
+    
+    // Traces the ray through the prototype's own hierarchy in the instance's local space.
+    // Distances stay local until the closest hit is found, since the hierarchy's boxes are local too.
+    fn trace_instance(&self, object: &Renderable, prototype: usize, ray_orig: Point, ray_dir: Vector, time: f64) -> Option<(Point, Vector, HitDetail, &Renderable)> {
+        let transform = object.get_transform(time);
+        let local_orig = transform.to_local_point(ray_orig);
+        let local_dir = transform.to_local_vector(ray_dir);
+        let parts = &self.scene.get_prototype(prototype).objects;
+        
+        let (hit_point, hit_normal, detail, part) = self.prototypes[prototype].trace(local_orig, local_dir, |ind| {
+            let (hit_point, hit_normal, detail) = parts[ind].trace(local_orig, local_dir, time)?;
+            Some((hit_point.distance(local_orig), (hit_point, hit_normal, detail, &parts[ind])))
+        })?;
+        Some((transform.to_global_point(hit_point), transform.to_global_normal(hit_normal), detail, part))
+    }
+}
+
+impl Bvh {
+    // Items with no box are left out of the hierarchy.
+    pub fn new(aabbs: Vec<Option<(Point, Vector)>>) -> Self {
+        let (bounded, unbounded) = (0..aabbs.len()).partition(|i| aabbs[*i].is_some());
+        let aabbs = aabbs.into_iter()
+            .map(|aabb| aabb.unwrap_or((Point::new(0.0, 0.0, 0.0), Vector::zero())))
+            .collect::<Vec<_>>();
+        let initial_box = BoundingBox::new(bounded, &aabbs);
+        Self {
+            bounding_boxes: vec![initial_box],
+            aabbs,
+            unbounded,
+        }
+    }
+    
     pub fn generate(&mut self) {
         let mut ind = 0;
         while ind < self.bounding_boxes.len() {
@@ -58,13 +337,13 @@ impl AccelerationStructure {
                 
                 let mut average_pos = Point::new(0.0, 0.0, 0.0);
                 for r in &self.bounding_boxes[ind].renderables {
-                    average_pos.add_assign_element_wise(self.scene.get_object(*r).get_aabb().0);
+                    average_pos.add_assign_element_wise(self.aabbs[*r].0);
                 }
                 average_pos = average_pos / self.bounding_boxes[ind].renderables.len() as f64;
                 
                 while !self.bounding_boxes[ind].renderables.is_empty() {
                     let renderable = self.bounding_boxes[ind].renderables.pop().unwrap();
-                    if axis.is_left(average_pos, self.scene.get_object(renderable).get_aabb().0) {
+                    if axis.is_left(average_pos, self.aabbs[renderable].0) {
                         left_objects.push(renderable);
                     } else {
                         right_objects.push(renderable);
@@ -74,7 +353,7 @@ impl AccelerationStructure {
                 let mut extra = 0;
                 if !left_objects.len() > 0 {
                     self.bounding_boxes[ind].left = box_len;
-                    let left_box = BoundingBox::new(left_objects, self.scene.clone());
+                    let left_box = BoundingBox::new(left_objects, &self.aabbs);
                     self.bounding_boxes.push(left_box);
                     extra = 1;
                 } else if left_objects.len() == 1 {
@@ -82,7 +361,7 @@ impl AccelerationStructure {
                 }
                 if !right_objects.len() > 0 {
                     self.bounding_boxes[ind].right = box_len + extra;
-                    let right_box = BoundingBox::new(right_objects, self.scene.clone());
+                    let right_box = BoundingBox::new(right_objects, &self.aabbs);
                     self.bounding_boxes.push(right_box);
                 } else if right_objects.len() == 1 {
                     self.bounding_boxes[ind].renderables.push(right_objects[0]);
@@ -92,38 +371,22 @@ impl AccelerationStructure {
         }
     }
     
-    pub fn trace_pixel(&self, x_coord: f64, y_coord: f64, num_bounces: usize) -> Vector {
-        //return self.scene.trace_pixel(x_coord, y_coord, num_bounces); // For testing performance improvement.
-        
-        let (mut ray_orig, mut ray_dir) = self.scene.camera.get_ray(Vector2::new(x_coord, y_coord));
-
-        let mut diffuse = Vector::new(1.0, 1.0, 1.0);
-        let mut lighting = Vector::new(0.0, 0.0, 0.0);
-
-        for _i in 0..num_bounces {
-            if let Some((hit_point, normal, material)) = self.trace_structure(ray_orig, ray_dir) {
-                //return normal;
-                let (hit_diffuse, hit_emissive) = material.hit_surface(&mut ray_orig, &mut ray_dir, hit_point, normal);
-                diffuse.mul_assign_element_wise(hit_diffuse);
-                lighting.add_assign_element_wise(hit_emissive.mul_element_wise(diffuse));
-                if material.emissive >= 1.0 {
-                    break;
-                }
-            } else {
-                lighting.add_assign_element_wise(self.scene.sky.get_sky_color(ray_dir).mul_element_wise(diffuse));
-                break;
-            }
-        }
-
-        return lighting;
-    }
-    
-    pub fn trace_structure(&self, ray_orig: Point, ray_dir: Vector) -> Option<(Point, Vector, &PhysicalMaterial)> {
+    // Finds the closest hit of the items whose boxes the ray passes through. trace_item returns the hit distance and the hit.
+    pub fn trace<T>(&self, ray_orig: Point, ray_dir: Vector, mut trace_item: impl FnMut(usize) -> Option<(f64, T)>) -> Option<T> {
         let mut trace_queue = vec![0];
         
         let mut res = None;
         let mut closest = 0.0;
         
+        for item in &self.unbounded {
+            if let Some((dist, hit)) = trace_item(*item) {
+                if dist < closest || res.is_none() {
+                    res = Some(hit);
+                    closest = dist;
+                }
+            }
+        }
+        
         while !trace_queue.is_empty() {
             let box_ind = trace_queue.pop().unwrap();
             let bounding_box = &self.bounding_boxes[box_ind];
@@ -137,11 +400,9 @@ impl AccelerationStructure {
                     trace_queue.push(bounding_box.right);
                 }
                 for renderable in &bounding_box.renderables {
-                    let object = self.scene.get_object(*renderable);
-                    if let Some((hit_point, hit_normal)) = object.trace(ray_orig, ray_dir) {
-                        let dist = hit_point.distance(ray_orig);
+                    if let Some((dist, hit)) = trace_item(*renderable) {
                         if dist < closest || res.is_none() {
-                            res = Some((hit_point, hit_normal, object.get_material()));
+                            res = Some(hit);
                             closest = dist;
                         }
                     }
@@ -153,8 +414,12 @@ impl AccelerationStructure {
     }
 }
 
+fn get_object_aabb(object: &Renderable) -> Option<(Point, Vector)> {
+    object.is_bounded().then(|| object.get_aabb())
+}
+
 impl BoundingBox {
-    fn new(renderables: Vec<usize>, scene: Arc<Scene>) -> Self {
+    fn new(renderables: Vec<usize>, aabbs: &[(Point, Vector)]) -> Self {
         let mut min = Point::new(f64::MAX, f64::MAX, f64::MAX);
         let mut max = Point::new(f64::MIN, f64::MIN, f64::MIN);
         
@@ -162,7 +427,7 @@ impl BoundingBox {
         let mut pos_max = max;
         
         for i in &renderables {
-            let (center, size) = scene.get_object(*i).get_aabb();
+            let (center, size) = aabbs[*i];
             pos_min.x = pos_min.x.min(center.x);
             pos_min.y = pos_min.y.min(center.y);
             pos_min.z = pos_min.z.min(center.z);
@@ -212,7 +477,8 @@ impl BoundingBox {
         let t_min = v_min.x.max(v_min.y.max(v_min.z));
         let t_max = v_max.x.min(v_max.y.min(v_max.z));
 
-        (t_max > t_min && t_min > 0.0, t_min)
+        // Flat shapes such as quads have boxes with no thickness, which rays still pass through.
+        (t_max >= t_min && t_min > 0.0, t_min)
     }
 }
 
@@ -225,3 +491,71 @@ impl BoxAxis {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, One};
+    use crate::camera::Camera;
+    use crate::material::PhysicalMaterial;
+    use crate::scene::{Prototype, Sky};
+    use crate::transform::{axis_angle_rotation, Quat};
+    use super::*;
+
+    fn part(center: Point, bounds: Vector) -> Renderable {
+        let transform = Transform::new(center, Quat::one(), Vector::new(1.0, 1.0, 1.0));
+        Renderable::new_box(transform, PhysicalMaterial::default(), bounds)
+    }
+
+    #[test]
+    fn scaled_instance_finds_the_closest_part() {
+        // Three parts split into two boxes along x, with the nearest two overlapping, so the far box is traced first.
+        let mut prototype = Prototype::new("parts".to_string());
+        prototype.objects.push(part(Point::new(-20.0, 0.0, 0.0), Vector::new(5.0, 1.0, 1.0)));
+        prototype.objects.push(part(Point::new(0.0, 0.0, 0.0), Vector::new(20.0, 1.0, 1.0)));
+        prototype.objects.push(part(Point::new(25.0, 0.0, 0.0), Vector::new(1.0, 1.0, 1.0)));
+        let (min, max) = prototype.get_bounds();
+
+        let mut scene = Scene::new(Camera::default(), Sky::black());
+        let ind = scene.add_prototype(prototype);
+        let transform = Transform::new(Point::new(1.0, 0.0, 0.0), Quat::one(), Vector::new(0.1, 2.0, 1.0));
+        scene.add_object(Renderable::new(transform, PhysicalMaterial::default(), RenderShape::Instance(ind, min, max)));
+
+        let mut structure = AccelerationStructure::new(Arc::new(scene));
+        structure.generate();
+        let (hit_point, normal, _, object_ind, _) = structure.trace_structure(Point::new(-10.0, 0.0, 0.0), Vector::unit_x(), &[], 0.0).unwrap();
+        assert_eq!(object_ind, 0);
+        assert!((hit_point - Point::new(-1.5, 0.0, 0.0)).magnitude() < 1e-9, "hit {hit_point:?}");
+        assert!((normal + Vector::unit_x()).magnitude() < 1e-9);
+
+        // From the other side, the far part is the closest.
+        let (hit_point, ..) = structure.trace_structure(Point::new(10.0, 0.0, 0.0), -Vector::unit_x(), &[], 0.0).unwrap();
+        assert!((hit_point - Point::new(3.6, 0.0, 0.0)).magnitude() < 1e-9, "hit {hit_point:?}");
+        assert!(structure.trace_structure(Point::new(-10.0, 3.0, 0.0), Vector::unit_x(), &[], 0.0).is_none());
+    }
+
+    #[test]
+    fn rotated_instance_turns_hits_and_bounds() {
+        let mut prototype = Prototype::new("bar".to_string());
+        prototype.objects.push(part(Point::new(0.0, 0.0, 0.0), Vector::new(2.0, 1.0, 1.0)));
+        let (min, max) = prototype.get_bounds();
+
+        // A quarter turn about y swings the bar's length from x to z.
+        let mut scene = Scene::new(Camera::default(), Sky::black());
+        let ind = scene.add_prototype(prototype);
+        let transform = Transform::new(Point::new(0.0, 0.0, 5.0), axis_angle_rotation(Vector::unit_y(), Deg(90.0)), Vector::new(1.0, 1.0, 1.0));
+        let instance = Renderable::new(transform, PhysicalMaterial::default(), RenderShape::Instance(ind, min, max));
+        let (center, half_size) = instance.get_aabb();
+        assert!((center - Point::new(0.0, 0.0, 5.0)).magnitude() < 1e-9);
+        assert!((half_size - Vector::new(1.0, 1.0, 2.0)).magnitude() < 1e-9, "half size {half_size:?}");
+        scene.add_object(instance);
+
+        let mut structure = AccelerationStructure::new(Arc::new(scene));
+        structure.generate();
+        let (hit_point, normal, ..) = structure.trace_structure(Point::new(-10.0, 0.0, 5.0), Vector::unit_x(), &[], 0.0).unwrap();
+        assert!((hit_point - Point::new(-1.0, 0.0, 5.0)).magnitude() < 1e-9, "hit {hit_point:?}");
+        assert!((normal + Vector::unit_x()).magnitude() < 1e-9);
+        let (hit_point, normal, ..) = structure.trace_structure(Point::new(0.0, 0.0, -10.0), Vector::unit_z(), &[], 0.0).unwrap();
+        assert!((hit_point - Point::new(0.0, 0.0, 3.0)).magnitude() < 1e-9, "hit {hit_point:?}");
+        assert!((normal + Vector::unit_z()).magnitude() < 1e-9);
+    }
+}
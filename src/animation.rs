@@ -0,0 +1,207 @@
+use cgmath::InnerSpace;
+use crate::scene::Scene;
+use crate::transform::{Point, Quat, Transform, Vector};
+
+// Transforms are stored as position, a rotation quaternion, then scale.
+const ROTATION_VALUES: std::ops::Range<usize> = 3..7;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Interpolation {
+    Linear,
+    // Smooth cubic curve through the keyframes, easing in and out at the first and last keys.
+    Bezier,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MaterialProperty {
+    Diffuse,
+    Roughness,
+    Metallic,
+    Emissive,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnimationTarget {
+    CameraTransform,
+    CameraFocalPlane,
+    SunDir,
+    Material(String, MaterialProperty),
+    ObjectTransform(String),
+}
+
+// A keyframed value. Transforms, vectors, and floats are all stored as their components.
+#[derive(Debug, Clone)]
+pub struct Keyframe {
+    pub frame: f64,
+    pub values: Vec<f64>,
+    pub interpolation: Interpolation,
+}
+
+#[derive(Debug, Clone)]
+pub struct Track {
+    pub target: AnimationTarget,
+    keys: Vec<Keyframe>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Animation {
+    pub start_frame: usize,
+    pub end_frame: usize,
+    tracks: Vec<Track>,
+}
+
+impl Animation {
+    pub fn new() -> Self {
+        Self {
+            start_frame: 0,
+            end_frame: 0,
+            tracks: Vec::new(),
+        }
+    }
+
+    pub fn add_key(&mut self, target: AnimationTarget, key: Keyframe) {
+        let track = match self.tracks.iter().position(|track| track.target == target) {
+            Some(ind) => &mut self.tracks[ind],
+            None => {
+                self.tracks.push(Track { target, keys: Vec::new() });
+                self.tracks.last_mut().unwrap()
+            }
+        };
+        let ind = track.keys.partition_point(|k| k.frame <= key.frame);
+        track.keys.insert(ind, key);
+        
+        // q and -q are the same rotation, so neighbouring keys are flipped to agree and curves take the short way round.
+        if track.target.is_transform() {
+            for i in 1..track.keys.len() {
+                if to_quat(&track.keys[i - 1].values).dot(to_quat(&track.keys[i].values)) < 0.0 {
+                    for value in &mut track.keys[i].values[ROTATION_VALUES] {
+                        *value = -*value;
+                    }
+                }
+            }
+        }
+    }
+
+    // Sets every animated property of the scene to its value at the given frame.
+    pub fn apply(&self, scene: &mut Scene, frame: f64) {
+        for track in &self.tracks {
+            let values = track.evaluate(frame);
+            match &track.target {
+                AnimationTarget::CameraTransform => scene.camera.transform = to_transform(&values),
+                AnimationTarget::CameraFocalPlane => scene.camera.focal_plane = values[0],
+                AnimationTarget::SunDir => scene.sky.set_sun_dir(to_vector(&values)),
+                AnimationTarget::Material(name, property) => {
+                    for i in 0..scene.get_object_count() {
+                        let object = scene.get_object_mut(i);
+                        if object.material_name.as_deref() != Some(name.as_str()) {
+                            continue;
+                        }
+                        match property {
+                            MaterialProperty::Diffuse => object.material.diffuse = to_vector(&values),
+                            MaterialProperty::Roughness => object.material.roughness = values[0],
+                            MaterialProperty::Metallic => object.material.metallic = values[0],
+                            MaterialProperty::Emissive => object.material.emissive = values[0],
+                        }
+                    }
+                }
+                AnimationTarget::ObjectTransform(name) => {
+                    if let Some(ind) = scene.find_object(name) {
+                        let object = scene.get_object_mut(ind);
+                        object.transform = match &object.parent {
+                            Some(parent) => to_transform(&values).with_parent(parent),
+                            None => to_transform(&values),
+                        };
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Default for Animation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AnimationTarget {
+    fn is_transform(&self) -> bool {
+        matches!(self, AnimationTarget::CameraTransform | AnimationTarget::ObjectTransform(_))
+    }
+}
+
+impl Track {
+    fn evaluate(&self, frame: f64) -> Vec<f64> {
+        let keys = &self.keys;
+        if frame <= keys[0].frame {
+            return keys[0].values.clone();
+        }
+        if frame >= keys[keys.len() - 1].frame {
+            return keys[keys.len() - 1].values.clone();
+        }
+
+        let next = keys.partition_point(|k| k.frame <= frame);
+        let prev = next - 1;
+        let span = keys[next].frame - keys[prev].frame;
+        let t = (frame - keys[prev].frame) / span;
+
+        let mut values: Vec<f64> = match keys[prev].interpolation {
+            Interpolation::Linear => keys[prev].values.iter().zip(&keys[next].values)
+                .map(|(a, b)| a + (b - a) * t)
+                .collect(),
+            Interpolation::Bezier => {
+                // Cubic Hermite segment, with tangents from the neighbouring keys, and flat at the ends.
+                let tangent = |ind: usize, component: usize| {
+                    if ind == 0 || ind == keys.len() - 1 {
+                        0.0
+                    } else {
+                        (keys[ind + 1].values[component] - keys[ind - 1].values[component]) / (keys[ind + 1].frame - keys[ind - 1].frame)
+                    }
+                };
+                let t2 = t * t;
+                let t3 = t2 * t;
+                (0..keys[prev].values.len()).map(|c| {
+                    (2.0 * t3 - 3.0 * t2 + 1.0) * keys[prev].values[c]
+                        + (t3 - 2.0 * t2 + t) * span * tangent(prev, c)
+                        + (-2.0 * t3 + 3.0 * t2) * keys[next].values[c]
+                        + (t3 - t2) * span * tangent(next, c)
+                }).collect()
+            }
+        };
+        
+        if self.target.is_transform() {
+            // Linear keys rotate at a constant speed, and curved keys are brought back to a unit quaternion.
+            let rotation = match keys[prev].interpolation {
+                Interpolation::Linear => to_quat(&keys[prev].values).slerp(to_quat(&keys[next].values), t),
+                Interpolation::Bezier => to_quat(&values).normalize(),
+            };
+            values.splice(ROTATION_VALUES, [rotation.s, rotation.v.x, rotation.v.y, rotation.v.z]);
+        }
+        values
+    }
+}
+
+pub fn from_transform(transform: &Transform) -> Vec<f64> {
+    let (position, rotation, scale) = (transform.get_position(), transform.get_rotation(), transform.get_scale());
+    vec![
+        position.x, position.y, position.z,
+        rotation.s, rotation.v.x, rotation.v.y, rotation.v.z,
+        scale.x, scale.y, scale.z,
+    ]
+}
+
+fn to_transform(values: &[f64]) -> Transform {
+    Transform::new(
+        Point::new(values[0], values[1], values[2]),
+        to_quat(values),
+        Vector::new(values[7], values[8], values[9]),
+    )
+}
+
+fn to_quat(values: &[f64]) -> Quat {
+    Quat::new(values[3], values[4], values[5], values[6])
+}
+
+fn to_vector(values: &[f64]) -> Vector {
+    Vector::new(values[0], values[1], values[2])
+}
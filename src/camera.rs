@@ -1,41 +1,315 @@
-use std::f64::consts::TAU;
+use std::f64::consts::{FRAC_PI_2, PI, TAU};
+use std::path::Path;
+use std::sync::Arc;
 use crate::transform::*;
 use cgmath::{InnerSpace, Rotation, Vector2};
+use image::ImageResult;
+use image::error::{ImageError, ParameterError, ParameterErrorKind};
 use rand::prelude::*;
 
-#[derive(Debug, Copy, Clone)]
+// The sunny 16 rule's exposure for bright daylight, which renders at its unscaled brightness.
+const REFERENCE_SHUTTER_SPEED: f64 = 1.0 / 100.0;
+const REFERENCE_F_NUMBER: f64 = 16.0;
+const REFERENCE_ISO: f64 = 100.0;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Projection {
+    Perspective,
+    Orthographic,
+    // Full 360x180 degree latitude-longitude panorama.
+    Equirectangular,
+    // Six cube faces in a 3x2 grid: +X -X +Y on top, -Y +Z -Z below.
+    CubeMap,
+    FisheyeEquidistant,
+    FisheyeEquisolid,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Eye {
+    Center,
+    Left,
+    Right,
+}
+
+// Artist-friendly ways of specifying the perspective field of view.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FieldOfView {
+    // Degrees across the image height.
+    Vertical(f64),
+    // Degrees across the image width.
+    Horizontal(f64),
+    // Lens focal length in millimeters, used with the sensor size.
+    FocalLength(f64),
+}
+
+// A grayscale image describing the transmission of the aperture, for custom bokeh shapes.
+#[derive(Debug, Clone)]
+pub struct ApertureImage {
+    width: usize,
+    height: usize,
+    // Running total of the pixel brightnesses, row by row, scaled to end at one.
+    cdf: Vec<f64>,
+}
+
+#[derive(Debug, Clone)]
 pub struct Camera {
     pub transform: Transform,
+    pub projection: Projection,
+    // Distance to the image plane, where the image is 2 units tall. Overridden by field_of_view.
     pub focal_length: f64,
+    pub field_of_view: Option<FieldOfView>,
+    // Sensor width and height in millimeters.
+    pub sensor_size: Vector2<f64>,
     pub focal_plane: f64,
+    // Aperture radius multiplier. Overridden by f_number.
     pub f_stop: f64,
+    // Real f-number, giving an aperture diameter of the focal length divided by the f-number, in meters.
+    pub f_number: Option<f64>,
+    // Number of aperture blades, giving polygonal bokeh. Zero for a circular aperture.
+    pub aperture_blades: usize,
+    // Rotation of the aperture blades in degrees.
+    pub aperture_rotation: f64,
+    pub aperture_image: Option<Arc<ApertureImage>>,
+    // How strongly the aperture is clipped towards the edges of the image, giving cat's eye bokeh.
+    pub cat_eye: f64,
+    // Horizontal squeeze of anamorphic lenses, stretching bokeh vertically.
+    pub anamorphic_squeeze: f64,
+    // Shutter speed in seconds. Exposure is relative to 1/100 second at f/16 and ISO 100, and is only applied when this or iso is given.
+    pub shutter_speed: Option<f64>,
+    pub iso: Option<f64>,
+    // World-space width of the view for orthographic projection.
+    pub ortho_width: f64,
+    // Full field of view of the fisheye projections in degrees, across the image height.
+    pub fisheye_fov: f64,
+    pub interocular_distance: f64,
+    // Distance at which both eyes' views meet. Infinite for parallel eyes.
+    pub convergence_distance: f64,
+    // Offsets each panoramic ray perpendicular to its heading, rather than offsetting the whole camera.
+    pub omnidirectional_stereo: bool,
+    // Further transforms the camera moves through over times 0 to 1, for motion blur.
+    pub motion: Vec<Transform>,
+    // Part of the 0 to 1 time range the shutter is open for.
+    pub shutter_open: f64,
+    pub shutter_close: f64,
+    // Flips the image horizontally, for camera transforms that include a mirroring scale.
+    pub mirror: bool,
 }
 
 impl Camera {
-    pub fn get_ray(&self, coord: Vector2<f64>) -> (Point, Vector) {
-        let mut rng = thread_rng();
-        
-        let rand_angle = rng.gen::<f64>() * TAU;
-        let rand_off = rng.gen::<f64>();
-        
-        let rand_x = rand_angle.cos() * rand_off;
-        let rand_y = rand_angle.sin() * rand_off;
+    // coord ranges over [-aspect, aspect] horizontally and [-1, 1] vertically.
+    // Returns None for coordinates outside the projection, such as the corners of a fisheye image.
+    pub fn get_ray(&self, mut coord: Vector2<f64>, aspect: f64, eye: Eye, time: f64) -> Option<(Point, Vector)> {
+        if self.mirror {
+            coord.x = -coord.x;
+        }
+        let transform = self.get_transform(time);
+        let basis = transform.get_basis();
         
-        let basis = self.transform.get_basis();
+        let eye_offset = match eye {
+            Eye::Center => 0.0,
+            Eye::Left => -self.interocular_distance / 2.0,
+            Eye::Right => self.interocular_distance / 2.0,
+        };
         
-        let mut ray_orig = self.transform.position;
+        let (mut ray_orig, mut ray_dir) = match self.projection {
+            Projection::Perspective => {
+                let eye_pos = Vector::new(eye_offset, 0.0, 0.0);
+                let focal_length = self.get_image_plane_distance(aspect);
+                let mut local_dir = Vector::new(coord.x, coord.y, focal_length);
+                if eye_offset != 0.0 && self.convergence_distance.is_finite() {
+                    // Off-axis projection, so both eyes see the same point on the convergence plane.
+                    local_dir = local_dir * (self.convergence_distance / focal_length) - eye_pos;
+                }
+                (
+                    transform.get_position() + basis.rotate_vector(eye_pos),
+                    basis.rotate_vector(local_dir.normalize()),
+                )
+            }
+            Projection::Orthographic => {
+                let half_size = self.ortho_width / (2.0 * aspect);
+                (
+                    transform.get_position() + basis.rotate_vector(Vector::new(coord.x, coord.y, 0.0) * half_size),
+                    basis.rotate_vector(Vector::unit_z()),
+                )
+            }
+            _ => {
+                let local_dir = self.get_panoramic_dir(coord, aspect)?;
+                let eye_dir = if self.omnidirectional_stereo {
+                    let heading = Vector::new(local_dir.z, 0.0, -local_dir.x);
+                    if heading.magnitude2() > 0.0 {
+                        heading.normalize()
+                    } else {
+                        heading
+                    }
+                } else {
+                    Vector::unit_x()
+                };
+                return Some((
+                    transform.get_position() + basis.rotate_vector(eye_dir * eye_offset),
+                    basis.rotate_vector(local_dir),
+                ));
+            }
+        };
         
-        let mut ray_dir = basis.rotate_vector(Vector::new(coord.x, coord.y, self.focal_length).normalize());
+        let aperture_sample = self.sample_aperture(coord, aspect)?;
         
         let target_point = ray_orig + ray_dir * self.focal_plane;
         
-        let offset = Vector::new(rand_x, rand_y, 0.0) * 2.0;
+        let offset = Vector::new(aperture_sample.x, aperture_sample.y, 0.0);
         
-        ray_orig += basis.rotate_vector(offset * self.f_stop);
+        ray_orig += basis.rotate_vector(offset * self.get_aperture_radius(aspect));
         
         ray_dir = (target_point - ray_orig).normalize();
         
-        (ray_orig, ray_dir)
+        Some((ray_orig, ray_dir))
+    }
+    
+    pub fn get_transform(&self, time: f64) -> Transform {
+        interpolate_motion(&self.transform, &self.motion, time)
+    }
+    
+    pub fn sample_time(&self) -> f64 {
+        self.shutter_open + thread_rng().gen::<f64>() * (self.shutter_close - self.shutter_open)
+    }
+    
+    // Picks a point on the aperture, within the unit circle. Returns None if the point was vignetted away.
+    fn sample_aperture(&self, coord: Vector2<f64>, aspect: f64) -> Option<Vector2<f64>> {
+        let mut rng = thread_rng();
+        
+        let mut sample = if let Some(aperture_image) = &self.aperture_image {
+            aperture_image.sample(&mut rng)
+        } else if self.aperture_blades >= 3 {
+            // Uniformly sample one of the triangles fanning out from the center of the polygon.
+            let blade_angle = TAU / self.aperture_blades as f64;
+            let rotation = self.aperture_rotation.to_radians();
+            let angle_a = rng.gen_range(0..self.aperture_blades) as f64 * blade_angle + rotation;
+            let angle_b = angle_a + blade_angle;
+            let corner_a = Vector2::new(angle_a.cos(), angle_a.sin());
+            let corner_b = Vector2::new(angle_b.cos(), angle_b.sin());
+            
+            let sqrt_u = rng.gen::<f64>().sqrt();
+            let v = rng.gen::<f64>();
+            corner_a * (sqrt_u * (1.0 - v)) + corner_b * (sqrt_u * v)
+        } else {
+            let rand_angle = rng.gen::<f64>() * TAU;
+            let rand_off = rng.gen::<f64>().sqrt();
+            Vector2::new(rand_angle.cos() * rand_off, rand_angle.sin() * rand_off)
+        };
+        
+        if self.cat_eye > 0.0 {
+            // Clip by a second circle that moves towards the image center as the pixel moves outwards.
+            let shift = Vector2::new(coord.x / aspect, coord.y) * self.cat_eye;
+            if (sample + shift).magnitude2() > 1.0 {
+                return None;
+            }
+        }
+        
+        sample.x /= self.anamorphic_squeeze;
+        Some(sample)
+    }
+    
+    // Distance from the pinhole to the image plane, in units where the image is 2 units tall.
+    pub fn get_image_plane_distance(&self, aspect: f64) -> f64 {
+        match self.field_of_view {
+            None => self.focal_length,
+            Some(FieldOfView::Vertical(fov)) => 1.0 / (fov.to_radians() / 2.0).tan(),
+            Some(FieldOfView::Horizontal(fov)) => aspect / (fov.to_radians() / 2.0).tan(),
+            Some(FieldOfView::FocalLength(focal_length)) => 2.0 * focal_length / self.get_sensor_height(aspect),
+        }
+    }
+    
+    // Focal length in millimeters, derived from the sensor size if the field of view was given as an angle.
+    pub fn get_focal_length_mm(&self, aspect: f64) -> f64 {
+        match self.field_of_view {
+            Some(FieldOfView::FocalLength(focal_length)) => focal_length,
+            _ => self.get_image_plane_distance(aspect) * self.get_sensor_height(aspect) / 2.0,
+        }
+    }
+    
+    pub fn get_aperture_radius(&self, aspect: f64) -> f64 {
+        match self.f_number {
+            Some(f_number) => self.get_focal_length_mm(aspect) / 1000.0 / (2.0 * f_number),
+            None => self.f_stop * 2.0,
+        }
+    }
+    
+    // Multiplier for rendered light, relative to the sunny 16 exposure of 1/100 second at f/16 and ISO 100.
+    // Only a shutter speed or ISO turns exposure on, so an f-number given for depth of field alone doesn't change brightness.
+    // Settings that aren't given take their sunny 16 values.
+    pub fn get_exposure(&self) -> f64 {
+        if self.shutter_speed.is_none() && self.iso.is_none() {
+            return 1.0;
+        }
+        let shutter_speed = self.shutter_speed.unwrap_or(REFERENCE_SHUTTER_SPEED);
+        let iso = self.iso.unwrap_or(REFERENCE_ISO);
+        let f_number = self.f_number.unwrap_or(REFERENCE_F_NUMBER);
+        let exposure = shutter_speed * iso / (f_number * f_number);
+        exposure / (REFERENCE_SHUTTER_SPEED * REFERENCE_ISO / (REFERENCE_F_NUMBER * REFERENCE_F_NUMBER))
+    }
+    
+    // The part of the sensor the image covers. The image is cropped from the sensor to match its aspect.
+    fn get_sensor_height(&self, aspect: f64) -> f64 {
+        if aspect >= self.sensor_size.x / self.sensor_size.y {
+            self.sensor_size.x / aspect
+        } else {
+            self.sensor_size.y
+        }
+    }
+    
+    // Camera-space direction for the projections that cover more than a plane can.
+    fn get_panoramic_dir(&self, coord: Vector2<f64>, aspect: f64) -> Option<Vector> {
+        match self.projection {
+            Projection::Equirectangular => {
+                let longitude = coord.x / aspect * PI;
+                let latitude = coord.y * FRAC_PI_2;
+                Some(Vector::new(
+                    latitude.cos() * longitude.sin(),
+                    latitude.sin(),
+                    latitude.cos() * longitude.cos(),
+                ))
+            }
+            Projection::CubeMap => {
+                let u = (coord.x / aspect * 0.5 + 0.5) * 3.0;
+                let column = (u.floor() as usize).min(2);
+                let row = if coord.y >= 0.0 { 0 } else { 1 };
+                
+                let face_u = (u - column as f64) * 2.0 - 1.0;
+                let face_v = if row == 0 { coord.y * 2.0 - 1.0 } else { coord.y * 2.0 + 1.0 };
+                
+                let dir = match (row, column) {
+                    (0, 0) => Vector::new(1.0, face_v, -face_u),
+                    (0, 1) => Vector::new(-1.0, face_v, face_u),
+                    (0, _) => Vector::new(face_u, 1.0, -face_v),
+                    (_, 0) => Vector::new(face_u, -1.0, face_v),
+                    (_, 1) => Vector::new(face_u, face_v, 1.0),
+                    (_, _) => Vector::new(-face_u, face_v, -1.0),
+                };
+                Some(dir.normalize())
+            }
+            Projection::FisheyeEquidistant | Projection::FisheyeEquisolid => {
+                let radius = coord.magnitude();
+                let half_fov = self.fisheye_fov.to_radians() / 2.0;
+                let theta = if self.projection == Projection::FisheyeEquidistant {
+                    radius * half_fov
+                } else {
+                    let sin_half_theta = radius * (half_fov / 2.0).sin();
+                    if sin_half_theta > 1.0 {
+                        return None;
+                    }
+                    2.0 * sin_half_theta.asin()
+                };
+                if theta > half_fov || theta > PI {
+                    return None;
+                }
+                let phi = coord.y.atan2(coord.x);
+                Some(Vector::new(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                ))
+            }
+            _ => None,
+        }
     }
 }
 
@@ -43,9 +317,93 @@ impl Default for Camera {
     fn default() -> Self {
         Self {
             transform: Transform::default(),
+            projection: Projection::Perspective,
             focal_length: 1.0,
+            field_of_view: None,
+            sensor_size: Vector2::new(36.0, 24.0),
             focal_plane: 1.0,
             f_stop: 0.0,
+            f_number: None,
+            aperture_blades: 0,
+            aperture_rotation: 0.0,
+            aperture_image: None,
+            cat_eye: 0.0,
+            anamorphic_squeeze: 1.0,
+            shutter_speed: None,
+            iso: None,
+            ortho_width: 2.0,
+            fisheye_fov: 180.0,
+            interocular_distance: 0.064,
+            convergence_distance: f64::INFINITY,
+            omnidirectional_stereo: true,
+            motion: Vec::new(),
+            shutter_open: 0.0,
+            shutter_close: 1.0,
+            mirror: false,
+        }
+    }
+}
+
+impl ApertureImage {
+    pub fn load<P: AsRef<Path>>(path: P) -> ImageResult<Self> {
+        let image = image::open(path)?.into_luma8();
+        let data = image.pixels().map(|p| p.0[0] as f64 / 255.0).collect();
+        Self::new(image.width() as usize, image.height() as usize, data)
+            .ok_or_else(|| ImageError::Parameter(ParameterError::from_kind(ParameterErrorKind::Generic("Aperture image is empty or black.".to_string()))))
+    }
+    
+    // Takes the brightness of each pixel, row by row. Returns None if there are no bright pixels.
+    pub fn new(width: usize, height: usize, data: Vec<f64>) -> Option<Self> {
+        if data.len() != width * height {
+            return None;
+        }
+        let mut cdf = data.into_iter().scan(0.0, |total, value: f64| {
+            *total += value.max(0.0);
+            Some(*total)
+        }).collect::<Vec<_>>();
+        let total = *cdf.last()?;
+        if total <= 0.0 {
+            return None;
+        }
+        cdf.iter_mut().for_each(|value| *value /= total);
+        Some(Self { width, height, cdf })
+    }
+    
+    // Picks a pixel proportional to its brightness, then a point within it, in the unit square.
+    fn sample(&self, rng: &mut ThreadRng) -> Vector2<f64> {
+        let target = rng.gen::<f64>();
+        let ind = self.cdf.partition_point(|value| *value <= target).min(self.cdf.len() - 1);
+        let u = ((ind % self.width) as f64 + rng.gen::<f64>()) / self.width as f64;
+        let v = ((ind / self.width) as f64 + rng.gen::<f64>()) / self.height as f64;
+        Vector2::new(u * 2.0 - 1.0, 1.0 - v * 2.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aperture_image_samples_only_bright_pixels() {
+        assert!(ApertureImage::new(2, 2, vec![0.0; 4]).is_none());
+        // Only the bottom right pixel lets light through.
+        let image = ApertureImage::new(2, 2, vec![0.0, 0.0, 0.0, 0.5]).unwrap();
+        let mut rng = thread_rng();
+        for _i in 0..100 {
+            let sample = image.sample(&mut rng);
+            assert!((0.0..=1.0).contains(&sample.x) && (-1.0..=0.0).contains(&sample.y), "{sample:?}");
         }
     }
+
+    #[test]
+    fn exposure() {
+        let mut camera = Camera { f_number: Some(2.0), ..Camera::default() };
+        assert_eq!(camera.get_exposure(), 1.0);
+        // Two stops faster than f/16 at the reference shutter speed and ISO.
+        camera.f_number = Some(8.0);
+        camera.iso = Some(100.0);
+        assert_eq!(camera.get_exposure(), 4.0);
+        camera.shutter_speed = Some(1.0 / 200.0);
+        assert_eq!(camera.get_exposure(), 2.0);
+    }
 }
@@ -0,0 +1,172 @@
+use std::path::{Path, PathBuf};
+use image::ColorType;
+use crate::path_tracer::*;
+use crate::scene::Scene;
+use crate::scene_loader;
+
+const USAGE: &str = "Usage: yarpt <scene.ypt|scene.gltf|scene.glb|scene.pbrt> [options]
+Options:
+    --output <file>       Image to save the render to. (render.png)
+    --width <pixels>      (480)
+    --height <pixels>     (360)
+    --samples <count>     (256)
+    --bounces <count>     (8)
+    --tile-size <pixels>  (64)
+    --threads <count>     (6)
+    --no-denoise
+    --stereo <layout>     mono, left, right, top-bottom, side-by-side, or separate. (mono)
+    --animation           Render every frame of the scene's animation.
+    --frames <start-end>  Render a range of animation frames.
+Frames are numbered in place of a run of # in the output name, e.g. frame_####.png.";
+
+// Renders a scene without opening the GUI, using the same defaults as the GUI.
+pub fn run(args: Vec<String>) {
+    let mut scene_path = None;
+    let mut output = PathBuf::from("render.png");
+    let mut render_settings = RenderSettings {
+        width: 480,
+        height: 360,
+        samples: 256,
+        bounces: 8,
+        tile_size: 64,
+        denoise: true,
+        stereo: StereoLayout::Mono,
+    };
+    let mut num_threads = 6;
+    let mut separate_eyes = false;
+    let mut animate = false;
+    let mut frame_range = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" => output = PathBuf::from(get_value(&mut args, &arg)),
+            "--width" => render_settings.width = parse_count(&mut args, &arg),
+            "--height" => render_settings.height = parse_count(&mut args, &arg),
+            "--samples" => render_settings.samples = parse_count(&mut args, &arg),
+            "--bounces" => render_settings.bounces = parse_value(&mut args, &arg),
+            "--tile-size" => render_settings.tile_size = parse_count(&mut args, &arg),
+            "--threads" => num_threads = parse_count(&mut args, &arg),
+            "--no-denoise" => render_settings.denoise = false,
+            "--stereo" => {
+                render_settings.stereo = match get_value(&mut args, &arg).as_str() {
+                    "mono" => StereoLayout::Mono,
+                    "left" => StereoLayout::LeftEye,
+                    "right" => StereoLayout::RightEye,
+                    "top-bottom" => StereoLayout::TopBottom,
+                    "side-by-side" => StereoLayout::SideBySide,
+                    "separate" => {
+                        separate_eyes = true;
+                        StereoLayout::LeftEye
+                    }
+                    layout => exit_with_usage(&format!("Unknown stereo layout: {layout}")),
+                }
+            }
+            "--animation" => animate = true,
+            "--frames" => {
+                let range = get_value(&mut args, &arg);
+                let (start, end) = range.split_once('-').unwrap_or((&range, &range));
+                match (start.parse::<usize>(), end.parse::<usize>()) {
+                    (Ok(start), Ok(end)) if start <= end => frame_range = Some((start, end)),
+                    _ => exit_with_usage(&format!("Invalid frame range: {range}")),
+                }
+            }
+            "--help" | "-h" => {
+                println!("{USAGE}");
+                return;
+            }
+            _ if arg.starts_with("--") => exit_with_usage(&format!("Unknown option: {arg}")),
+            _ => scene_path = Some(arg),
+        }
+    }
+
+    let scene_path = scene_path.unwrap_or_else(|| exit_with_usage("No scene file given."));
+    let mut warnings = Vec::new();
+    let scene = scene_loader::load(&scene_path, &mut warnings);
+    for warning in warnings {
+        eprintln!("Warning: {warning}");
+    }
+    let scene = scene.unwrap_or_else(|err| {
+        eprintln!("Error: {err}");
+        std::process::exit(1);
+    });
+
+    if animate && frame_range.is_none() {
+        frame_range = Some((scene.animation.start_frame, scene.animation.end_frame));
+    }
+    
+    match frame_range {
+        Some((start, end)) => {
+            // The scene is only loaded once, and each frame is posed from it.
+            for frame in start..=end {
+                println!("Rendering frame {frame}");
+                let frame_scene = scene.at_frame(frame as f64);
+                render_frame(&frame_scene, render_settings, num_threads, &frame_path(&output, frame), separate_eyes);
+            }
+        }
+        None => render_frame(&scene, render_settings, num_threads, &output, separate_eyes),
+    }
+}
+
+fn render_frame(scene: &Scene, render_settings: RenderSettings, num_threads: usize, output: &Path, separate_eyes: bool) {
+    if separate_eyes {
+        render_to_file(scene, RenderSettings { stereo: StereoLayout::LeftEye, ..render_settings }, num_threads, &eye_path(output, "left"));
+        render_to_file(scene, RenderSettings { stereo: StereoLayout::RightEye, ..render_settings }, num_threads, &eye_path(output, "right"));
+    } else {
+        render_to_file(scene, render_settings, num_threads, output);
+    }
+}
+
+fn render_to_file(scene: &Scene, render_settings: RenderSettings, num_threads: usize, output: &Path) {
+    let mut path_tracer = PathTracer::new(render_settings, scene.clone(), num_threads);
+    path_tracer.render(|_| {});
+
+    let data = to_rgb8(path_tracer.get_image());
+    if let Err(err) = image::save_buffer(output, data.as_slice(), render_settings.width as u32, render_settings.height as u32, ColorType::Rgb8) {
+        eprintln!("Error: Failed to save {} ({err})", output.display());
+        std::process::exit(1);
+    }
+    println!("Saved {}", output.display());
+}
+
+// render.png becomes render_left.png and render_right.png.
+fn eye_path(output: &Path, eye: &str) -> PathBuf {
+    let stem = output.file_stem().and_then(|s| s.to_str()).unwrap_or("render");
+    let extension = output.extension().and_then(|s| s.to_str()).unwrap_or("png");
+    output.with_file_name(format!("{stem}_{eye}.{extension}"))
+}
+
+// frame_####.png becomes frame_0012.png. Without any #, the frame number is appended to the name.
+fn frame_path(output: &Path, frame: usize) -> PathBuf {
+    let file_name = output.file_name().and_then(|s| s.to_str()).unwrap_or("render.png");
+    if let Some(start) = file_name.find('#') {
+        let width = file_name[start..].chars().take_while(|c| *c == '#').count();
+        let numbered = format!("{}{:0width$}{}", &file_name[..start], frame, &file_name[start + width..]);
+        return output.with_file_name(numbered);
+    }
+    let stem = output.file_stem().and_then(|s| s.to_str()).unwrap_or("render");
+    let extension = output.extension().and_then(|s| s.to_str()).unwrap_or("png");
+    output.with_file_name(format!("{stem}_{frame:04}.{extension}"))
+}
+
+fn get_value(args: &mut impl Iterator<Item = String>, option: &str) -> String {
+    args.next().unwrap_or_else(|| exit_with_usage(&format!("Missing value for {option}")))
+}
+
+fn parse_value<T: std::str::FromStr>(args: &mut impl Iterator<Item = String>, option: &str) -> T {
+    let value = get_value(args, option);
+    value.parse().unwrap_or_else(|_| exit_with_usage(&format!("Invalid value for {option}: {value}")))
+}
+
+// Sizes and counts that can't be zero.
+fn parse_count(args: &mut impl Iterator<Item = String>, option: &str) -> usize {
+    match parse_value(args, option) {
+        0 => exit_with_usage(&format!("{option} must be at least 1")),
+        count => count,
+    }
+}
+
+fn exit_with_usage(message: &str) -> ! {
+    eprintln!("{message}\n{USAGE}");
+    std::process::exit(1);
+}
@@ -0,0 +1,197 @@
+use crate::renderable::RenderShape;
+use crate::transform::{Point, Transform, Vector};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CsgOp {
+    Union,
+    Intersection,
+    // The first shape with all the others cut out of it.
+    Difference,
+}
+
+// Closed shapes combined into one solid, such as a box with a spherical cavity.
+#[derive(Debug, Clone)]
+pub enum Csg {
+    // A shape placed by its own transform within the combined solid.
+    Shape(RenderShape, Box<Transform>),
+    Combine(CsgOp, Box<Csg>, Box<Csg>),
+}
+
+// Part of a ray inside a solid, from where it enters to where it exits, with the outward normals there.
+#[derive(Debug, Copy, Clone)]
+pub struct Span {
+    pub enter: (f64, Vector),
+    pub exit: (f64, Vector),
+}
+
+impl Csg {
+    // Combines shapes in order, so a difference of a, b, and c is a with b and c cut out.
+    pub fn combine(op: CsgOp, shapes: Vec<Csg>) -> Option<Csg> {
+        shapes.into_iter().reduce(|a, b| Csg::Combine(op, Box::new(a), Box::new(b)))
+    }
+
+    // Every span of the ray inside the solid, nearest first. Distances are along ray_dir, which isn't normalized.
+    pub fn get_spans(&self, ray_orig: Point, ray_dir: Vector) -> Vec<Span> {
+        match self {
+            Csg::Shape(shape, transform) => {
+                // Both ends of the direction are transformed, so distances along the ray stay the same in the shape's space.
+                let local_orig = transform.to_local_point(ray_orig);
+                let local_dir = transform.to_local_point(ray_orig + ray_dir) - local_orig;
+
+                let mut spans = Vec::new();
+                let mut enter = None;
+                for (dist, normal) in shape.get_crossings(local_orig, local_dir) {
+                    let normal = transform.to_global_normal(normal);
+                    match enter.take() {
+                        None => enter = Some((dist, normal)),
+                        Some(start) => spans.push(Span { enter: start, exit: (dist, normal) }),
+                    }
+                }
+                spans
+            }
+            Csg::Combine(op, a, b) => combine_spans(*op, &a.get_spans(ray_orig, ray_dir), &b.get_spans(ray_orig, ray_dir)),
+        }
+    }
+
+    pub fn contains(&self, point: Point) -> bool {
+        match self {
+            Csg::Shape(shape, transform) => shape.contains(transform.to_local_point(point)),
+            Csg::Combine(op, a, b) => op.apply(a.contains(point), b.contains(point)),
+        }
+    }
+
+    // Returns the min and max corners of a box enclosing the solid.
+    pub fn get_bounds(&self) -> (Point, Point) {
+        match self {
+            Csg::Shape(shape, transform) => {
+                let mut min = Point::new(f64::MAX, f64::MAX, f64::MAX);
+                let mut max = Point::new(f64::MIN, f64::MIN, f64::MIN);
+                for p in shape.get_box_points() {
+                    let p = transform.to_global_point(p);
+                    min = min.zip(p, f64::min);
+                    max = max.zip(p, f64::max);
+                }
+                (min, max)
+            }
+            Csg::Combine(op, a, b) => {
+                let (a_min, a_max) = a.get_bounds();
+                let (b_min, b_max) = b.get_bounds();
+                match op {
+                    CsgOp::Union => (a_min.zip(b_min, f64::min), a_max.zip(b_max, f64::max)),
+                    CsgOp::Intersection => {
+                        let min = a_min.zip(b_min, f64::max);
+                        // Shapes that don't overlap leave an empty box at the first shape's corner.
+                        (min, a_max.zip(b_max, f64::min).zip(min, f64::max))
+                    }
+                    CsgOp::Difference => (a_min, a_max),
+                }
+            }
+        }
+    }
+}
+
+impl CsgOp {
+    fn apply(&self, in_a: bool, in_b: bool) -> bool {
+        match self {
+            CsgOp::Union => in_a || in_b,
+            CsgOp::Intersection => in_a && in_b,
+            CsgOp::Difference => in_a && !in_b,
+        }
+    }
+}
+
+// Walks through where the ray crosses either solid, keeping the spans where the combined solid is entered and exited.
+fn combine_spans(op: CsgOp, a: &[Span], b: &[Span]) -> Vec<Span> {
+    // Surfaces of a cut out shape face into it, so their normals are flipped.
+    let b_sign = if op == CsgOp::Difference { -1.0 } else { 1.0 };
+    let mut events: Vec<(f64, Vector, bool, bool)> = Vec::with_capacity((a.len() + b.len()) * 2);
+    for span in a {
+        events.push((span.enter.0, span.enter.1, true, true));
+        events.push((span.exit.0, span.exit.1, true, false));
+    }
+    for span in b {
+        events.push((span.enter.0, span.enter.1 * b_sign, false, true));
+        events.push((span.exit.0, span.exit.1 * b_sign, false, false));
+    }
+    events.sort_by(|x, y| x.0.total_cmp(&y.0));
+
+    let mut spans = Vec::new();
+    let (mut in_a, mut in_b) = (false, false);
+    let mut enter = None;
+    for (dist, normal, from_a, entering) in events {
+        let was_inside = op.apply(in_a, in_b);
+        if from_a {
+            in_a = entering;
+        } else {
+            in_b = entering;
+        }
+        match (was_inside, op.apply(in_a, in_b)) {
+            (false, true) => enter = Some((dist, normal)),
+            (true, false) => {
+                if let Some(start) = enter.take() {
+                    spans.push(Span { enter: start, exit: (dist, normal) });
+                }
+            }
+            _ => {}
+        }
+    }
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A span along the x axis, entered through a face pointing back along the ray.
+    fn span(enter: f64, exit: f64) -> Span {
+        Span { enter: (enter, Vector::new(-1.0, 0.0, 0.0)), exit: (exit, Vector::new(1.0, 0.0, 0.0)) }
+    }
+
+    fn distances(spans: &[Span]) -> Vec<(f64, f64)> {
+        spans.iter().map(|span| (span.enter.0, span.exit.0)).collect()
+    }
+
+    #[test]
+    fn union() {
+        assert_eq!(distances(&combine_spans(CsgOp::Union, &[span(1.0, 3.0)], &[span(2.0, 4.0)])), [(1.0, 4.0)]);
+        assert_eq!(distances(&combine_spans(CsgOp::Union, &[span(1.0, 2.0)], &[span(3.0, 4.0)])), [(1.0, 2.0), (3.0, 4.0)]);
+        // One span bridging the gap between two others.
+        let a = [span(1.0, 2.0), span(5.0, 6.0)];
+        assert_eq!(distances(&combine_spans(CsgOp::Union, &a, &[span(1.5, 5.5)])), [(1.0, 6.0)]);
+    }
+
+    #[test]
+    fn intersection() {
+        let spans = combine_spans(CsgOp::Intersection, &[span(1.0, 3.0)], &[span(2.0, 4.0)]);
+        assert_eq!(distances(&spans), [(2.0, 3.0)]);
+        assert_eq!(spans[0].enter.1, Vector::new(-1.0, 0.0, 0.0));
+        assert_eq!(spans[0].exit.1, Vector::new(1.0, 0.0, 0.0));
+        assert!(combine_spans(CsgOp::Intersection, &[span(1.0, 2.0)], &[span(3.0, 4.0)]).is_empty());
+    }
+
+    #[test]
+    fn difference() {
+        let spans = combine_spans(CsgOp::Difference, &[span(1.0, 4.0)], &[span(2.0, 3.0)]);
+        assert_eq!(distances(&spans), [(1.0, 2.0), (3.0, 4.0)]);
+        // The walls of the cut face into it.
+        assert_eq!(spans[0].exit.1, Vector::new(1.0, 0.0, 0.0));
+        assert_eq!(spans[1].enter.1, Vector::new(-1.0, 0.0, 0.0));
+
+        assert_eq!(distances(&combine_spans(CsgOp::Difference, &[span(1.0, 3.0)], &[span(2.0, 4.0)])), [(1.0, 2.0)]);
+        assert!(combine_spans(CsgOp::Difference, &[span(2.0, 3.0)], &[span(1.0, 4.0)]).is_empty());
+        assert_eq!(distances(&combine_spans(CsgOp::Difference, &[span(1.0, 2.0)], &[])), [(1.0, 2.0)]);
+    }
+
+    #[test]
+    fn capsule_seam() {
+        // A ray along the seam between the capsule's side and its top hemisphere crosses it once on each side.
+        let capsule = Csg::Shape(RenderShape::Capsule(1.0, 1.0), Box::default());
+        let spans = capsule.get_spans(Point::new(-5.0, 1.0, 0.0), Vector::unit_x());
+        assert_eq!(distances(&spans), [(4.0, 6.0)]);
+
+        let cut = Csg::Shape(RenderShape::Box(Vector::new(0.5, 5.0, 5.0)), Box::default());
+        let difference = Csg::combine(CsgOp::Difference, vec![capsule, cut]).unwrap();
+        let spans = difference.get_spans(Point::new(-5.0, 1.0, 0.0), Vector::unit_x());
+        assert_eq!(distances(&spans), [(4.0, 4.5), (5.5, 6.0)]);
+    }
+}
@@ -0,0 +1,270 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use cgmath::{EuclideanSpace, InnerSpace, Vector2};
+use crate::acceleration_structure::Bvh;
+use crate::renderable::HitDetail;
+use crate::transform::{Point, Vector};
+
+// Flatness, as a fraction of the curve's width, that segments are split down to before being traced as lines.
+const FLATNESS: f64 = 0.05;
+const MAX_SPLIT_DEPTH: i32 = 10;
+// Minimum distance to a hit, so rays leaving a curve don't hit it again.
+const MIN_DISTANCE: f64 = 1e-6;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CurveType {
+    // Flat strips that always face the ray, such as blades of grass seen from afar.
+    Ribbon,
+    // Traced like ribbons, but shaded with normals that curve around like a tube, for hair and fur.
+    Cylinder,
+}
+
+// A cubic Bezier segment, with widths at its start and end.
+#[derive(Debug, Copy, Clone)]
+pub struct CurveSegment {
+    points: [Point; 4],
+    widths: (f64, f64),
+}
+
+// Many curves sharing one shape, such as all the hairs on a head, with a hierarchy of their segments.
+#[derive(Debug, Clone)]
+pub struct Curves {
+    segments: Vec<CurveSegment>,
+    bvh: Bvh,
+    min: Point,
+    max: Point,
+    pub curve_type: CurveType,
+}
+
+impl Curves {
+    pub fn new(segments: Vec<CurveSegment>, curve_type: CurveType) -> Self {
+        let aabbs = segments.iter().map(|segment| Some(segment.get_aabb())).collect::<Vec<_>>();
+        let mut min = Point::new(f64::MAX, f64::MAX, f64::MAX);
+        let mut max = Point::new(f64::MIN, f64::MIN, f64::MIN);
+        for (center, size) in aabbs.iter().flatten() {
+            min = min.zip(center - size, f64::min);
+            max = max.zip(center + size, f64::max);
+        }
+        if segments.is_empty() {
+            (min, max) = (Point::origin(), Point::origin());
+        }
+
+        let mut bvh = Bvh::new(aabbs);
+        bvh.generate();
+        Self {
+            segments,
+            bvh,
+            min,
+            max,
+            curve_type,
+        }
+    }
+
+    // Curve files have one curve per line, as control points followed by the widths at its root and tip.
+    // A curve of several segments shares the end point of each with the start of the next, so it has 3n + 1 points.
+    // A single width keeps the curve the same width throughout. Blank lines and lines starting with # are skipped.
+    pub fn load<P: AsRef<Path>>(path: P, curve_type: CurveType) -> io::Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+
+        let mut segments = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let values = line.split_whitespace()
+                .map(|value| value.parse::<f64>().map_err(|_| invalid("Curve values must be numbers.")))
+                .collect::<io::Result<Vec<_>>>()?;
+
+            let (point_count, widths) = match values.len() % 3 {
+                1 => (values.len() / 3, (values[values.len() - 1], values[values.len() - 1])),
+                2 => (values.len() / 3, (values[values.len() - 2], values[values.len() - 1])),
+                _ => return Err(invalid("Curves need control points followed by one or two widths.")),
+            };
+            if point_count < 4 || (point_count - 1) % 3 != 0 {
+                return Err(invalid("Curves need 3n + 1 control points."));
+            }
+
+            let points = values[..point_count * 3].chunks(3).map(|p| Point::new(p[0], p[1], p[2])).collect::<Vec<_>>();
+            let segment_count = (point_count - 1) / 3;
+            for i in 0..segment_count {
+                let width_at = |s: usize| widths.0 + (widths.1 - widths.0) * s as f64 / segment_count as f64;
+                segments.push(CurveSegment {
+                    points: [points[i * 3], points[i * 3 + 1], points[i * 3 + 2], points[i * 3 + 3]],
+                    widths: (width_at(i), width_at(i + 1)),
+                });
+            }
+        }
+
+        Ok(Self::new(segments, curve_type))
+    }
+
+    // Returns the min and max corners of a box enclosing all the curves.
+    pub fn get_bounds(&self) -> (Point, Point) {
+        (self.min, self.max)
+    }
+
+    // Returns the nearest hit, its normal, which faces back along the ray, and the direction along the curve there.
+    pub fn trace(&self, ray_orig: Point, ray_dir: Vector) -> Option<(Point, Vector, HitDetail)> {
+        let speed = ray_dir.magnitude();
+        let dir = ray_dir / speed;
+        // The ray's frame has the ray along z, so curves are hit where they pass over the origin in x and y.
+        let helper = if dir.x.abs() < 0.9 { Vector::unit_x() } else { Vector::unit_y() };
+        let frame_x = dir.cross(helper).normalize();
+        let frame_y = dir.cross(frame_x);
+        let to_ray_space = |p: Point| {
+            let offset = p - ray_orig;
+            Point::new(offset.dot(frame_x), offset.dot(frame_y), offset.dot(dir))
+        };
+
+        let (dist, ind, u) = self.bvh.trace(ray_orig, ray_dir, |ind| {
+            let segment = &self.segments[ind];
+            let (dist, u) = segment.trace(segment.points.map(to_ray_space))?;
+            Some((dist / speed, (dist / speed, ind, u)))
+        })?;
+
+        let segment = &self.segments[ind];
+        let hit_point = ray_orig + ray_dir * dist;
+        let (center, tangent) = segment.evaluate(u);
+        let tangent = tangent.normalize();
+        let facing = perpendicular_to(tangent, -dir);
+        let normal = match self.curve_type {
+            CurveType::Ribbon => facing,
+            CurveType::Cylinder => {
+                // The offset across the ribbon sets how far round the tube the normal turns.
+                let side = facing.cross(tangent);
+                let half_width = segment.get_width(u) / 2.0;
+                let h = ((hit_point - center).dot(side) / half_width).clamp(-1.0, 1.0);
+                facing * (1.0 - h * h).sqrt() + side * h
+            }
+        };
+        Some((hit_point, normal, HitDetail::Tangent(tangent)))
+    }
+
+}
+
+impl CurveSegment {
+    fn get_width(&self, u: f64) -> f64 {
+        self.widths.0 + (self.widths.1 - self.widths.0) * u
+    }
+
+    // Center and half size of a box around the control points, which enclose the curve, padded by its width.
+    fn get_aabb(&self) -> (Point, Vector) {
+        let mut min = Point::new(f64::MAX, f64::MAX, f64::MAX);
+        let mut max = Point::new(f64::MIN, f64::MIN, f64::MIN);
+        for p in self.points {
+            min = min.zip(p, f64::min);
+            max = max.zip(p, f64::max);
+        }
+        let half_width = self.widths.0.max(self.widths.1) / 2.0;
+        let pad = Vector::new(half_width, half_width, half_width);
+        (min.midpoint(max), (max - min) / 2.0 + pad)
+    }
+
+    // Point and derivative at a parameter along the segment.
+    fn evaluate(&self, u: f64) -> (Point, Vector) {
+        evaluate_bezier(&self.points, u)
+    }
+
+    // Finds where the ray meets the segment, given its control points in the ray's frame.
+    // Returns the distance along the normalized ray, and the parameter along the segment.
+    fn trace(&self, points: [Point; 4]) -> Option<(f64, f64)> {
+        // Segments are split in half until each piece is close enough to a line, based on how far the curve bends.
+        let bend = (0..2).map(|i| {
+            let d = points[i].to_vec() - points[i + 1].to_vec() * 2.0 + points[i + 2].to_vec();
+            d.x.abs().max(d.y.abs()).max(d.z.abs())
+        }).fold(0.0, f64::max);
+        let flatness = self.widths.0.max(self.widths.1) * FLATNESS;
+        let depth = if flatness > 0.0 {
+            ((std::f64::consts::SQRT_2 * 6.0 * bend / (8.0 * flatness)).log2() / 2.0) as i32
+        } else {
+            0
+        };
+        self.trace_piece(points, 0.0, 1.0, depth.clamp(0, MAX_SPLIT_DEPTH), f64::INFINITY)
+    }
+
+    fn trace_piece(&self, points: [Point; 4], u0: f64, u1: f64, depth: i32, max_dist: f64) -> Option<(f64, f64)> {
+        let half_width = self.get_width(u0).max(self.get_width(u1)) / 2.0;
+        let mut min = Point::new(f64::MAX, f64::MAX, f64::MAX);
+        let mut max = Point::new(f64::MIN, f64::MIN, f64::MIN);
+        for p in points {
+            min = min.zip(p, f64::min);
+            max = max.zip(p, f64::max);
+        }
+        if min.x - half_width > 0.0 || max.x + half_width < 0.0 || min.y - half_width > 0.0 || max.y + half_width < 0.0
+            || max.z + half_width < 0.0 || min.z - half_width > max_dist {
+            return None;
+        }
+
+        if depth > 0 {
+            let (first, second) = split_bezier(&points);
+            let u_mid = (u0 + u1) / 2.0;
+            let first_hit = self.trace_piece(first, u0, u_mid, depth - 1, max_dist);
+            let max_dist = first_hit.map_or(max_dist, |(dist, _)| dist);
+            return self.trace_piece(second, u_mid, u1, depth - 1, max_dist).or(first_hit);
+        }
+
+        // The piece is traced as a line, ignoring the parts of the ray beyond the tangents at its ends.
+        let start_edge = (points[1].y - points[0].y) * -points[0].y + points[0].x * (points[0].x - points[1].x);
+        let end_edge = (points[2].y - points[3].y) * -points[3].y + points[3].x * (points[3].x - points[2].x);
+        if start_edge < 0.0 || end_edge < 0.0 {
+            return None;
+        }
+        let line = Vector2::new(points[3].x - points[0].x, points[3].y - points[0].y);
+        let length2 = line.magnitude2();
+        if length2 == 0.0 {
+            return None;
+        }
+        let w = (Vector2::new(-points[0].x, -points[0].y).dot(line) / length2).clamp(0.0, 1.0);
+        let u = u0 + (u1 - u0) * w;
+        let width = self.get_width(u);
+        let (point, _) = evaluate_bezier(&points, w);
+        if point.x * point.x + point.y * point.y > width * width / 4.0 {
+            return None;
+        }
+        if point.z < MIN_DISTANCE || point.z > max_dist {
+            return None;
+        }
+        // Rays starting on the curve, such as those leaving it, are inside its width there, and don't hit it again.
+        if point.to_vec().magnitude2() < width * width / 4.0 {
+            return None;
+        }
+        Some((point.z, u))
+    }
+}
+
+fn evaluate_bezier(points: &[Point; 4], u: f64) -> (Point, Vector) {
+    let p = points.map(|p| p.to_vec());
+    let a = p[0] * (1.0 - u) + p[1] * u;
+    let b = p[1] * (1.0 - u) + p[2] * u;
+    let c = p[2] * (1.0 - u) + p[3] * u;
+    let ab = a * (1.0 - u) + b * u;
+    let bc = b * (1.0 - u) + c * u;
+    let derivative = (bc - ab) * 3.0;
+    // The derivative vanishes at ends with repeated control points, where the direction to the next point is used instead.
+    let derivative = if derivative.magnitude2() > 0.0 { derivative } else { p[3] - p[0] };
+    (Point::from_vec(ab * (1.0 - u) + bc * u), derivative)
+}
+
+// Splits a Bezier segment into its two halves.
+fn split_bezier(points: &[Point; 4]) -> ([Point; 4], [Point; 4]) {
+    let mid = |a: Point, b: Point| a.midpoint(b);
+    let (a, b, c) = (mid(points[0], points[1]), mid(points[1], points[2]), mid(points[2], points[3]));
+    let (ab, bc) = (mid(a, b), mid(b, c));
+    let center = mid(ab, bc);
+    ([points[0], a, ab, center], [center, bc, c, points[3]])
+}
+
+// The direction's part perpendicular to the axis, or any perpendicular if they are parallel.
+fn perpendicular_to(axis: Vector, direction: Vector) -> Vector {
+    let perpendicular = direction - axis * direction.dot(axis);
+    if perpendicular.magnitude2() > f64::EPSILON {
+        return perpendicular.normalize();
+    }
+    let helper = if axis.x.abs() < 0.9 { Vector::unit_x() } else { Vector::unit_y() };
+    axis.cross(helper).normalize()
+}
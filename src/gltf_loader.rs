@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use std::f64::consts::{PI, TAU};
+use std::path::Path;
+use std::sync::Arc;
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, One, SquareMatrix, Vector2, Vector4};
+use gltf::image::Format;
+use image::{Rgb, Rgb32FImage};
+use crate::camera::{Camera, FieldOfView, Projection};
+use crate::material::PhysicalMaterial;
+use crate::mesh::Mesh;
+use crate::renderable::{Renderable, RenderShape};
+use crate::scene::{Scene, Sky};
+use crate::scene_error::SceneError;
+use crate::texture::srgb_to_linear;
+use crate::transform::{Point, Quat, Transform, Vector};
+
+// Radius of the glowing spheres standing in for point and spot lights.
+const LIGHT_RADIUS: f64 = 0.05;
+
+// Objects, camera, and sun read from a glTF file.
+// glTF space is right handed, so z is mirrored to turn it into yarpt's left handed space.
+pub struct GltfContents {
+    pub objects: Vec<Renderable>,
+    // The first camera in the node hierarchy.
+    pub camera: Option<Camera>,
+    // Direction towards the first directional light, and its color.
+    pub sun: Option<(Vector, Vector)>,
+}
+
+// Loads a .gltf or .glb file as a whole scene, under the default sky unless it has a directional light.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Scene, SceneError> {
+    let path = path.as_ref();
+    let contents = import(path).map_err(|err| located_error(path, err))?;
+    let mut scene = Scene::new(contents.camera.unwrap_or_default(), Sky::default());
+    if let Some((sun_dir, sun_color)) = contents.sun {
+        scene.sky.set_sun_dir(sun_dir);
+        scene.sky.sun_color = sun_color;
+    }
+    for object in contents.objects {
+        scene.add_object(object);
+    }
+    Ok(scene)
+}
+
+// Syntax errors are placed at their line and column in the JSON, and invalid values are named by their path in it,
+// such as meshes[0].primitives[0].
+fn located_error(path: &Path, err: gltf::Error) -> SceneError {
+    match err {
+        gltf::Error::Deserialize(err) => {
+            // serde_json ends its messages with the line and column, which are shown before the message instead.
+            let message = err.to_string();
+            let message = message.split(" at line ").next().unwrap_or_default();
+            SceneError {
+                file: path.to_path_buf(),
+                line: err.line(),
+                column: err.column(),
+                message: format!("Failed to read glTF file ({message})"),
+                token: String::new(),
+                suggestion: None,
+            }
+        }
+        gltf::Error::Validation(errors) if !errors.is_empty() => {
+            let (json_path, err) = &errors[0];
+            SceneError {
+                token: json_path.to_string(),
+                ..SceneError::in_file(path, format!("Invalid glTF file ({err}) at"))
+            }
+        }
+        err => SceneError::in_file(path, format!("Failed to load glTF file ({err})")),
+    }
+}
+
+pub fn import<P: AsRef<Path>>(path: P) -> gltf::Result<GltfContents> {
+    let (document, buffers, images) = gltf::import(path)?;
+    let mut contents = GltfContents {
+        objects: Vec::new(),
+        camera: None,
+        sun: None,
+    };
+    let Some(scene) = document.default_scene().or_else(|| document.scenes().next()) else {
+        return Ok(contents);
+    };
+
+    let mirror = Matrix4::from_nonuniform_scale(1.0, 1.0, -1.0);
+    // Meshes placed by several nodes share their triangles and hierarchy.
+    let mut meshes: HashMap<(usize, usize), Arc<Mesh>> = HashMap::new();
+    let mut nodes = scene.nodes().map(|node| (node, Matrix4::identity())).collect::<Vec<_>>();
+    while let Some((node, parent)) = nodes.pop() {
+        let matrix = parent * Matrix4::from(node.transform().matrix()).cast::<f64>().unwrap();
+        // Mesh data is mirrored when it is read, so objects are mirrored on both sides.
+        let transform = Transform::from_matrix(mirror * matrix * mirror);
+
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                if primitive.mode() != gltf::mesh::Mode::Triangles {
+                    continue;
+                }
+                let shape = meshes.entry((mesh.index(), primitive.index()))
+                    .or_insert_with(|| Arc::new(read_primitive(&primitive, &buffers, &images)))
+                    .clone();
+                let mut object = Renderable::new(transform, read_material(&primitive), RenderShape::Mesh(shape));
+                object.name = node.name().map(str::to_string);
+                contents.objects.push(object);
+            }
+        }
+
+        if let (Some(camera), None) = (node.camera(), &contents.camera) {
+            let mut scene_camera = Camera {
+                transform,
+                ..Camera::default()
+            };
+            match camera.projection() {
+                gltf::camera::Projection::Perspective(perspective) => {
+                    scene_camera.field_of_view = Some(FieldOfView::Vertical((perspective.yfov() as f64).to_degrees()));
+                }
+                gltf::camera::Projection::Orthographic(orthographic) => {
+                    scene_camera.projection = Projection::Orthographic;
+                    scene_camera.ortho_width = 2.0 * orthographic.xmag() as f64;
+                }
+            }
+            contents.camera = Some(scene_camera);
+        }
+
+        if let Some(light) = node.light() {
+            let color = to_vector(light.color());
+            let intensity = light.intensity() as f64;
+            match light.kind() {
+                gltf::khr_lights_punctual::Kind::Directional => {
+                    if contents.sun.is_none() {
+                        // Lights shine down their local -z axis, and the sun's irradiance is TAU times its color.
+                        let light_dir = (mirror * matrix * Vector4::new(0.0, 0.0, -1.0, 0.0)).truncate();
+                        contents.sun = Some((-light_dir.normalize(), color * intensity / TAU));
+                    }
+                }
+                // yarpt has no cone lights, so spot lights shine in every direction too.
+                gltf::khr_lights_punctual::Kind::Point | gltf::khr_lights_punctual::Kind::Spot { .. } => {
+                    let position = Point::from_homogeneous(mirror * matrix * Vector4::unit_w());
+                    // A sphere's intensity is its radiance times its cross section.
+                    let material = PhysicalMaterial::new(color, 1.0, 0.0, intensity / (PI * LIGHT_RADIUS * LIGHT_RADIUS));
+                    let mut object = Renderable::new_sphere(Transform::new(position, Quat::one(), Vector::new(1.0, 1.0, 1.0)), material, LIGHT_RADIUS);
+                    object.name = node.name().or(light.name()).map(str::to_string);
+                    contents.objects.push(object);
+                }
+            }
+        }
+
+        nodes.extend(node.children().map(|child| (child, matrix)));
+    }
+    Ok(contents)
+}
+
+fn read_primitive(primitive: &gltf::Primitive, buffers: &[gltf::buffer::Data], images: &[gltf::image::Data]) -> Mesh {
+    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+    let mirror = |[x, y, z]: [f32; 3]| Vector::new(x as f64, y as f64, -z as f64);
+    let positions = reader.read_positions().map_or(Vec::new(), |iter| iter.map(|p| Point::from_vec(mirror(p))).collect());
+    let normals = reader.read_normals().map_or(Vec::new(), |iter| iter.map(mirror).collect());
+    let base_color_texture = primitive.material().pbr_metallic_roughness().base_color_texture();
+    let uv_set = base_color_texture.as_ref().map_or(0, |info| info.tex_coord());
+    let uvs = reader.read_tex_coords(uv_set).map_or(Vec::new(), |iter| {
+        iter.into_f32().map(|[u, v]| Vector2::new(u as f64, v as f64)).collect()
+    });
+    let colors = reader.read_colors(0).map_or(Vec::new(), |iter| {
+        iter.into_rgb_f32().map(to_vector).collect()
+    });
+    let indices = match reader.read_indices() {
+        Some(iter) => iter.into_u32().map(|i| i as usize).collect(),
+        None => (0..positions.len()).collect::<Vec<_>>(),
+    };
+    let triangles = indices.chunks_exact(3).map(|triangle| [triangle[0], triangle[1], triangle[2]]).collect();
+
+    let mut mesh = Mesh::new(positions, normals, uvs, colors, triangles);
+    mesh.texture = base_color_texture.map(|info| read_texture(&images[info.texture().source().index()]));
+    mesh
+}
+
+// Base color, metallic, roughness, and emission are read from their factors.
+// Metallic and roughness textures aren't read.
+fn read_material(primitive: &gltf::Primitive) -> PhysicalMaterial {
+    let gltf_material = primitive.material();
+    let pbr = gltf_material.pbr_metallic_roughness();
+    let [r, g, b, _] = pbr.base_color_factor();
+    let mut material = PhysicalMaterial::new(
+        Vector::new(r as f64, g as f64, b as f64),
+        pbr.roughness_factor() as f64,
+        pbr.metallic_factor() as f64,
+        0.0,
+    );
+    material.vertex_colors = primitive.get(&gltf::Semantic::Colors(0)).is_some() || pbr.base_color_texture().is_some();
+
+    // Emission is a multiple of the diffuse color, so emissive materials take the emissive color as their diffuse.
+    let emissive = to_vector(gltf_material.emissive_factor())
+        * gltf_material.emissive_strength().unwrap_or(1.0) as f64;
+    let strength = emissive.x.max(emissive.y).max(emissive.z);
+    if strength > 0.0 {
+        material.diffuse = emissive / strength;
+        material.emissive = strength;
+        material.vertex_colors = false;
+    }
+    material
+}
+
+// Integer images are sRGB encoded, and float images are already linear.
+fn read_texture(data: &gltf::image::Data) -> Rgb32FImage {
+    let (channels, bytes) = match data.format {
+        Format::R8 => (1, 1),
+        Format::R8G8 => (2, 1),
+        Format::R8G8B8 => (3, 1),
+        Format::R8G8B8A8 => (4, 1),
+        Format::R16 => (1, 2),
+        Format::R16G16 => (2, 2),
+        Format::R16G16B16 => (3, 2),
+        Format::R16G16B16A16 => (4, 2),
+        Format::R32G32B32FLOAT => (3, 4),
+        Format::R32G32B32A32FLOAT => (4, 4),
+    };
+    let value = |i: usize| {
+        let value = &data.pixels[i * bytes..(i + 1) * bytes];
+        match bytes {
+            1 => srgb_to_linear(value[0] as f32 / u8::MAX as f32),
+            2 => srgb_to_linear(u16::from_ne_bytes([value[0], value[1]]) as f32 / u16::MAX as f32),
+            _ => f32::from_ne_bytes([value[0], value[1], value[2], value[3]]),
+        }
+    };
+    // Gray images use their first channel for all three colors.
+    let channel = |c: usize| if channels < 3 { 0 } else { c };
+    Rgb32FImage::from_fn(data.width, data.height, |x, y| {
+        let pixel = (y as usize * data.width as usize + x as usize) * channels;
+        Rgb([value(pixel + channel(0)), value(pixel + channel(1)), value(pixel + channel(2))])
+    })
+}
+
+fn to_vector([x, y, z]: [f32; 3]) -> Vector {
+    Vector::new(x as f64, y as f64, z as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One triangle from (0, 0, 0) to (1, 0, 0) and (0, 1, 1), a camera, and a sun shining down -z.
+    const TRIANGLE: &str = r#"{
+        "asset": { "version": "2.0" },
+        "extensionsUsed": ["KHR_lights_punctual"],
+        "extensions": { "KHR_lights_punctual": { "lights": [{ "type": "directional", "color": [1, 1, 1], "intensity": 2 }] } },
+        "scene": 0,
+        "scenes": [{ "nodes": [0, 1, 2] }],
+        "nodes": [
+            { "name": "triangle", "mesh": 0, "translation": [1, 2, 3] },
+            { "camera": 0, "translation": [0, 0, 5] },
+            { "extensions": { "KHR_lights_punctual": { "light": 0 } } }
+        ],
+        "cameras": [{ "type": "perspective", "perspective": { "yfov": 0.5, "znear": 0.1 } }],
+        "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "material": 0 }] }],
+        "materials": [{ "pbrMetallicRoughness": { "baseColorFactor": [1, 0, 0, 1], "metallicFactor": 0, "roughnessFactor": 0.5 } }],
+        "accessors": [{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 1] }],
+        "bufferViews": [{ "buffer": 0, "byteLength": 36 }],
+        "buffers": [{ "byteLength": 36, "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAIA/" }]
+    }"#;
+
+    fn load_text(name: &str, text: &str) -> Result<Scene, SceneError> {
+        let path = std::env::temp_dir().join(format!("yarpt_test_{name}.gltf"));
+        std::fs::write(&path, text).unwrap();
+        let scene = load(&path);
+        std::fs::remove_file(&path).unwrap();
+        scene
+    }
+
+    #[test]
+    fn triangle_scene() {
+        let scene = load_text("gltf_triangle", TRIANGLE).unwrap();
+        assert_eq!(scene.get_object_count(), 1);
+        let object = scene.get_object(0);
+        assert_eq!(object.name.as_deref(), Some("triangle"));
+        assert_eq!(object.material.diffuse, Vector::new(1.0, 0.0, 0.0));
+        // z is mirrored in both the node transforms and the mesh data.
+        assert_eq!(object.transform.get_position(), Point::new(1.0, 2.0, -3.0));
+        let RenderShape::Mesh(mesh) = &object.shape else {
+            panic!("expected a mesh, found {:?}", object.shape);
+        };
+        assert_eq!(mesh.get_bounds(), (Point::new(0.0, 0.0, -1.0), Point::new(1.0, 1.0, 0.0)));
+
+        assert_eq!(scene.camera.transform.get_position(), Point::new(0.0, 0.0, -5.0));
+        let Some(FieldOfView::Vertical(fov)) = scene.camera.field_of_view else {
+            panic!("expected a vertical field of view, found {:?}", scene.camera.field_of_view);
+        };
+        assert!((fov - 0.5f64.to_degrees()).abs() < 1e-4);
+        // The sun is behind the light, which shines down -z in glTF space and +z in yarpt's.
+        // Without a disk, the sun is sampled at its exact direction.
+        let mut sky = scene.sky.clone();
+        sky.sun_size = 0.0;
+        assert!((sky.sample_sun().0 + Vector::unit_z()).magnitude() < 1e-9);
+        assert!((scene.sky.sun_color - Vector::new(2.0, 2.0, 2.0) / TAU).magnitude() < 1e-9);
+    }
+
+    #[test]
+    fn syntax_errors_are_located() {
+        let err = load_text("gltf_syntax", "{\n  \"asset\": { \"version\": \"2.0\" },\n  \"scenes\": [ 1, ]\n}").unwrap_err();
+        assert_eq!(err.line, 3);
+        assert!(err.message.starts_with("Failed to read glTF file"), "{err}");
+    }
+}
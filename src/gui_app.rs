@@ -5,12 +5,13 @@ use crate::path_tracer::*;
 
 use fltk::{app, prelude::*, window::Window};
 use fltk::button::{Button, CheckButton};
-use fltk::dialog::file_chooser;
+use fltk::dialog::{alert_default, file_chooser, message_default};
 use fltk::draw::{draw_image, draw_rect_fill};
 use fltk::enums::{Color, ColorDepth};
 use fltk::frame::Frame;
 use fltk::group::{Pack, PackType, Scroll, ScrollType};
 use fltk::input::{Input, IntInput};
+use fltk::menu::Choice;
 use fltk::surface::ImageSurface;
 use image::ColorType;
 use crate::scene::{Sky};
@@ -72,6 +73,12 @@ pub fn run (){
         .with_label("Denoise");
     denoise_checkbox.set_value(true);
     
+    let mut stereo_choice = Choice::default()
+        .with_size(1, 30)
+        .with_label("Stereo");
+    stereo_choice.add_choice("Mono|Left Eye|Right Eye|Top-Bottom|Side-by-Side");
+    stereo_choice.set_value(0);
+    
     let mut render_button = Button::default()
         .with_size(100, 30)
         .with_label("Render");
@@ -113,6 +120,7 @@ pub fn run (){
         let tile_size_input = tile_size_input.clone();
         let threads_input = threads_input.clone();
         let denoise_checkbox = denoise_checkbox.clone();
+        let stereo_choice = stereo_choice.clone();
         move |render_button| {
             let scene_path = scene_path_input.value();
             let width = w_input.value().parse().unwrap();
@@ -122,6 +130,27 @@ pub fn run (){
             let tile_size = tile_size_input.value().parse().unwrap();
             let num_threads = threads_input.value().parse().unwrap();
             let denoise = denoise_checkbox.value();
+            let stereo = match stereo_choice.value() {
+                1 => StereoLayout::LeftEye,
+                2 => StereoLayout::RightEye,
+                3 => StereoLayout::TopBottom,
+                4 => StereoLayout::SideBySide,
+                _ => StereoLayout::Mono,
+            };
+            
+            // Scene problems are shown in a dialog, and the render is only started once the scene loads.
+            let mut warnings = Vec::new();
+            let mut scene = match scene_loader::load(scene_path, &mut warnings) {
+                Ok(scene) => scene,
+                Err(err) => {
+                    alert_default(&err.to_string());
+                    return;
+                }
+            };
+            if !warnings.is_empty() {
+                let warnings = warnings.iter().map(|warning| warning.to_string()).collect::<Vec<_>>();
+                message_default(&format!("The scene loaded with warnings:\n{}", warnings.join("\n")));
+            }
             
             render_result.set_size(width as i32, height as i32);
             render_button.deactivate();
@@ -130,8 +159,6 @@ pub fn run (){
             let mut sky = Sky::default();
             sky.set_sun_dir(Vector::new(1.0, 2.0, 1.5));
             
-            let mut scene = scene_loader::load(scene_path).unwrap();
-            
             println!("{}", scene.get_object_count());
             //scene.sky = sky;
             
@@ -142,6 +169,7 @@ pub fn run (){
                 bounces,
                 tile_size,
                 denoise,
+                stereo,
             };
             
             let path_tracer = PathTracer::new(
@@ -153,7 +181,7 @@ pub fn run (){
             let render_sender = render_sender.clone();
             
             render_sender.send(RenderMessages::StartRender(width, height));
-            thread_sender.send(std::thread::spawn(move||{path_tracer.clone().render(render_sender);})).unwrap();
+            thread_sender.send(std::thread::spawn(move||{path_tracer.clone().render(|msg| render_sender.send(msg));})).unwrap();
         }
     });
     
@@ -180,13 +208,7 @@ pub fn run (){
                     })
                 }
                 RenderMessages::UpdateRender(x, y, tile_size, image) => {
-                    let mut data: Vec<u8> = Vec::new();
-                    data.resize(image.len() * 3, 0);
-                    for i in 0..image.len() {
-                        for j in 0..3 {
-                            data[i * 3 + j] = (image[i][j] * 255.0) as u8;
-                        }
-                    }
+                    let data = to_rgb8(&image);
                     
                     ImageSurface::push_current(&surf.borrow());
                     draw_image(
@@ -203,13 +225,7 @@ pub fn run (){
                 }
                 RenderMessages::FinishRender(width, height, image) => {
                     if let Some(image) = image {
-                        let mut res_data: Vec<u8> = Vec::new();
-                        res_data.resize(width * height * 3, 0);
-                        for i in 0..image.len() {
-                            for j in 0..3 {
-                                res_data[i * 3 + j] = (image[i][j] * 255.0) as u8;
-                            }
-                        }
+                        let res_data = to_rgb8(&image);
 
                         ImageSurface::push_current(&surf.borrow());
                         draw_image(
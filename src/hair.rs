@@ -0,0 +1,325 @@
+use std::f64::consts::{PI, TAU};
+use cgmath::{ElementWise, InnerSpace};
+use rand::{Rng, thread_rng};
+use crate::material::PhysicalMaterial;
+use crate::transform::{Point, Vector};
+
+// Reflection, transmission, and internal reflection lobes, followed by one lobe for all further paths.
+const LOBES: usize = 3;
+const HAIR_IOR: f64 = 1.55;
+// Absorption of the two melanin pigments, per unit of concentration.
+const EUMELANIN_ABSORPTION: Vector = Vector::new(0.419, 0.697, 1.37);
+const PHEOMELANIN_ABSORPTION: Vector = Vector::new(0.187, 0.4, 1.05);
+const MIN_ROUGHNESS: f64 = 0.01;
+
+// Settings for shading a material as hair, after Chiang et al. and d'Eon et al.
+// Light is reflected off the fiber, transmitted through it, or reflected inside it, each lobe spread along and around the fiber.
+// The material's roughness spreads the lobes, and its color sets how much light the fiber absorbs, unless melanin is given.
+#[derive(Debug, Copy, Clone)]
+pub struct Hair {
+    // Eumelanin and pheomelanin concentrations, for natural hair colors from blonde to black.
+    pub melanin: Option<(f64, f64)>,
+    // Tilt of the scales on the fiber's surface, in degrees, which shifts the lobes apart.
+    pub scale_angle: f64,
+}
+
+// A hair lit at one point, in a frame with x along the fiber, and the ray's outgoing direction at no angle around it.
+struct HairBsdf {
+    h: f64,
+    gamma_o: f64,
+    absorption: Vector,
+    // Longitudinal variance and azimuthal scale of the lobes.
+    variances: [f64; LOBES + 1],
+    azimuthal_scale: f64,
+    // Sines and cosines of the scale angle doubled once and twice, for shifting each lobe.
+    sin_2k_alpha: [f64; 3],
+    cos_2k_alpha: [f64; 3],
+}
+
+impl Default for Hair {
+    fn default() -> Self {
+        Self {
+            melanin: None,
+            scale_angle: 2.0,
+        }
+    }
+}
+
+impl Hair {
+    // Samples a new direction for the ray through the hair BSDF. The tangent runs along the fiber, where it is known.
+    // Returns the color the ray is scaled by and the light emitted, like other materials.
+    pub fn hit_surface(&self, material: &PhysicalMaterial, ray_orig: &mut Point, ray_dir: &mut Vector, hit_point: Point, normal: Vector, tangent: Option<Vector>) -> (Vector, Vector) {
+        let tangent = tangent.unwrap_or_else(|| any_perpendicular(normal)).normalize();
+        let wo = -ray_dir.normalize();
+        // z faces the ray across the fiber, and the normal turns from it towards y by how far off center the ray hit.
+        let facing = {
+            let across = wo - tangent * wo.dot(tangent);
+            if across.magnitude2() > f64::EPSILON { across.normalize() } else { any_perpendicular(tangent) }
+        };
+        let side = facing.cross(tangent);
+        let h = normal.dot(side).clamp(-1.0, 1.0);
+
+        let roughness = material.roughness.clamp(MIN_ROUGHNESS, 1.0);
+        let absorption = match self.melanin {
+            Some((eumelanin, pheomelanin)) => EUMELANIN_ABSORPTION * eumelanin + PHEOMELANIN_ABSORPTION * pheomelanin,
+            None => absorption_from_color(material.diffuse, roughness),
+        };
+        let bsdf = HairBsdf::new(h, absorption, roughness, roughness, self.scale_angle);
+
+        let to_local = |v: Vector| Vector::new(v.dot(tangent), v.dot(facing), -v.dot(side));
+        let (wi, weight) = bsdf.sample(to_local(wo));
+
+        *ray_orig = hit_point;
+        *ray_dir = (tangent * wi.x + facing * wi.y - side * wi.z).normalize();
+        (weight, material.diffuse * material.emissive)
+    }
+}
+
+impl HairBsdf {
+    fn new(h: f64, absorption: Vector, longitudinal_roughness: f64, azimuthal_roughness: f64, scale_angle: f64) -> Self {
+        let beta_m = longitudinal_roughness;
+        let v0 = (0.726 * beta_m + 0.812 * beta_m.powi(2) + 3.7 * beta_m.powi(20)).powi(2);
+        let beta_n = azimuthal_roughness;
+        let azimuthal_scale = (PI / 8.0).sqrt() * (0.265 * beta_n + 1.194 * beta_n.powi(2) + 5.372 * beta_n.powi(22));
+
+        let mut sin_2k_alpha = [scale_angle.to_radians().sin(), 0.0, 0.0];
+        let mut cos_2k_alpha = [(1.0 - sin_2k_alpha[0] * sin_2k_alpha[0]).max(0.0).sqrt(), 0.0, 0.0];
+        for i in 1..3 {
+            sin_2k_alpha[i] = 2.0 * cos_2k_alpha[i - 1] * sin_2k_alpha[i - 1];
+            cos_2k_alpha[i] = cos_2k_alpha[i - 1].powi(2) - sin_2k_alpha[i - 1].powi(2);
+        }
+
+        Self {
+            h,
+            gamma_o: h.asin(),
+            absorption,
+            variances: [v0, 0.25 * v0, 4.0 * v0, 4.0 * v0],
+            azimuthal_scale,
+            sin_2k_alpha,
+            cos_2k_alpha,
+        }
+    }
+
+    // Returns the sampled incoming direction, and the BSDF value divided by the chance of sampling it.
+    fn sample(&self, wo: Vector) -> (Vector, Vector) {
+        let mut rng = thread_rng();
+        let (sin_theta_o, cos_theta_o) = (wo.x, (1.0 - wo.x * wo.x).max(0.0).sqrt());
+        let phi_o = wo.z.atan2(wo.y);
+        let lobe_chances = self.lobe_chances(sin_theta_o, cos_theta_o);
+
+        let mut choice = rng.gen::<f64>();
+        let mut lobe = 0;
+        while lobe < LOBES && choice >= lobe_chances[lobe] {
+            choice -= lobe_chances[lobe];
+            lobe += 1;
+        }
+
+        // Longitudinal angle, around the cone of directions the lobe is shifted to.
+        let (sin_theta_op, cos_theta_op) = self.tilt(lobe, sin_theta_o, cos_theta_o);
+        let v = self.variances[lobe];
+        let u = rng.gen::<f64>().max(1e-5);
+        let cos_theta = 1.0 + v * (u + (1.0 - u) * (-2.0 / v).exp()).ln();
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let cos_phi = (TAU * rng.gen::<f64>()).cos();
+        let sin_theta_i = (-cos_theta * sin_theta_op + sin_theta * cos_phi * cos_theta_op).clamp(-1.0, 1.0);
+        let cos_theta_i = (1.0 - sin_theta_i * sin_theta_i).max(0.0).sqrt();
+
+        // Azimuthal angle, around the direction the lobe leaves the fiber in.
+        let gamma_t = self.gamma_t(sin_theta_o, cos_theta_o);
+        let dphi = if lobe < LOBES {
+            lobe_phi(lobe, self.gamma_o, gamma_t) + sample_trimmed_logistic(rng.gen::<f64>(), self.azimuthal_scale, -PI, PI)
+        } else {
+            TAU * rng.gen::<f64>()
+        };
+        let phi_i = phi_o + dphi;
+        let wi = Vector::new(sin_theta_i, cos_theta_i * phi_i.cos(), cos_theta_i * phi_i.sin());
+
+        let mut pdf = 0.0;
+        for (p, chance) in lobe_chances.iter().enumerate() {
+            let (sin_theta_op, cos_theta_op) = self.tilt(p, sin_theta_o, cos_theta_o);
+            let azimuthal = if p < LOBES {
+                azimuthal_distribution(dphi, p, self.azimuthal_scale, self.gamma_o, gamma_t)
+            } else {
+                1.0 / TAU
+            };
+            pdf += longitudinal_distribution(cos_theta_i, cos_theta_op, sin_theta_i, sin_theta_op, self.variances[p]) * chance * azimuthal;
+        }
+
+        let value = self.evaluate(wo, wi);
+        if pdf > 0.0 {
+            (wi, value / pdf)
+        } else {
+            (wi, Vector::new(0.0, 0.0, 0.0))
+        }
+    }
+
+    // The BSDF times the cosine of the incoming direction, as the ray's weight doesn't include it separately.
+    fn evaluate(&self, wo: Vector, wi: Vector) -> Vector {
+        let (sin_theta_o, cos_theta_o) = (wo.x, (1.0 - wo.x * wo.x).max(0.0).sqrt());
+        let (sin_theta_i, cos_theta_i) = (wi.x, (1.0 - wi.x * wi.x).max(0.0).sqrt());
+        let phi = wi.z.atan2(wi.y) - wo.z.atan2(wo.y);
+        let gamma_t = self.gamma_t(sin_theta_o, cos_theta_o);
+        let attenuation = self.attenuation(sin_theta_o, cos_theta_o);
+
+        let mut value = Vector::new(0.0, 0.0, 0.0);
+        for (p, lobe_attenuation) in attenuation.iter().enumerate() {
+            let (sin_theta_op, cos_theta_op) = self.tilt(p, sin_theta_o, cos_theta_o);
+            let azimuthal = if p < LOBES {
+                azimuthal_distribution(phi, p, self.azimuthal_scale, self.gamma_o, gamma_t)
+            } else {
+                1.0 / TAU
+            };
+            value += lobe_attenuation * longitudinal_distribution(cos_theta_i, cos_theta_op, sin_theta_i, sin_theta_op, self.variances[p]) * azimuthal;
+        }
+        value
+    }
+
+    // Angle of the path through the fiber, seen along it.
+    fn gamma_t(&self, sin_theta_o: f64, cos_theta_o: f64) -> f64 {
+        let eta_p = (HAIR_IOR * HAIR_IOR - sin_theta_o * sin_theta_o).sqrt() / cos_theta_o.max(f64::EPSILON);
+        (self.h / eta_p).clamp(-1.0, 1.0).asin()
+    }
+
+    // How much light is left in each lobe after the reflections and absorption along its path.
+    fn attenuation(&self, sin_theta_o: f64, cos_theta_o: f64) -> [Vector; LOBES + 1] {
+        let sin_theta_t = sin_theta_o / HAIR_IOR;
+        let cos_theta_t = (1.0 - sin_theta_t * sin_theta_t).max(0.0).sqrt();
+        let gamma_t = self.gamma_t(sin_theta_o, cos_theta_o);
+        let transmittance = (-self.absorption * (2.0 * gamma_t.cos() / cos_theta_t)).map(f64::exp);
+
+        let cos_gamma_o = (1.0 - self.h * self.h).max(0.0).sqrt();
+        let f = fresnel_dielectric(cos_theta_o * cos_gamma_o, HAIR_IOR);
+        let mut attenuation = [Vector::new(f, f, f); LOBES + 1];
+        attenuation[1] = transmittance * (1.0 - f).powi(2);
+        for p in 2..LOBES {
+            attenuation[p] = attenuation[p - 1].mul_element_wise(transmittance) * f;
+        }
+        attenuation[LOBES] = (attenuation[LOBES - 1].mul_element_wise(transmittance) * f)
+            .zip(transmittance, |a, t| if t * f < 1.0 { a / (1.0 - t * f) } else { 0.0 });
+        attenuation
+    }
+
+    // Chance of sampling each lobe, by its share of the light.
+    fn lobe_chances(&self, sin_theta_o: f64, cos_theta_o: f64) -> [f64; LOBES + 1] {
+        let luminance = self.attenuation(sin_theta_o, cos_theta_o).map(|a| 0.212671 * a.x + 0.715160 * a.y + 0.072169 * a.z);
+        let total = luminance.iter().sum::<f64>();
+        if total > 0.0 {
+            luminance.map(|l| l / total)
+        } else {
+            [1.0, 0.0, 0.0, 0.0]
+        }
+    }
+
+    // Shifts the outgoing angle by the scale tilt, up for reflection and down for the paths through the fiber.
+    fn tilt(&self, lobe: usize, sin_theta_o: f64, cos_theta_o: f64) -> (f64, f64) {
+        let (sin_theta, cos_theta) = match lobe {
+            0 => (
+                sin_theta_o * self.cos_2k_alpha[1] - cos_theta_o * self.sin_2k_alpha[1],
+                cos_theta_o * self.cos_2k_alpha[1] + sin_theta_o * self.sin_2k_alpha[1],
+            ),
+            1 => (
+                sin_theta_o * self.cos_2k_alpha[0] + cos_theta_o * self.sin_2k_alpha[0],
+                cos_theta_o * self.cos_2k_alpha[0] - sin_theta_o * self.sin_2k_alpha[0],
+            ),
+            2 => (
+                sin_theta_o * self.cos_2k_alpha[2] + cos_theta_o * self.sin_2k_alpha[2],
+                cos_theta_o * self.cos_2k_alpha[2] - sin_theta_o * self.sin_2k_alpha[2],
+            ),
+            _ => (sin_theta_o, cos_theta_o),
+        };
+        (sin_theta, cos_theta.abs())
+    }
+}
+
+// Absorption that gives roughly the color after many scattering paths through a head of hair.
+fn absorption_from_color(color: Vector, azimuthal_roughness: f64) -> Vector {
+    let b = azimuthal_roughness;
+    let scale = 5.969 - 0.215 * b + 2.532 * b.powi(2) - 10.73 * b.powi(3) + 5.574 * b.powi(4) + 0.245 * b.powi(5);
+    color.map(|c| (c.max(1e-4).ln() / scale).powi(2))
+}
+
+// Spread of a lobe along the fiber, as a normalized distribution over the sphere.
+fn longitudinal_distribution(cos_theta_i: f64, cos_theta_o: f64, sin_theta_i: f64, sin_theta_o: f64, v: f64) -> f64 {
+    let a = cos_theta_i * cos_theta_o / v;
+    let b = sin_theta_i * sin_theta_o / v;
+    if v <= 0.1 {
+        // Large exponents are kept in log space to avoid overflowing.
+        (log_bessel_i0(a) - b - 1.0 / v + std::f64::consts::LN_2 + (1.0 / (2.0 * v)).ln()).exp()
+    } else {
+        (-b).exp() * bessel_i0(a) / ((1.0 / v).sinh() * 2.0 * v)
+    }
+}
+
+// Angle around the fiber that a lobe leaves at, for a ray that hits it off center.
+fn lobe_phi(lobe: usize, gamma_o: f64, gamma_t: f64) -> f64 {
+    let p = lobe as f64;
+    2.0 * p * gamma_t - 2.0 * gamma_o + p * PI
+}
+
+// Spread of a lobe around the fiber.
+fn azimuthal_distribution(phi: f64, lobe: usize, scale: f64, gamma_o: f64, gamma_t: f64) -> f64 {
+    let dphi = (phi - lobe_phi(lobe, gamma_o, gamma_t) + PI).rem_euclid(TAU) - PI;
+    trimmed_logistic(dphi, scale, -PI, PI)
+}
+
+fn logistic(x: f64, s: f64) -> f64 {
+    let x = x.abs();
+    (-x / s).exp() / (s * (1.0 + (-x / s).exp()).powi(2))
+}
+
+fn logistic_cdf(x: f64, s: f64) -> f64 {
+    1.0 / (1.0 + (-x / s).exp())
+}
+
+fn trimmed_logistic(x: f64, s: f64, a: f64, b: f64) -> f64 {
+    logistic(x, s) / (logistic_cdf(b, s) - logistic_cdf(a, s))
+}
+
+fn sample_trimmed_logistic(u: f64, s: f64, a: f64, b: f64) -> f64 {
+    let k = logistic_cdf(b, s) - logistic_cdf(a, s);
+    let x = -s * (1.0 / (u * k + logistic_cdf(a, s)) - 1.0).ln();
+    x.clamp(a, b)
+}
+
+// Modified Bessel function of the first kind, from its series.
+fn bessel_i0(x: f64) -> f64 {
+    let mut value = 0.0;
+    let mut x2i = 1.0;
+    let mut factorial: f64 = 1.0;
+    let mut four_i = 1.0;
+    for i in 0..10 {
+        if i > 1 {
+            factorial *= i as f64;
+        }
+        value += x2i / (four_i * factorial * factorial);
+        x2i *= x * x;
+        four_i *= 4.0;
+    }
+    value
+}
+
+fn log_bessel_i0(x: f64) -> f64 {
+    if x > 12.0 {
+        x + 0.5 * (-(TAU).ln() + (1.0 / x).ln() + 1.0 / (8.0 * x))
+    } else {
+        bessel_i0(x).ln()
+    }
+}
+
+// Fraction of light reflected when entering a dielectric from air.
+fn fresnel_dielectric(cos_theta_i: f64, ior: f64) -> f64 {
+    let cos_theta_i = cos_theta_i.clamp(-1.0, 1.0).abs();
+    let sin_theta_t = (1.0 - cos_theta_i * cos_theta_i).max(0.0).sqrt() / ior;
+    if sin_theta_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_theta_t = (1.0 - sin_theta_t * sin_theta_t).max(0.0).sqrt();
+    let parallel = (ior * cos_theta_i - cos_theta_t) / (ior * cos_theta_i + cos_theta_t);
+    let perpendicular = (cos_theta_i - ior * cos_theta_t) / (cos_theta_i + ior * cos_theta_t);
+    (parallel * parallel + perpendicular * perpendicular) / 2.0
+}
+
+fn any_perpendicular(v: Vector) -> Vector {
+    let helper = if v.x.abs() < 0.9 { Vector::unit_x() } else { Vector::unit_y() };
+    v.cross(helper).normalize()
+}
@@ -0,0 +1,189 @@
+use std::path::Path;
+use cgmath::{InnerSpace, Vector2};
+use image::ImageResult;
+use image::error::{ImageError, ParameterError, ParameterErrorKind};
+use crate::mesh::trace_triangle;
+use crate::transform::{Point, Vector};
+
+// Terrain from a grid of heights, spread over the xz plane and traced through a tree of height ranges.
+#[derive(Debug, Clone)]
+pub struct Heightfield {
+    width: usize,
+    depth: usize,
+    heights: Vec<f32>,
+    // Lowest and highest height under each block of cells, halving in size at each level up from single cells.
+    levels: Vec<HeightLevel>,
+    // Half sizes of the terrain along x and z.
+    pub size: Vector2<f64>,
+    // Height of a heightmap value of one.
+    pub height_scale: f64,
+}
+
+#[derive(Debug, Clone)]
+struct HeightLevel {
+    width: usize,
+    depth: usize,
+    ranges: Vec<(f32, f32)>,
+}
+
+impl Heightfield {
+    // Heights are given in rows along x, one row for each step along z.
+    // Grids smaller than 2 by 2 make a flat terrain, such as the one a heightfield has before its heightmap is loaded.
+    pub fn new(width: usize, depth: usize, heights: Vec<f32>) -> Self {
+        let (width, depth, heights) = if width < 2 || depth < 2 {
+            (2, 2, vec![0.0; 4])
+        } else {
+            (width, depth, heights)
+        };
+
+        let mut cells = Vec::with_capacity((width - 1) * (depth - 1));
+        for z in 0..depth - 1 {
+            for x in 0..width - 1 {
+                let corners = [heights[z * width + x], heights[z * width + x + 1], heights[(z + 1) * width + x], heights[(z + 1) * width + x + 1]];
+                cells.push((corners.into_iter().fold(f32::MAX, f32::min), corners.into_iter().fold(f32::MIN, f32::max)));
+            }
+        }
+        let mut levels = vec![HeightLevel { width: width - 1, depth: depth - 1, ranges: cells }];
+        while levels.last().unwrap().width > 1 || levels.last().unwrap().depth > 1 {
+            let below = levels.last().unwrap();
+            let (level_width, level_depth) = (below.width.div_ceil(2), below.depth.div_ceil(2));
+            let mut ranges = vec![(f32::MAX, f32::MIN); level_width * level_depth];
+            for z in 0..below.depth {
+                for x in 0..below.width {
+                    let (min, max) = below.ranges[z * below.width + x];
+                    let range = &mut ranges[(z / 2) * level_width + x / 2];
+                    *range = (range.0.min(min), range.1.max(max));
+                }
+            }
+            levels.push(HeightLevel { width: level_width, depth: level_depth, ranges });
+        }
+
+        Self {
+            width,
+            depth,
+            heights,
+            levels,
+            size: Vector2::new(1.0, 1.0),
+            height_scale: 1.0,
+        }
+    }
+
+    // 16 bit images keep their precision as heights from zero to one, and float images keep their values.
+    // The top of the image is at -z.
+    pub fn load<P: AsRef<Path>>(path: P) -> ImageResult<Self> {
+        let image = image::open(path)?.to_luma32f();
+        if image.width() < 2 || image.height() < 2 {
+            return Err(ImageError::Parameter(ParameterError::from_kind(ParameterErrorKind::Generic("Heightmaps must be at least 2 by 2 pixels.".to_string()))));
+        }
+        Ok(Self::new(image.width() as usize, image.height() as usize, image.into_raw()))
+    }
+
+    // Returns the min and max heights of the whole terrain.
+    pub fn get_height_range(&self) -> (f64, f64) {
+        let (min, max) = self.levels.last().unwrap().ranges[0];
+        (min as f64 * self.height_scale, max as f64 * self.height_scale)
+    }
+
+    // Returns the nearest hit and the smoothed normal there, facing back along the ray.
+    pub fn trace(&self, ray_orig: Point, ray_dir: Vector) -> Option<(Point, Vector)> {
+        // Rays are moved into grid space, where cells are one unit wide and heights are unscaled.
+        // The mapping is linear, so distances along the ray stay the same.
+        let to_grid = Vector::new(
+            (self.width - 1) as f64 / (2.0 * self.size.x),
+            1.0 / self.height_scale,
+            (self.depth - 1) as f64 / (2.0 * self.size.y),
+        );
+        let grid_orig = Point::new(
+            (ray_orig.x + self.size.x) * to_grid.x,
+            ray_orig.y * to_grid.y,
+            (ray_orig.z + self.size.y) * to_grid.z,
+        );
+        let grid_dir = Vector::new(ray_dir.x * to_grid.x, ray_dir.y * to_grid.y, ray_dir.z * to_grid.z);
+        let inv_dir = 1.0 / grid_dir;
+
+        let mut nearest: Option<(f64, Vector)> = None;
+        // Blocks are visited nearest first, and skipped once they are behind the nearest hit.
+        let mut stack = vec![(self.levels.len() - 1, 0, 0)];
+        while let Some((level, x, z)) = stack.pop() {
+            let max_dist = nearest.map_or(f64::INFINITY, |(dist, _)| dist);
+            let block = 1 << level;
+            let (min_height, max_height) = self.levels[level].ranges[z * self.levels[level].width + x];
+            let min = Point::new((x * block) as f64, min_height as f64, (z * block) as f64);
+            let max = Point::new(
+                ((x + 1) * block).min(self.width - 1) as f64,
+                max_height as f64,
+                ((z + 1) * block).min(self.depth - 1) as f64,
+            );
+            if slab_distance(grid_orig, inv_dir, min, max).filter(|dist| *dist < max_dist).is_none() {
+                continue;
+            }
+
+            if level == 0 {
+                if let Some((dist, normal)) = self.trace_cell(x, z, grid_orig, grid_dir).filter(|(dist, _)| *dist < max_dist) {
+                    nearest = Some((dist, normal));
+                }
+                continue;
+            }
+
+            let below = &self.levels[level - 1];
+            let mut children = Vec::with_capacity(4);
+            for (child_x, child_z) in [(2 * x, 2 * z), (2 * x + 1, 2 * z), (2 * x, 2 * z + 1), (2 * x + 1, 2 * z + 1)] {
+                if child_x < below.width && child_z < below.depth {
+                    let center = Point::new((child_x as f64 + 0.5) * (block / 2) as f64, grid_orig.y, (child_z as f64 + 0.5) * (block / 2) as f64);
+                    children.push(((level - 1, child_x, child_z), (center - grid_orig).dot(grid_dir)));
+                }
+            }
+            // The stack is last in, first out, so the farthest child goes on first.
+            children.sort_by(|a, b| b.1.total_cmp(&a.1));
+            stack.extend(children.into_iter().map(|(child, _)| child));
+        }
+
+        let (dist, grid_normal) = nearest?;
+        // Normals go back through the inverse transpose of the grid mapping.
+        let normal = Vector::new(grid_normal.x * to_grid.x, grid_normal.y * to_grid.y, grid_normal.z * to_grid.z).normalize();
+        let normal = if normal.dot(ray_dir) > 0.0 { -normal } else { normal };
+        Some((ray_orig + ray_dir * dist, normal))
+    }
+
+    // Each cell is split into two triangles along its diagonal, with normals blended from the corners.
+    fn trace_cell(&self, x: usize, z: usize, ray_orig: Point, ray_dir: Vector) -> Option<(f64, Vector)> {
+        let corner = |cx: usize, cz: usize| Point::new(cx as f64, self.get_height(cx, cz), cz as f64);
+        let (p00, p10, p01, p11) = (corner(x, z), corner(x + 1, z), corner(x, z + 1), corner(x + 1, z + 1));
+        [((x, z), (x + 1, z), (x + 1, z + 1), p00, p10, p11), ((x, z), (x + 1, z + 1), (x, z + 1), p00, p11, p01)]
+            .into_iter()
+            .filter_map(|(a, b, c, pa, pb, pc)| {
+                let (dist, u, v) = trace_triangle(ray_orig, ray_dir, pa, pb, pc)?;
+                let normal = self.get_normal(a.0, a.1) * (1.0 - u - v) + self.get_normal(b.0, b.1) * u + self.get_normal(c.0, c.1) * v;
+                Some((dist, normal))
+            })
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+    }
+
+    fn get_height(&self, x: usize, z: usize) -> f64 {
+        self.heights[z * self.width + x] as f64
+    }
+
+    // Normal at a grid point in grid space, from the slope to its neighbours.
+    fn get_normal(&self, x: usize, z: usize) -> Vector {
+        let (left, right) = (x.saturating_sub(1), (x + 1).min(self.width - 1));
+        let (back, front) = (z.saturating_sub(1), (z + 1).min(self.depth - 1));
+        let slope_x = (self.get_height(right, z) - self.get_height(left, z)) / (right - left) as f64;
+        let slope_z = (self.get_height(x, front) - self.get_height(x, back)) / (front - back) as f64;
+        Vector::new(-slope_x, 1.0, -slope_z)
+    }
+}
+
+// Distance along the ray to where it enters a box, or zero if it starts inside.
+fn slab_distance(ray_orig: Point, inv_dir: Vector, min: Point, max: Point) -> Option<f64> {
+    let t0 = (min - ray_orig).zip(inv_dir, |d, inv| d * inv);
+    let t1 = (max - ray_orig).zip(inv_dir, |d, inv| d * inv);
+    let t_min = t0.zip(t1, f64::min);
+    let t_max = t0.zip(t1, f64::max);
+    let near = t_min.x.max(t_min.y.max(t_min.z));
+    let far = t_max.x.min(t_max.y.min(t_max.z));
+    if far >= near.max(0.0) {
+        Some(near.max(0.0))
+    } else {
+        None
+    }
+}
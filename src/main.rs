@@ -1,13 +1,33 @@
 mod transform;
+mod animation;
 mod camera;
 mod renderable;
+mod polynomial;
+mod csg;
+mod sdf;
+mod heightfield;
+mod curve;
+mod mesh;
+mod texture;
+mod gltf_loader;
+mod pbrt_loader;
 mod material;
+mod hair;
+mod medium;
+mod voxel_grid;
 mod acceleration_structure;
 mod scene;
+mod scene_error;
 mod scene_loader;
 mod path_tracer;
 mod gui_app;
+mod cli;
 
 fn main() {
-    gui_app::run();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.is_empty() {
+        gui_app::run();
+    } else {
+        cli::run(args);
+    }
 }
@@ -2,6 +2,7 @@ use lerp::Lerp;
 use cgmath::{ElementWise, InnerSpace};
 use rand::{Rng, thread_rng};
 use rand::distributions::Bernoulli;
+use crate::hair::Hair;
 use crate::transform::{Point, Vector};
 
 pub trait Material {
@@ -14,6 +15,10 @@ pub struct PhysicalMaterial {
     pub roughness: f64,
     pub metallic: f64,
     pub emissive: f64,
+    // Tints the diffuse color by the shape's vertex colors or texture where it has them.
+    pub vertex_colors: bool,
+    // Shades the surface as hair instead, using the color and roughness above.
+    pub hair: Option<Hair>,
 }
 
 impl PhysicalMaterial {
@@ -23,6 +28,8 @@ impl PhysicalMaterial {
             roughness,
             metallic,
             emissive,
+            vertex_colors: false,
+            hair: None,
         }
     }
 }
@@ -64,6 +71,8 @@ impl Default for PhysicalMaterial {
             roughness: 1.0,
             metallic: 0.0,
             emissive: 0.0,
+            vertex_colors: false,
+            hair: None,
         }
     }
 }
\ No newline at end of file
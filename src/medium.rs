@@ -0,0 +1,279 @@
+use std::f64::consts::{PI, TAU};
+use std::sync::Arc;
+use cgmath::{Array, ElementWise, EuclideanSpace, InnerSpace, Zero};
+use rand::{Rng, thread_rng};
+use crate::transform::{Point, Transform, Vector};
+use crate::voxel_grid::VoxelGrid;
+
+// A participating medium, such as fog, smoke, murky water, or fire.
+// Without a density grid the medium is homogeneous. With one, the coefficients are
+// scaled by the grid density, which spans the unit cube [-1, 1]^3 of the owning transform.
+#[derive(Debug, Clone)]
+pub struct Medium {
+    pub absorption: Vector,
+    pub scattering: Vector,
+    pub anisotropy: f64,
+    pub emission: Vector,
+    pub density: Option<Arc<VoxelGrid>>,
+    pub emission_grid: Option<Arc<VoxelGrid>>,
+}
+
+impl Medium {
+    pub fn new(absorption: Vector, scattering: Vector, anisotropy: f64) -> Self {
+        Self {
+            absorption,
+            scattering,
+            anisotropy,
+            emission: Vector::zero(),
+            density: None,
+            emission_grid: None,
+        }
+    }
+
+    pub fn extinction(&self) -> Vector {
+        self.absorption + self.scattering
+    }
+
+    // Fraction of light passing through a segment of the medium.
+    pub fn transmittance(&self, ray_orig: Point, ray_dir: Vector, dist: f64, transform: &Transform) -> Vector {
+        if let Some(density) = &self.density {
+            return self.ratio_tracking(density, ray_orig, ray_dir, dist, transform);
+        }
+        self.extinction().map(|sigma_t| (-sigma_t * dist).exp())
+    }
+
+    // Samples a free-flight distance along a ray segment of length max_dist.
+    // Returns the scatter distance (if the ray scattered before max_dist), the throughput weight,
+    // and the light emitted by the medium towards the ray origin.
+    pub fn sample_distance(&self, ray_orig: Point, ray_dir: Vector, max_dist: f64, transform: &Transform) -> (Option<f64>, Vector, Vector) {
+        if let Some(density) = &self.density {
+            return self.spectral_tracking(density, ray_orig, ray_dir, max_dist, transform);
+        }
+        
+        let sigma_t = self.extinction();
+        if sigma_t.x <= 0.0 && sigma_t.y <= 0.0 && sigma_t.z <= 0.0 {
+            return (None, Vector::new(1.0, 1.0, 1.0), Vector::zero());
+        }
+
+        // Emission integrated analytically over the whole segment.
+        let emitted = self.absorption.mul_element_wise(self.emission).zip(sigma_t, |emit, sigma_t| {
+            if sigma_t > 0.0 && max_dist.is_finite() {
+                emit * (1.0 - (-sigma_t * max_dist).exp()) / sigma_t
+            } else if sigma_t > 0.0 {
+                emit / sigma_t
+            } else {
+                0.0
+            }
+        });
+
+        let mut rng = thread_rng();
+
+        // Pick a color channel to sample from, and weight by the average pdf of all channels.
+        let channel = rng.gen_range(0..3);
+        let dist = if sigma_t[channel] > 0.0 {
+            -(1.0 - rng.gen::<f64>()).ln() / sigma_t[channel]
+        } else {
+            f64::INFINITY
+        };
+
+        let homogeneous = |dist: f64| sigma_t.map(|sigma_t| (-sigma_t * dist).exp());
+        if dist < max_dist {
+            let transmittance = homogeneous(dist);
+            let pdf = sigma_t.mul_element_wise(transmittance).sum() / 3.0;
+            (Some(dist), self.scattering.mul_element_wise(transmittance) / pdf, emitted)
+        } else {
+            let transmittance = homogeneous(max_dist);
+            let pdf = transmittance.sum() / 3.0;
+            if pdf <= 0.0 {
+                return (None, Vector::zero(), emitted);
+            }
+            (None, transmittance / pdf, emitted)
+        }
+    }
+    
+    // Delta tracking generalized to colored media (Kutz et al. 2017), with a collision estimator for emission.
+    fn spectral_tracking(&self, density: &VoxelGrid, ray_orig: Point, ray_dir: Vector, max_dist: f64, transform: &Transform) -> (Option<f64>, Vector, Vector) {
+        let majorant = max_component(self.extinction()) * density.get_max_value();
+        let mut weight = Vector::new(1.0, 1.0, 1.0);
+        let mut emitted = Vector::zero();
+        if majorant <= 0.0 {
+            return (None, weight, emitted);
+        }
+        
+        let mut rng = thread_rng();
+        let mut dist = 0.0;
+        loop {
+            dist -= (1.0 - rng.gen::<f64>()).ln() / majorant;
+            if dist >= max_dist {
+                return (None, weight, emitted);
+            }
+            
+            let local_point = Self::grid_point(ray_orig + ray_dir * dist, transform);
+            let local_density = density.lookup(local_point);
+            let sigma_a = self.absorption * local_density;
+            let sigma_s = self.scattering * local_density;
+            let sigma_n = (sigma_a + sigma_s).map(|sigma_t| (majorant - sigma_t).max(0.0));
+            
+            emitted.add_assign_element_wise(
+                weight.mul_element_wise(sigma_a).mul_element_wise(self.get_emission(local_point, local_density)) / majorant
+            );
+            
+            let p_a = weight.mul_element_wise(sigma_a).sum();
+            let p_s = weight.mul_element_wise(sigma_s).sum();
+            let p_n = weight.mul_element_wise(sigma_n).sum();
+            let total = p_a + p_s + p_n;
+            if total <= 0.0 {
+                return (None, Vector::zero(), emitted);
+            }
+            
+            let choice = rng.gen::<f64>() * total;
+            if choice < p_a {
+                return (None, Vector::zero(), emitted);
+            } else if choice < p_a + p_s {
+                weight.mul_assign_element_wise(sigma_s * (total / (majorant * p_s)));
+                return (Some(dist), weight, emitted);
+            } else {
+                weight.mul_assign_element_wise(sigma_n * (total / (majorant * p_n)));
+            }
+        }
+    }
+    
+    fn ratio_tracking(&self, density: &VoxelGrid, ray_orig: Point, ray_dir: Vector, max_dist: f64, transform: &Transform) -> Vector {
+        let majorant = max_component(self.extinction()) * density.get_max_value();
+        let mut transmittance = Vector::new(1.0, 1.0, 1.0);
+        if majorant <= 0.0 {
+            return transmittance;
+        }
+        
+        let mut rng = thread_rng();
+        let mut dist = 0.0;
+        loop {
+            dist -= (1.0 - rng.gen::<f64>()).ln() / majorant;
+            if dist >= max_dist {
+                return transmittance;
+            }
+            let local_density = density.lookup(Self::grid_point(ray_orig + ray_dir * dist, transform));
+            let sigma_t = self.extinction() * local_density;
+            transmittance.mul_assign_element_wise(sigma_t.map(|sigma_t| (1.0 - sigma_t / majorant).max(0.0)));
+            if max_component(transmittance) <= 0.0 {
+                return transmittance;
+            }
+        }
+    }
+    
+    fn get_emission(&self, local_point: Point, local_density: f64) -> Vector {
+        if let Some(emission_grid) = &self.emission_grid {
+            self.emission * emission_grid.lookup(local_point)
+        } else {
+            self.emission * local_density
+        }
+    }
+    
+    // Maps a global point into the [0, 1]^3 coordinates of a density grid.
+    fn grid_point(point: Point, transform: &Transform) -> Point {
+        Point::from_vec((transform.to_local_point(point).to_vec() + Vector::new(1.0, 1.0, 1.0)) * 0.5)
+    }
+
+    // Henyey-Greenstein phase function for light travelling along in_dir and leaving along out_dir.
+    pub fn phase(&self, in_dir: Vector, out_dir: Vector) -> f64 {
+        let g = self.anisotropy;
+        let denom = 1.0 + g * g - 2.0 * g * in_dir.dot(out_dir);
+        (1.0 - g * g) / (4.0 * PI * denom * denom.sqrt())
+    }
+
+    // Importance samples a new direction from the phase function.
+    pub fn sample_phase(&self, ray_dir: Vector) -> Vector {
+        let mut rng = thread_rng();
+        let g = self.anisotropy;
+
+        let u = rng.gen::<f64>();
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u
+        } else {
+            let sqr_term = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+            (1.0 + g * g - sqr_term * sqr_term) / (2.0 * g)
+        }.clamp(-1.0, 1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = rng.gen::<f64>() * TAU;
+
+        let (tangent, bitangent) = orthonormal_basis(ray_dir);
+        (tangent * (sin_theta * phi.cos()) + bitangent * (sin_theta * phi.sin()) + ray_dir * cos_theta).normalize()
+    }
+}
+
+impl Default for Medium {
+    fn default() -> Self {
+        Self {
+            absorption: Vector::zero(),
+            scattering: Vector::zero(),
+            anisotropy: 0.0,
+            emission: Vector::zero(),
+            density: None,
+            emission_grid: None,
+        }
+    }
+}
+
+pub fn orthonormal_basis(normal: Vector) -> (Vector, Vector) {
+    let up = if normal.x.abs() > 0.9 {
+        Vector::unit_y()
+    } else {
+        Vector::unit_x()
+    };
+    let tangent = normal.cross(up).normalize();
+    let bitangent = normal.cross(tangent);
+    (tangent, bitangent)
+}
+
+fn max_component(vector: Vector) -> f64 {
+    vector.x.max(vector.y).max(vector.z)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLES: usize = 20000;
+
+    #[test]
+    fn homogeneous_transmittance() {
+        let medium = Medium::new(Vector::new(0.5, 0.0, 0.0), Vector::new(0.5, 1.0, 0.0), 0.0);
+        let transmittance = medium.transmittance(Point::origin(), Vector::unit_z(), 2.0, &Transform::default());
+        assert!((transmittance - Vector::new((-2.0f64).exp(), (-2.0f64).exp(), 1.0)).magnitude() < 1e-12);
+        // Clear media never scatter.
+        let (dist, weight, _) = Medium::default().sample_distance(Point::origin(), Vector::unit_z(), 1.0, &Transform::default());
+        assert_eq!((dist, weight), (None, Vector::new(1.0, 1.0, 1.0)));
+    }
+
+    #[test]
+    fn homogeneous_scattering_rate() {
+        let medium = Medium::new(Vector::zero(), Vector::new(1.0, 1.0, 1.0), 0.0);
+        let scattered = (0..SAMPLES)
+            .filter(|_| medium.sample_distance(Point::origin(), Vector::unit_z(), 1.0, &Transform::default()).0.is_some())
+            .count();
+        let expected = 1.0 - (-1.0f64).exp();
+        assert!((scattered as f64 / SAMPLES as f64 - expected).abs() < 0.02);
+    }
+
+    #[test]
+    fn uniform_grid_matches_homogeneous() {
+        let mut medium = Medium::new(Vector::new(1.0, 1.0, 1.0), Vector::zero(), 0.0);
+        medium.density = Some(Arc::new(VoxelGrid::new([2, 2, 2], vec![0.5; 8])));
+        // The grid spans [-1, 1]^3, so a ray through its middle crosses two units of half density.
+        let ray_orig = Point::new(0.0, 0.0, -1.0);
+        let mean = (0..SAMPLES)
+            .map(|_| medium.transmittance(ray_orig, Vector::unit_z(), 2.0, &Transform::default()).x)
+            .sum::<f64>() / SAMPLES as f64;
+        assert!((mean - (-1.0f64).exp()).abs() < 0.02, "{mean}");
+    }
+
+    #[test]
+    fn isotropic_phase() {
+        let medium = Medium::new(Vector::zero(), Vector::new(1.0, 1.0, 1.0), 0.0);
+        assert!((medium.phase(Vector::unit_x(), Vector::unit_y()) - 1.0 / (4.0 * PI)).abs() < 1e-12);
+        // Forward scattering media mostly keep going the same way.
+        let forward = Medium::new(Vector::zero(), Vector::new(1.0, 1.0, 1.0), 0.8);
+        let mean_cos = (0..SAMPLES).map(|_| forward.sample_phase(Vector::unit_z()).z).sum::<f64>() / SAMPLES as f64;
+        assert!((mean_cos - 0.8).abs() < 0.02, "{mean_cos}");
+    }
+}
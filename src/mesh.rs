@@ -0,0 +1,481 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use cgmath::{ElementWise, EuclideanSpace, InnerSpace, Vector2};
+use image::Rgb32FImage;
+use crate::acceleration_structure::Bvh;
+use crate::renderable::HitDetail;
+use crate::texture::sample_texture;
+use crate::transform::{Point, Vector};
+
+// Minimum distance to a triangle, so rays leaving it don't hit it again.
+const MIN_DISTANCE: f64 = 1e-6;
+
+// Triangles sharing a list of vertices, with a hierarchy of the triangles.
+// Normals, texture coordinates, and colors are per vertex, and are left empty when the file has none.
+#[derive(Debug, Clone)]
+pub struct Mesh {
+    positions: Vec<Point>,
+    normals: Vec<Vector>,
+    uvs: Vec<Vector2<f64>>,
+    colors: Vec<Vector>,
+    triangles: Vec<[usize; 3]>,
+    bvh: Bvh,
+    min: Point,
+    max: Point,
+    // Linear colors over the texture coordinates, which start at the top left of the image as in glTF.
+    pub texture: Option<Rgb32FImage>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum PlyType {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64,
+}
+
+#[derive(Debug, Clone)]
+struct PlyProperty {
+    name: String,
+    value_type: PlyType,
+    // Lists, such as a face's vertex indices, start with a count of this type.
+    count_type: Option<PlyType>,
+}
+
+#[derive(Debug, Clone)]
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>,
+}
+
+// Reads values from the body of a PLY file, after its header.
+struct PlyReader<'a> {
+    format: PlyFormat,
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Mesh {
+    pub fn new(positions: Vec<Point>, normals: Vec<Vector>, uvs: Vec<Vector2<f64>>, colors: Vec<Vector>, triangles: Vec<[usize; 3]>) -> Self {
+        let mut min = Point::new(f64::MAX, f64::MAX, f64::MAX);
+        let mut max = Point::new(f64::MIN, f64::MIN, f64::MIN);
+        for p in &positions {
+            min = min.zip(*p, f64::min);
+            max = max.zip(*p, f64::max);
+        }
+        if positions.is_empty() {
+            (min, max) = (Point::origin(), Point::origin());
+        }
+
+        let aabbs = triangles.iter().map(|triangle| {
+            let [a, b, c] = triangle.map(|i| positions[i]);
+            let (tri_min, tri_max) = (a.zip(b, f64::min).zip(c, f64::min), a.zip(b, f64::max).zip(c, f64::max));
+            Some((tri_min.midpoint(tri_max), (tri_max - tri_min) / 2.0))
+        }).collect();
+        let mut bvh = Bvh::new(aabbs);
+        bvh.generate();
+
+        Self {
+            positions,
+            normals,
+            uvs,
+            colors,
+            triangles,
+            bvh,
+            min,
+            max,
+            texture: None,
+        }
+    }
+
+    // Loads a PLY or STL file, in either text or binary form, by its extension.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let data = fs::read(path)?;
+        match path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase()).as_deref() {
+            Some("ply") => Mesh::from_ply(&data),
+            Some("stl") => Mesh::from_stl(&data),
+            _ => Err(invalid_data("Meshes must be PLY or STL files.")),
+        }
+    }
+
+    // Returns the min and max corners of a box enclosing the mesh.
+    pub fn get_bounds(&self) -> (Point, Point) {
+        (self.min, self.max)
+    }
+
+    // Returns the nearest hit, its normal, which faces back along the ray, and the triangle hit.
+    // Vertex normals are blended across the triangle when the mesh has them.
+    pub fn trace(&self, ray_orig: Point, ray_dir: Vector) -> Option<(Point, Vector, HitDetail)> {
+        let (dist, ind, u, v) = self.bvh.trace(ray_orig, ray_dir, |ind| {
+            let [a, b, c] = self.triangles[ind].map(|i| self.positions[i]);
+            let (dist, u, v) = trace_triangle(ray_orig, ray_dir, a, b, c)?;
+            Some((dist, (dist, ind, u, v)))
+        })?;
+
+        let [a, b, c] = self.triangles[ind].map(|i| self.positions[i]);
+        let face_normal = (b - a).cross(c - a).normalize();
+        let face_normal = if face_normal.dot(ray_dir) > 0.0 { -face_normal } else { face_normal };
+        let normal = match self.interpolate(&self.normals, ind, u, v) {
+            Some(normal) if normal.magnitude2() > 0.0 => {
+                let normal = normal.normalize();
+                if normal.dot(face_normal) < 0.0 { -normal } else { normal }
+            }
+            _ => face_normal,
+        };
+        Some((ray_orig + ray_dir * dist, normal, HitDetail::Triangle(ind, u, v)))
+    }
+
+    // Texture coordinates at a point on a triangle, given by the weights of its second and third vertices.
+    pub fn get_uv(&self, ind: usize, u: f64, v: f64) -> Option<Vector2<f64>> {
+        self.interpolate(&self.uvs, ind, u, v)
+    }
+
+    // Vertex colors and the texture are multiplied where the mesh has both.
+    pub fn get_color(&self, ind: usize, u: f64, v: f64) -> Option<Vector> {
+        let vertex_color = self.interpolate(&self.colors, ind, u, v);
+        let texture_color = match (&self.texture, self.interpolate(&self.uvs, ind, u, v)) {
+            (Some(texture), Some(uv)) => Some(sample_texture(texture, uv)),
+            _ => None,
+        };
+        match (vertex_color, texture_color) {
+            (Some(vertex_color), Some(texture_color)) => Some(vertex_color.mul_element_wise(texture_color)),
+            (vertex_color, texture_color) => vertex_color.or(texture_color),
+        }
+    }
+
+    // Blends a per vertex value across a triangle, by the weights of its second and third vertices.
+    fn interpolate<T>(&self, values: &[T], ind: usize, u: f64, v: f64) -> Option<T>
+    where T: Copy + std::ops::Mul<f64, Output = T> + std::ops::Add<Output = T> {
+        if values.is_empty() {
+            return None;
+        }
+        let [a, b, c] = self.triangles[ind].map(|i| values[i]);
+        Some(a * (1.0 - u - v) + b * u + c * v)
+    }
+
+    fn from_ply(data: &[u8]) -> io::Result<Self> {
+        let header_end = find_subslice(data, b"end_header").ok_or_else(|| invalid_data("PLY file has no end_header."))?;
+        let header = String::from_utf8_lossy(&data[..header_end]);
+        // The body starts on the line after end_header.
+        let body_start = data[header_end..].iter().position(|b| *b == b'\n').map_or(data.len(), |i| header_end + i + 1);
+
+        let mut format = None;
+        let mut elements: Vec<PlyElement> = Vec::new();
+        for line in header.lines() {
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            match words.as_slice() {
+                ["format", "ascii", ..] => format = Some(PlyFormat::Ascii),
+                ["format", "binary_little_endian", ..] => format = Some(PlyFormat::BinaryLittleEndian),
+                ["format", "binary_big_endian", ..] => format = Some(PlyFormat::BinaryBigEndian),
+                ["element", name, count] => elements.push(PlyElement {
+                    name: name.to_string(),
+                    count: count.parse().map_err(|_| invalid_data("PLY element count must be a number."))?,
+                    properties: Vec::new(),
+                }),
+                ["property", "list", count_type, value_type, name] => {
+                    elements.last_mut().ok_or_else(|| invalid_data("PLY property is outside any element."))?.properties.push(PlyProperty {
+                        name: name.to_string(),
+                        value_type: PlyType::parse(value_type)?,
+                        count_type: Some(PlyType::parse(count_type)?),
+                    });
+                }
+                ["property", value_type, name] => {
+                    elements.last_mut().ok_or_else(|| invalid_data("PLY property is outside any element."))?.properties.push(PlyProperty {
+                        name: name.to_string(),
+                        value_type: PlyType::parse(value_type)?,
+                        count_type: None,
+                    });
+                }
+                _ => {}
+            }
+        }
+        let format = format.ok_or_else(|| invalid_data("PLY file has no format."))?;
+
+        let mut reader = PlyReader { format, data: &data[body_start..], pos: 0 };
+        let (mut positions, mut normals, mut uvs, mut colors, mut triangles) = (Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        for element in &elements {
+            let has = |names: &[&str]| names.iter().all(|name| element.properties.iter().any(|p| p.name == *name));
+            let uv_names = ["u", "v", "s", "t", "texture_u", "texture_v", "texture_s", "texture_t"];
+            for _i in 0..element.count {
+                let mut values = Vec::with_capacity(element.properties.len());
+                let mut indices = Vec::new();
+                for property in &element.properties {
+                    match property.count_type {
+                        Some(count_type) => {
+                            let count = reader.read(count_type)? as usize;
+                            let list = (0..count).map(|_| reader.read(property.value_type)).collect::<io::Result<Vec<_>>>()?;
+                            if property.name == "vertex_indices" || property.name == "vertex_index" {
+                                indices = list.into_iter().map(|i| i as usize).collect();
+                            }
+                        }
+                        None => values.push((property.name.as_str(), property.value_type, reader.read(property.value_type)?)),
+                    }
+                }
+                let get = |name: &str| values.iter().find(|(n, _, _)| *n == name).map(|(_, _, value)| *value);
+
+                if element.name == "vertex" {
+                    positions.push(Point::new(get("x").unwrap_or(0.0), get("y").unwrap_or(0.0), get("z").unwrap_or(0.0)));
+                    if has(&["nx", "ny", "nz"]) {
+                        normals.push(Vector::new(get("nx").unwrap(), get("ny").unwrap(), get("nz").unwrap()));
+                    }
+                    let uv = uv_names.chunks(2).find_map(|pair| Some(Vector2::new(get(pair[0])?, get(pair[1])?)));
+                    if let Some(uv) = uv {
+                        uvs.push(uv);
+                    }
+                    if has(&["red", "green", "blue"]) {
+                        // Integer colors go up to their type's maximum, and float colors up to one.
+                        let channel = |name: &str| {
+                            let (_, value_type, value) = values.iter().find(|(n, _, _)| *n == name).unwrap();
+                            value / value_type.max_color()
+                        };
+                        colors.push(Vector::new(channel("red"), channel("green"), channel("blue")));
+                    }
+                } else if element.name == "face" {
+                    // Polygons are split into fans of triangles.
+                    for i in 1..indices.len().saturating_sub(1) {
+                        triangles.push([indices[0], indices[i], indices[i + 1]]);
+                    }
+                }
+            }
+        }
+
+        if triangles.is_empty() {
+            return Err(invalid_data("PLY file has no faces."));
+        }
+        if triangles.iter().flatten().any(|i| *i >= positions.len()) {
+            return Err(invalid_data("PLY face refers to a missing vertex."));
+        }
+        Ok(Mesh::new(positions, normals, uvs, colors, triangles))
+    }
+
+    // Binary STL files are recognized by their size, which is set by the triangle count after the 80 byte header.
+    // Files with bytes past the triangles are read as binary too, unless they end their text like ASCII files, with "endsolid".
+    fn from_stl(data: &[u8]) -> io::Result<Self> {
+        let mut positions = Vec::new();
+        let binary_size = data.get(80..84)
+            .and_then(|count| (u32::from_le_bytes(count.try_into().unwrap()) as usize).checked_mul(50))
+            .and_then(|size| size.checked_add(84));
+        let is_binary = binary_size.is_some_and(|size| data.len() == size || (data.len() > size && find_subslice(data, b"endsolid").is_none()));
+        if let (true, Some(size)) = (is_binary, binary_size) {
+            for triangle in data[84..size].chunks_exact(50) {
+                // Each triangle is a normal, three vertices, and two unused bytes. The normal is found from the vertices instead.
+                for vertex in triangle[12..48].chunks_exact(12) {
+                    let coord = |i: usize| f32::from_le_bytes(vertex[i * 4..i * 4 + 4].try_into().unwrap()) as f64;
+                    positions.push(Point::new(coord(0), coord(1), coord(2)));
+                }
+            }
+        } else {
+            let text = String::from_utf8_lossy(data);
+            for line in text.lines() {
+                let words = line.split_whitespace().collect::<Vec<_>>();
+                if let ["vertex", x, y, z] = words.as_slice() {
+                    let coord = |value: &str| value.parse::<f64>().map_err(|_| invalid_data("STL vertex coordinates must be numbers."));
+                    positions.push(Point::new(coord(x)?, coord(y)?, coord(z)?));
+                }
+            }
+            if positions.len() % 3 != 0 {
+                return Err(invalid_data("STL facets must have three vertices."));
+            }
+        }
+        if positions.is_empty() {
+            return Err(invalid_data("STL file has no facets."));
+        }
+
+        let triangles = (0..positions.len() / 3).map(|i| [i * 3, i * 3 + 1, i * 3 + 2]).collect();
+        Ok(Mesh::new(positions, Vec::new(), Vec::new(), Vec::new(), triangles))
+    }
+}
+
+impl PlyType {
+    fn parse(name: &str) -> io::Result<Self> {
+        match name {
+            "char" | "int8" => Ok(PlyType::Int8),
+            "uchar" | "uint8" => Ok(PlyType::UInt8),
+            "short" | "int16" => Ok(PlyType::Int16),
+            "ushort" | "uint16" => Ok(PlyType::UInt16),
+            "int" | "int32" => Ok(PlyType::Int32),
+            "uint" | "uint32" => Ok(PlyType::UInt32),
+            "float" | "float32" => Ok(PlyType::Float32),
+            "double" | "float64" => Ok(PlyType::Float64),
+            _ => Err(invalid_data("Unknown PLY property type.")),
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            PlyType::Int8 | PlyType::UInt8 => 1,
+            PlyType::Int16 | PlyType::UInt16 => 2,
+            PlyType::Int32 | PlyType::UInt32 | PlyType::Float32 => 4,
+            PlyType::Float64 => 8,
+        }
+    }
+
+    fn max_color(&self) -> f64 {
+        match self {
+            PlyType::Int8 => i8::MAX as f64,
+            PlyType::UInt8 => u8::MAX as f64,
+            PlyType::Int16 => i16::MAX as f64,
+            PlyType::UInt16 => u16::MAX as f64,
+            PlyType::Int32 => i32::MAX as f64,
+            PlyType::UInt32 => u32::MAX as f64,
+            PlyType::Float32 | PlyType::Float64 => 1.0,
+        }
+    }
+}
+
+impl PlyReader<'_> {
+    fn read(&mut self, value_type: PlyType) -> io::Result<f64> {
+        if self.format == PlyFormat::Ascii {
+            let rest = &self.data[self.pos..];
+            let start = rest.iter().position(|b| !b.is_ascii_whitespace()).ok_or_else(|| invalid_data("PLY file ended early."))?;
+            let len = rest[start..].iter().position(|b| b.is_ascii_whitespace()).unwrap_or(rest.len() - start);
+            self.pos += start + len;
+            return std::str::from_utf8(&rest[start..start + len]).ok()
+                .and_then(|word| word.parse::<f64>().ok())
+                .ok_or_else(|| invalid_data("PLY values must be numbers."));
+        }
+
+        let size = value_type.size();
+        let bytes = self.data.get(self.pos..self.pos + size).ok_or_else(|| invalid_data("PLY file ended early."))?;
+        self.pos += size;
+        let mut word = [0u8; 8];
+        word[..size].copy_from_slice(bytes);
+        if self.format == PlyFormat::BinaryBigEndian {
+            word[..size].reverse();
+        }
+        let value = match value_type {
+            PlyType::Int8 => word[0] as i8 as f64,
+            PlyType::UInt8 => word[0] as f64,
+            PlyType::Int16 => i16::from_le_bytes([word[0], word[1]]) as f64,
+            PlyType::UInt16 => u16::from_le_bytes([word[0], word[1]]) as f64,
+            PlyType::Int32 => i32::from_le_bytes([word[0], word[1], word[2], word[3]]) as f64,
+            PlyType::UInt32 => u32::from_le_bytes([word[0], word[1], word[2], word[3]]) as f64,
+            PlyType::Float32 => f32::from_le_bytes([word[0], word[1], word[2], word[3]]) as f64,
+            PlyType::Float64 => f64::from_le_bytes(word),
+        };
+        Ok(value)
+    }
+}
+
+// Returns the distance and the barycentric weights of b and c where the ray hits the triangle.
+pub fn trace_triangle(ray_orig: Point, ray_dir: Vector, a: Point, b: Point, c: Point) -> Option<(f64, f64, f64)> {
+    let edge_b = b - a;
+    let edge_c = c - a;
+    let p = ray_dir.cross(edge_c);
+    let det = edge_b.dot(p);
+    if det.abs() < f64::EPSILON {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+    let offset = ray_orig - a;
+    let u = offset.dot(p) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = offset.cross(edge_b);
+    let v = ray_dir.dot(q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let dist = edge_c.dot(q) * inv_det;
+    if dist > MIN_DISTANCE {
+        Some((dist, u, v))
+    } else {
+        None
+    }
+}
+
+// Index of the first place the pattern appears in the data.
+fn find_subslice(data: &[u8], pattern: &[u8]) -> Option<usize> {
+    data.windows(pattern.len()).position(|window| window == pattern)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLY_HEADER: &str = "ply\nformat {format} 1.0\nelement vertex 4\nproperty float x\nproperty float y\nproperty float z\nproperty uchar red\nproperty uchar green\nproperty uchar blue\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n";
+
+    // A square of four vertices, split into two triangles.
+    fn check_square(mesh: &Mesh) {
+        assert_eq!(mesh.positions, [Point::new(0.0, 0.0, 0.0), Point::new(1.0, 0.0, 0.0), Point::new(1.0, 1.0, 0.0), Point::new(0.0, 1.0, 0.0)]);
+        assert_eq!(mesh.triangles, [[0, 1, 2], [0, 2, 3]]);
+        assert_eq!(mesh.colors[1], Vector::new(1.0, 0.0, 0.0));
+    }
+
+    fn binary_ply(format: &str, to_bytes: fn(f32) -> [u8; 4], int_to_bytes: fn(i32) -> [u8; 4]) -> Vec<u8> {
+        let mut data = PLY_HEADER.replace("{format}", format).into_bytes();
+        for (x, y, red) in [(0.0, 0.0, 0), (1.0, 0.0, 255), (1.0, 1.0, 0), (0.0, 1.0, 0)] {
+            for coord in [x, y, 0.0] {
+                data.extend(to_bytes(coord));
+            }
+            data.extend([red, 0, 0]);
+        }
+        data.push(4);
+        for i in 0..4 {
+            data.extend(int_to_bytes(i));
+        }
+        data
+    }
+
+    #[test]
+    fn ply_formats() {
+        let ascii = PLY_HEADER.replace("{format}", "ascii") + "0 0 0 0 0 0\n1 0 0 255 0 0\n1 1 0 0 0 0\n0 1 0 0 0 0\n4 0 1 2 3\n";
+        check_square(&Mesh::from_ply(ascii.as_bytes()).unwrap());
+        check_square(&Mesh::from_ply(&binary_ply("binary_little_endian", f32::to_le_bytes, i32::to_le_bytes)).unwrap());
+        check_square(&Mesh::from_ply(&binary_ply("binary_big_endian", f32::to_be_bytes, i32::to_be_bytes)).unwrap());
+    }
+
+    #[test]
+    fn bad_ply() {
+        let no_faces = "ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nelement face 0\nproperty list uchar int vertex_indices\nend_header\n1\n";
+        assert!(Mesh::from_ply(no_faces.as_bytes()).is_err());
+        let missing_vertex = PLY_HEADER.replace("{format}", "ascii") + "0 0 0 0 0 0\n1 0 0 255 0 0\n1 1 0 0 0 0\n0 1 0 0 0 0\n3 0 1 4\n";
+        assert!(Mesh::from_ply(missing_vertex.as_bytes()).is_err());
+        let truncated = PLY_HEADER.replace("{format}", "ascii") + "0 0 0 0 0 0\n";
+        assert!(Mesh::from_ply(truncated.as_bytes()).is_err());
+    }
+
+    fn binary_stl(header: &[u8]) -> Vec<u8> {
+        let mut data = header.to_vec();
+        data.resize(80, b' ');
+        data.extend(1u32.to_le_bytes());
+        for value in [0.0f32, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+            data.extend(value.to_le_bytes());
+        }
+        data.extend([0, 0]);
+        data
+    }
+
+    #[test]
+    fn stl_formats() {
+        let triangle = [Point::new(0.0, 0.0, 0.0), Point::new(1.0, 0.0, 0.0), Point::new(0.0, 1.0, 0.0)];
+        let ascii = "solid t\nfacet normal 0 0 1\nouter loop\nvertex 0 0 0\nvertex 1 0 0\nvertex 0 1 0\nendloop\nendfacet\nendsolid t\n";
+        assert_eq!(Mesh::from_stl(ascii.as_bytes()).unwrap().positions, triangle);
+        // Binary files often start with "solid" too, and are told apart by their size.
+        assert_eq!(Mesh::from_stl(&binary_stl(b"solid exported")).unwrap().positions, triangle);
+        let mut padded = binary_stl(b"binary");
+        padded.extend([0; 8]);
+        assert_eq!(Mesh::from_stl(&padded).unwrap().positions, triangle);
+        assert!(Mesh::from_stl(b"solid empty\nendsolid empty\n").is_err());
+        assert!(Mesh::from_stl(b"solid t\nvertex 0 0 0\nvertex 1 0 0\nendsolid t\n").is_err());
+    }
+}
@@ -7,9 +7,9 @@ use std::sync::mpsc::{channel};
 use std::sync::Arc;
 use std::time::{Instant};
 use cgmath::ElementWise;
-use fltk::app::{Sender};
 use rand::{Rng, thread_rng};
 use crate::acceleration_structure::AccelerationStructure;
+use crate::camera::Eye;
 use crate::transform::Vector;
 
 pub type Pixel = [f64; 3];
@@ -20,6 +20,17 @@ pub enum RenderMessages{
     FinishRender(usize, usize, Option<Vec<Pixel>>),
 }
 
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StereoLayout {
+    Mono,
+    LeftEye,
+    RightEye,
+    // Left eye in the top half of the frame, right eye in the bottom half.
+    TopBottom,
+    // Left eye in the left half of the frame, right eye in the right half.
+    SideBySide,
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct RenderSettings{
     pub width: usize,
@@ -28,6 +39,7 @@ pub struct RenderSettings{
     pub bounces: usize,
     pub tile_size: usize,
     pub denoise: bool,
+    pub stereo: StereoLayout,
 }
 
 #[derive(Clone)]
@@ -48,7 +60,7 @@ impl PathTracer {
         }
     }
     
-    pub fn render(&mut self, sender: Sender<RenderMessages>) {
+    pub fn render<F: FnMut(RenderMessages)>(&mut self, mut send: F) {
         let render_start = Instant::now();
         self.image.resize(self.render_settings.width * self.render_settings.height, [0.0; 3]);
         
@@ -95,7 +107,7 @@ impl PathTracer {
             
             let is_done = finished == created;
 
-            sender.send(RenderMessages::UpdateRender(x, y, self.render_settings.tile_size, pixels));
+            send(RenderMessages::UpdateRender(x, y, self.render_settings.tile_size, pixels));
             
             println!("{}", finished);
             if is_done {
@@ -123,13 +135,13 @@ impl PathTracer {
                         }
                     }
                     
-                    sender.send(RenderMessages::FinishRender(
+                    send(RenderMessages::FinishRender(
                         self.render_settings.width, 
                         self.render_settings.height, 
                         Some(self.image.clone())
                     ));
                 } else {
-                    sender.send(RenderMessages::FinishRender(
+                    send(RenderMessages::FinishRender(
                         self.render_settings.width, 
                         self.render_settings.height, 
                         None
@@ -143,6 +155,10 @@ impl PathTracer {
         }
     }
     
+    pub fn get_image(&self) -> &[Pixel] {
+        &self.image
+    }
+    
     fn set_pixel(&mut self, x: usize, y: usize, pixel: Pixel) {
         if x < self.render_settings.width && y < self.render_settings.height {
             self.image[y * self.render_settings.width + x] = pixel;
@@ -156,18 +172,22 @@ fn render_region(acceleration_structure: Arc<AccelerationStructure>, x: usize, y
 
     let mut rng = thread_rng();
     
-    let aspect = render_settings.width as f64 / render_settings.height as f64;
-    
     for tx in 0..render_settings.tile_size {
         for ty in 0..render_settings.tile_size {
+            let (eye, view_x, view_y, view_width, view_height) = render_settings.stereo.get_view(
+                x + tx, 
+                y + ty, 
+                render_settings.width, 
+                render_settings.height
+            );
+            let aspect = view_width as f64 / view_height as f64;
+            
             let mut col = Vector::new(0.0, 0.0, 0.0);
             for _i in 0..render_settings.samples {
-                let x = x + tx;
-                let y = y + ty;
-                let x_coord = (((x as f64 + rng.gen::<f64>()) / render_settings.width as f64) * 2.0 - 1.0) * aspect;
-                let y_coord = (1.0 - (y as f64 + rng.gen::<f64>()) / render_settings.height as f64) * 2.0 - 1.0;
+                let x_coord = (((view_x as f64 + rng.gen::<f64>()) / view_width as f64) * 2.0 - 1.0) * aspect;
+                let y_coord = (1.0 - (view_y as f64 + rng.gen::<f64>()) / view_height as f64) * 2.0 - 1.0;
                 
-                let lighting = acceleration_structure.trace_pixel(x_coord, y_coord, render_settings.bounces);
+                let lighting = acceleration_structure.trace_pixel(x_coord, y_coord, aspect, eye, render_settings.bounces);
                 col.add_assign_element_wise(lighting);
             }
             col.div_assign(render_settings.samples as f64);
@@ -177,4 +197,44 @@ fn render_region(acceleration_structure: Arc<AccelerationStructure>, x: usize, y
     
     println!("Finished rendering region: ({}, {})", x, y);
     (x, y, result)
-}
\ No newline at end of file
+}
+
+impl StereoLayout {
+    // Returns which eye a pixel belongs to, along with its position and the size of that eye's view.
+    fn get_view(&self, x: usize, y: usize, width: usize, height: usize) -> (Eye, usize, usize, usize, usize) {
+        match self {
+            StereoLayout::Mono => (Eye::Center, x, y, width, height),
+            StereoLayout::LeftEye => (Eye::Left, x, y, width, height),
+            StereoLayout::RightEye => (Eye::Right, x, y, width, height),
+            StereoLayout::TopBottom => {
+                // The right eye takes the extra row of an odd height.
+                let half_height = (height / 2).max(1);
+                if y < half_height {
+                    (Eye::Left, x, y, width, half_height)
+                } else {
+                    (Eye::Right, x, y - half_height, width, height - half_height)
+                }
+            }
+            StereoLayout::SideBySide => {
+                let half_width = (width / 2).max(1);
+                if x < half_width {
+                    (Eye::Left, x, y, half_width, height)
+                } else {
+                    (Eye::Right, x - half_width, y, width - half_width, height)
+                }
+            }
+        }
+    }
+}
+
+// Converts a rendered image to 8 bit RGB for display or saving.
+pub fn to_rgb8(image: &[Pixel]) -> Vec<u8> {
+    let mut data: Vec<u8> = Vec::new();
+    data.resize(image.len() * 3, 0);
+    for i in 0..image.len() {
+        for j in 0..3 {
+            data[i * 3 + j] = (image[i][j] * 255.0) as u8;
+        }
+    }
+    data
+}
@@ -0,0 +1,567 @@
+use std::collections::{HashMap, VecDeque};
+use std::f64::consts::TAU;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use cgmath::{Deg, EuclideanSpace, InnerSpace, Matrix4, One, SquareMatrix, Vector2, Vector4, Zero};
+use crate::camera::{Camera, FieldOfView};
+use crate::material::PhysicalMaterial;
+use crate::mesh::Mesh;
+use crate::renderable::{Renderable, RenderShape};
+use crate::scene::{Prototype, Scene, Sky};
+use crate::scene_error::SceneError;
+use crate::transform::{Point, Quat, Transform, Vector};
+
+// Reflectance of pbrt's diffuse materials when none is given.
+const DEFAULT_REFLECTANCE: f64 = 0.5;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Text(String),
+    Number(f64),
+    Bool(bool),
+    Open,
+    Close,
+}
+
+// Where a token starts, with the file it's in, as included files are read into the same list of tokens.
+#[derive(Debug, Clone)]
+struct Location {
+    file: Arc<Path>,
+    line: usize,
+    column: usize,
+}
+
+// A parameter such as `"float radius" [ 2 ]`, with its type and name split apart.
+#[derive(Debug, Clone)]
+struct Param {
+    name: String,
+    values: Vec<Token>,
+}
+
+// Material, transform, and area light in effect, saved and restored by attribute blocks.
+#[derive(Debug, Clone)]
+struct GraphicsState {
+    transform: Matrix4<f64>,
+    material: PhysicalMaterial,
+    // Emitted color and brightness of the shapes that follow.
+    area_light: Option<(Vector, f64)>,
+}
+
+// Loads a practical subset of pbrt-v3 and pbrt-v4 scene files: perspective cameras, spheres,
+// triangle, bilinear, and PLY meshes, object instances, diffuse, conductor, and dielectric materials,
+// and area, infinite, and distant lights. Other directives, lights, and shapes are skipped with a warning.
+// pbrt space is left handed like yarpt's, so no mirroring is needed.
+pub fn load<P: AsRef<Path>>(path: P, warnings: &mut Vec<SceneError>) -> Result<Scene, SceneError> {
+    let path = path.as_ref();
+    // Included files and meshes are found relative to the main scene file.
+    let scene_dir = path.parent().unwrap_or(Path::new(""));
+    let text = fs::read_to_string(path).map_err(|err| SceneError::in_file(path, format!("Failed to read pbrt file ({err})")))?;
+    let mut tokens = tokenize(&text, &Arc::from(path))?;
+
+    let mut scene = Scene::new(Camera::default(), Sky::black());
+    let mut state = GraphicsState {
+        transform: Matrix4::identity(),
+        material: diffuse_material(Vector::new(DEFAULT_REFLECTANCE, DEFAULT_REFLECTANCE, DEFAULT_REFLECTANCE)),
+        area_light: None,
+    };
+    let mut attribute_stack: Vec<GraphicsState> = Vec::new();
+    let mut transform_stack: Vec<Matrix4<f64>> = Vec::new();
+    let mut named_materials: HashMap<String, PhysicalMaterial> = HashMap::new();
+    let mut coordinate_systems: HashMap<String, Matrix4<f64>> = HashMap::new();
+    // The object being defined, which becomes a prototype when it ends.
+    let mut object: Option<Prototype> = None;
+    // The field of view is applied once the film is known, which may come after the camera.
+    let mut fov = None;
+    let mut resolution = Vector2::new(640.0, 480.0);
+
+    while let Some((token, location)) = tokens.pop_front() {
+        let Token::Word(directive) = token else {
+            return Err(location.error("Expected a directive, but found", &token.to_string()));
+        };
+        let mut args = Vec::new();
+        while tokens.front().is_some_and(|(token, _)| !matches!(token, Token::Word(_))) {
+            args.push(tokens.pop_front().unwrap());
+        }
+        let numbers = args.iter().filter_map(|(arg, _)| if let Token::Number(n) = arg { Some(*n) } else { None }).collect::<Vec<_>>();
+        let (kind, params) = parse_params(&args)?;
+
+        match directive.as_str() {
+            "Include" | "Import" => {
+                let included_path = scene_dir.join(&kind);
+                let text = fs::read_to_string(&included_path).map_err(|err| location.error(format!("Failed to read included file ({err})"), &kind))?;
+                let included = tokenize(&text, &Arc::from(included_path))?;
+                for token in included.into_iter().rev() {
+                    tokens.push_front(token);
+                }
+            }
+            "Identity" => state.transform = Matrix4::identity(),
+            "Translate" if numbers.len() == 3 => {
+                state.transform = state.transform * Matrix4::from_translation(Vector::new(numbers[0], numbers[1], numbers[2]));
+            }
+            "Scale" if numbers.len() == 3 => {
+                state.transform = state.transform * Matrix4::from_nonuniform_scale(numbers[0], numbers[1], numbers[2]);
+            }
+            "Rotate" if numbers.len() == 4 => {
+                let axis = Vector::new(numbers[1], numbers[2], numbers[3]).normalize();
+                state.transform = state.transform * Matrix4::from_axis_angle(axis, Deg(numbers[0]));
+            }
+            "LookAt" if numbers.len() == 9 => {
+                let eye = Point::new(numbers[0], numbers[1], numbers[2]);
+                let target = Point::new(numbers[3], numbers[4], numbers[5]);
+                let up = Vector::new(numbers[6], numbers[7], numbers[8]);
+                state.transform = state.transform * look_at(eye, target, up);
+            }
+            // Matrices are listed a column at a time, as cgmath builds them.
+            "Transform" if numbers.len() == 16 => state.transform = matrix_from_values(&numbers),
+            "ConcatTransform" if numbers.len() == 16 => state.transform = state.transform * matrix_from_values(&numbers),
+            "Translate" | "Scale" | "Rotate" | "LookAt" | "Transform" | "ConcatTransform" => {
+                warnings.push(location.error(format!("Skipped a transform with {} values", numbers.len()), &directive));
+            }
+            "CoordinateSystem" => {
+                coordinate_systems.insert(kind, state.transform);
+            }
+            "CoordSysTransform" => {
+                state.transform = *coordinate_systems.get(&kind).ok_or_else(|| {
+                    location.error("Unknown coordinate system", &kind).suggest(coordinate_systems.keys().map(String::as_str))
+                })?;
+            }
+            "TransformBegin" => transform_stack.push(state.transform),
+            "TransformEnd" => {
+                state.transform = transform_stack.pop().ok_or_else(|| location.error("No TransformBegin to end", &directive))?;
+            }
+            "AttributeBegin" => attribute_stack.push(state.clone()),
+            "AttributeEnd" => {
+                state = attribute_stack.pop().ok_or_else(|| location.error("No AttributeBegin to end", &directive))?;
+            }
+            "Film" => {
+                let width = get_float(&params, "xresolution").unwrap_or(resolution.x);
+                let height = get_float(&params, "yresolution").unwrap_or(resolution.y);
+                resolution = Vector2::new(width, height);
+            }
+            "Camera" => {
+                // The current transform goes from world to camera space.
+                let camera_to_world = state.transform.invert().unwrap_or(Matrix4::identity());
+                coordinate_systems.insert("camera".to_string(), camera_to_world);
+                let position = Point::from_homogeneous(camera_to_world * Vector4::unit_w());
+                let mut transform = Transform::new(position, Quat::one(), Vector::new(1.0, 1.0, 1.0));
+                transform.look_at(position + (camera_to_world * Vector4::unit_z()).truncate(), (camera_to_world * Vector4::unit_y()).truncate());
+                scene.camera.transform = transform;
+                // The rotation can't hold a mirroring scale, such as the `Scale -1 1 1` many exporters start with.
+                scene.camera.mirror = camera_to_world.determinant() < 0.0;
+                fov = Some(get_float(&params, "fov").unwrap_or(90.0));
+                // yarpt's aperture radius is twice its f-stop.
+                scene.camera.f_stop = get_float(&params, "lensradius").unwrap_or(0.0) / 2.0;
+                scene.camera.focal_plane = get_float(&params, "focaldistance").unwrap_or(1e6);
+            }
+            "WorldBegin" => {
+                state.transform = Matrix4::identity();
+                coordinate_systems.insert("world".to_string(), state.transform);
+            }
+            // pbrt-v3 scenes end with WorldEnd, which has nothing left to do.
+            "WorldEnd" => {}
+            "Material" => state.material = parse_material(&kind, &params, &location, warnings),
+            "MakeNamedMaterial" => {
+                let material_type = get_string(&params, "type").unwrap_or_default();
+                named_materials.insert(kind, parse_material(&material_type, &params, &location, warnings));
+            }
+            "NamedMaterial" => {
+                state.material = *named_materials.get(&kind).ok_or_else(|| {
+                    location.error("Unknown material", &kind).suggest(named_materials.keys().map(String::as_str))
+                })?;
+            }
+            "AreaLightSource" => {
+                let color = get_rgb(&params, "L").unwrap_or(Vector::new(1.0, 1.0, 1.0));
+                state.area_light = Some((color, get_float(&params, "scale").unwrap_or(1.0)));
+            }
+            "LightSource" => {
+                let color = get_rgb(&params, "L").unwrap_or(Vector::new(1.0, 1.0, 1.0)) * get_float(&params, "scale").unwrap_or(1.0);
+                match kind.as_str() {
+                    // Environment maps aren't read, so infinite lights are a single color all around.
+                    "infinite" => scene.sky = Sky::new(Vector::unit_y(), 0.0, Vector::zero(), color, color, color),
+                    // The sun's irradiance is TAU times its color.
+                    "distant" => {
+                        let from = get_point(&params, "from").unwrap_or(Point::origin());
+                        let to = get_point(&params, "to").unwrap_or(Point::new(0.0, 0.0, 1.0));
+                        let sun_dir = (state.transform * (from - to).extend(0.0)).truncate();
+                        scene.sky.set_sun_dir(sun_dir);
+                        scene.sky.sun_color = color / TAU;
+                        if scene.sky.sun_size <= 0.0 {
+                            scene.sky.sun_size = 0.01;
+                        }
+                    }
+                    _ => warnings.push(location.error("Skipped an unsupported light", &kind)),
+                }
+            }
+            "Shape" => {
+                let Some(shape) = parse_shape(&kind, &params, scene_dir, &location)? else {
+                    warnings.push(location.error("Skipped an unsupported shape", &kind));
+                    continue;
+                };
+                let mut material = state.material;
+                // Emission is a multiple of the diffuse color, so area lights take their emitted color as their diffuse.
+                if let Some((color, scale)) = state.area_light {
+                    let strength = color.x.max(color.y).max(color.z);
+                    if strength > 0.0 {
+                        material = PhysicalMaterial::new(color / strength, 1.0, 0.0, strength * scale);
+                    }
+                }
+                let renderable = Renderable::new(Transform::from_matrix(state.transform), material, shape);
+                match &mut object {
+                    Some(prototype) => prototype.objects.push(renderable),
+                    None => scene.add_object(renderable),
+                }
+            }
+            "ObjectBegin" => {
+                if object.is_some() {
+                    return Err(location.error("Objects can't be nested, but found", &kind));
+                }
+                attribute_stack.push(state.clone());
+                object = Some(Prototype::new(kind));
+            }
+            "ObjectEnd" => {
+                let prototype = object.take().ok_or_else(|| location.error("No ObjectBegin to end", &directive))?;
+                scene.add_prototype(prototype);
+                state = attribute_stack.pop().ok_or_else(|| location.error("No AttributeBegin to end", &directive))?;
+            }
+            "ObjectInstance" => {
+                if object.is_some() {
+                    return Err(location.error("Objects can't contain instances, but found", &kind));
+                }
+                let ind = scene.find_prototype(&kind).ok_or_else(|| {
+                    let names = (0..scene.get_prototype_count()).map(|ind| scene.get_prototype(ind).name.as_str());
+                    location.error("Unknown object", &kind).suggest(names)
+                })?;
+                let (min, max) = scene.get_prototype(ind).get_bounds();
+                scene.add_object(Renderable::new(Transform::from_matrix(state.transform), PhysicalMaterial::default(), RenderShape::Instance(ind, min, max)));
+            }
+            _ => warnings.push(location.error("Skipped an unsupported directive", &directive)),
+        }
+    }
+
+    // pbrt's field of view spans the shorter side of the image.
+    scene.camera.field_of_view = fov.map(|fov| if resolution.x >= resolution.y {
+        FieldOfView::Vertical(fov)
+    } else {
+        FieldOfView::Horizontal(fov)
+    });
+    Ok(scene)
+}
+
+// Diffuse, conductor, and dielectric materials, with the pbrt-v3 names of each.
+// yarpt has no refraction, so dielectrics become clear mirrors, and textures aren't read.
+// Other materials become diffuse. Both substitutions are added to the warnings.
+fn parse_material(material_type: &str, params: &[Param], location: &Location, warnings: &mut Vec<SceneError>) -> PhysicalMaterial {
+    let roughness = get_float(params, "roughness")
+        .or_else(|| Some((get_float(params, "uroughness")? + get_float(params, "vroughness")?) / 2.0))
+        .unwrap_or(0.0)
+        .clamp(0.0, 1.0);
+    match material_type {
+        "conductor" | "metal" => {
+            let color = get_rgb(params, "reflectance").unwrap_or_else(|| metal_color(get_string(params, "eta").as_deref()));
+            PhysicalMaterial::new(color, roughness, 1.0, 0.0)
+        }
+        "dielectric" | "thindielectric" | "glass" => {
+            warnings.push(location.error("Substituted a clear mirror for the refractive material", material_type));
+            PhysicalMaterial::new(Vector::new(1.0, 1.0, 1.0), roughness, 1.0, 0.0)
+        }
+        "coateddiffuse" | "plastic" => {
+            let color = get_rgb(params, "reflectance").or_else(|| get_rgb(params, "Kd")).unwrap_or(Vector::new(DEFAULT_REFLECTANCE, DEFAULT_REFLECTANCE, DEFAULT_REFLECTANCE));
+            PhysicalMaterial::new(color, roughness.max(0.5), 0.0, 0.0)
+        }
+        _ => {
+            if !matches!(material_type, "diffuse" | "matte") {
+                warnings.push(location.error("Substituted a diffuse material for the unsupported material", material_type));
+            }
+            let color = get_rgb(params, "reflectance").or_else(|| get_rgb(params, "Kd")).unwrap_or(Vector::new(DEFAULT_REFLECTANCE, DEFAULT_REFLECTANCE, DEFAULT_REFLECTANCE));
+            diffuse_material(color)
+        }
+    }
+}
+
+fn diffuse_material(color: Vector) -> PhysicalMaterial {
+    PhysicalMaterial::new(color, 1.0, 0.0, 0.0)
+}
+
+// Colors of the named metal spectra pbrt uses for conductors, which default to copper.
+fn metal_color(eta: Option<&str>) -> Vector {
+    match eta {
+        Some(name) if name.contains("Au") => Vector::new(1.0, 0.78, 0.34),
+        Some(name) if name.contains("Ag") => Vector::new(0.97, 0.96, 0.91),
+        Some(name) if name.contains("Al") => Vector::new(0.91, 0.92, 0.92),
+        _ => Vector::new(0.95, 0.64, 0.54),
+    }
+}
+
+// Spheres and meshes. pbrt spheres can be cut open, but are always read whole.
+fn parse_shape(shape_type: &str, params: &[Param], scene_dir: &Path, location: &Location) -> Result<Option<RenderShape>, SceneError> {
+    let shape = match shape_type {
+        "sphere" => RenderShape::Sphere(get_float(params, "radius").unwrap_or(1.0)),
+        "trianglemesh" | "bilinearmesh" => {
+            let positions = get_floats(params, "P").unwrap_or_default()
+                .chunks_exact(3)
+                .map(|p| Point::new(p[0], p[1], p[2]))
+                .collect::<Vec<_>>();
+            let normals = get_floats(params, "N").unwrap_or_default()
+                .chunks_exact(3)
+                .map(|n| Vector::new(n[0], n[1], n[2]))
+                .collect::<Vec<_>>();
+            let uvs = get_floats(params, "uv").or_else(|| get_floats(params, "st")).unwrap_or_default()
+                .chunks_exact(2)
+                .map(|uv| Vector2::new(uv[0], uv[1]))
+                .collect::<Vec<_>>();
+            let corners = if shape_type == "bilinearmesh" { 4 } else { 3 };
+            let indices = get_floats(params, "indices")
+                .map(|indices| indices.into_iter().map(|i| i as usize).collect::<Vec<_>>())
+                .unwrap_or_else(|| (0..positions.len()).collect());
+            // Bilinear patches list their corners in rows, so each is split into two triangles across them.
+            let triangles = indices.chunks_exact(corners).flat_map(|face| match face {
+                [a, b, c] => vec![[*a, *b, *c]],
+                [a, b, c, d] => vec![[*a, *b, *d], [*a, *d, *c]],
+                _ => Vec::new(),
+            }).collect::<Vec<_>>();
+            if triangles.iter().flatten().any(|i| *i >= positions.len()) {
+                return Err(location.error(format!("{shape_type} refers to a missing vertex"), ""));
+            }
+            let normals = if normals.len() == positions.len() { normals } else { Vec::new() };
+            let uvs = if uvs.len() == positions.len() { uvs } else { Vec::new() };
+            RenderShape::Mesh(Arc::new(Mesh::new(positions, normals, uvs, Vec::new(), triangles)))
+        }
+        "plymesh" => {
+            let file = get_string(params, "filename").ok_or_else(|| location.error("Missing the filename of", shape_type))?;
+            let mesh = Mesh::load(scene_dir.join(&file)).map_err(|err| location.error(format!("Failed to load mesh ({err})"), &file))?;
+            RenderShape::Mesh(Arc::new(mesh))
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(shape))
+}
+
+// Splits the leading type or name from the parameter list that follows it.
+// Leading numbers, such as a transform's, may be bracketed and are read separately.
+fn parse_params(args: &[(Token, Location)]) -> Result<(String, Vec<Param>), SceneError> {
+    let mut args = args.iter().skip_while(|(arg, _)| matches!(arg, Token::Number(_) | Token::Open | Token::Close)).peekable();
+    let kind = match args.peek() {
+        Some((Token::Text(kind), _)) if kind.split_whitespace().count() == 1 => {
+            args.next();
+            kind.clone()
+        }
+        _ => String::new(),
+    };
+
+    let mut params = Vec::new();
+    while let Some((arg, location)) = args.next() {
+        let Token::Text(declaration) = arg else {
+            return Err(location.error("Expected a parameter, but found", &arg.to_string()));
+        };
+        let name = declaration.split_whitespace().last().unwrap_or_default().to_string();
+        let values = match args.next() {
+            Some((Token::Open, _)) => args.by_ref().map(|(arg, _)| arg).take_while(|arg| **arg != Token::Close).cloned().collect(),
+            Some((value, _)) => vec![value.clone()],
+            None => Vec::new(),
+        };
+        params.push(Param { name, values });
+    }
+    Ok((kind, params))
+}
+
+// Comments and strings end with their line, so the file is read a line at a time.
+fn tokenize(text: &str, file: &Arc<Path>) -> Result<VecDeque<(Token, Location)>, SceneError> {
+    let mut tokens = VecDeque::new();
+    for (line_ind, line) in text.lines().enumerate() {
+        let mut chars = line.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            let location = Location { file: file.clone(), line: line_ind + 1, column: line[..start].chars().count() + 1 };
+            let token = match c {
+                '#' => break,
+                '[' => Token::Open,
+                ']' => Token::Close,
+                '"' => Token::Text(chars.by_ref().map(|(_, c)| c).take_while(|c| *c != '"').collect()),
+                c if c.is_whitespace() => continue,
+                c => {
+                    let mut word = c.to_string();
+                    while let Some((_, c)) = chars.next_if(|(_, c)| !c.is_whitespace() && !matches!(c, '[' | ']' | '"' | '#')) {
+                        word.push(c);
+                    }
+                    match word.as_str() {
+                        "true" => Token::Bool(true),
+                        "false" => Token::Bool(false),
+                        _ if c.is_ascii_alphabetic() => Token::Word(word),
+                        _ => Token::Number(word.parse().map_err(|_| location.error("Expected a number, but found", &word))?),
+                    }
+                }
+            };
+            tokens.push_back((token, location));
+        }
+    }
+    Ok(tokens)
+}
+
+// Same as pbrt's LookAt, from world to camera space.
+fn look_at(eye: Point, target: Point, up: Vector) -> Matrix4<f64> {
+    let forward = (target - eye).normalize();
+    let right = up.normalize().cross(forward).normalize();
+    let new_up = forward.cross(right);
+    let camera_to_world = Matrix4::from_cols(right.extend(0.0), new_up.extend(0.0), forward.extend(0.0), eye.to_homogeneous());
+    camera_to_world.invert().unwrap_or(Matrix4::identity())
+}
+
+fn matrix_from_values(values: &[f64]) -> Matrix4<f64> {
+    let column = |c: usize| Vector4::new(values[c * 4], values[c * 4 + 1], values[c * 4 + 2], values[c * 4 + 3]);
+    Matrix4::from_cols(column(0), column(1), column(2), column(3))
+}
+
+fn get_floats(params: &[Param], name: &str) -> Option<Vec<f64>> {
+    let param = params.iter().find(|param| param.name == name)?;
+    Some(param.values.iter().filter_map(|value| if let Token::Number(n) = value { Some(*n) } else { None }).collect())
+}
+
+fn get_float(params: &[Param], name: &str) -> Option<f64> {
+    get_floats(params, name)?.first().copied()
+}
+
+// Colors given as spectra or blackbodies aren't read.
+fn get_rgb(params: &[Param], name: &str) -> Option<Vector> {
+    match get_floats(params, name)?.as_slice() {
+        [r, g, b] => Some(Vector::new(*r, *g, *b)),
+        _ => None,
+    }
+}
+
+fn get_point(params: &[Param], name: &str) -> Option<Point> {
+    get_rgb(params, name).map(Point::from_vec)
+}
+
+fn get_string(params: &[Param], name: &str) -> Option<String> {
+    let param = params.iter().find(|param| param.name == name)?;
+    match param.values.first()? {
+        Token::Text(text) => Some(text.clone()),
+        _ => None,
+    }
+}
+
+// Tokens are shown as they would be written in the file.
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "{word}"),
+            Token::Text(text) => write!(f, "\"{text}\""),
+            Token::Number(number) => write!(f, "{number}"),
+            Token::Bool(value) => write!(f, "{value}"),
+            Token::Open => write!(f, "["),
+            Token::Close => write!(f, "]"),
+        }
+    }
+}
+
+impl Location {
+    fn error(&self, message: impl Into<String>, token: &str) -> SceneError {
+        SceneError {
+            file: self.file.to_path_buf(),
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+            token: token.to_string(),
+            suggestion: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Eye;
+
+    fn load_text(name: &str, text: &str) -> Result<Scene, SceneError> {
+        let path = std::env::temp_dir().join(format!("yarpt_test_{name}.pbrt"));
+        fs::write(&path, text).unwrap();
+        let scene = load(&path, &mut Vec::new());
+        fs::remove_file(&path).unwrap();
+        scene
+    }
+
+    fn tokens(text: &str) -> Vec<(Token, Location)> {
+        tokenize(text, &Arc::from(Path::new("test.pbrt"))).unwrap().into()
+    }
+
+    #[test]
+    fn tokens_and_locations() {
+        let tokens = tokens("Shape \"sphere\" # a comment\n  \"float radius\" [2.5 -1e2] \"bool on\" true");
+        let expected = [
+            Token::Word("Shape".to_string()),
+            Token::Text("sphere".to_string()),
+            Token::Text("float radius".to_string()),
+            Token::Open,
+            Token::Number(2.5),
+            Token::Number(-100.0),
+            Token::Close,
+            Token::Text("bool on".to_string()),
+            Token::Bool(true),
+        ];
+        assert_eq!(tokens.iter().map(|(token, _)| token.clone()).collect::<Vec<_>>(), expected);
+        let (_, location) = &tokens[2];
+        assert_eq!((location.line, location.column), (2, 3));
+        assert!(tokenize("Translate 1 2.3.4 5", &Arc::from(Path::new("test.pbrt"))).is_err());
+    }
+
+    #[test]
+    fn params() {
+        let (kind, params) = parse_params(&tokens("\"trianglemesh\" \"point3 P\" [0 0 0 1 0 0 0 1 0] \"string name\" \"mesh\"")).unwrap();
+        assert_eq!(kind, "trianglemesh");
+        assert_eq!(get_floats(&params, "P").unwrap().len(), 9);
+        assert_eq!(get_string(&params, "name").as_deref(), Some("mesh"));
+        // A transform's numbers come before any parameters.
+        let (kind, params) = parse_params(&tokens("[1 2 3] \"float fov\" 45")).unwrap();
+        assert_eq!(kind, "");
+        assert_eq!(get_float(&params, "fov"), Some(45.0));
+        assert!(parse_params(&tokens("\"sphere\" \"float radius\" 1 2")).is_err());
+    }
+
+    #[test]
+    fn scene() {
+        let scene = load_text("pbrt_scene", "
+            Scale -1 1 1
+            LookAt 0 0 0  0 0 1  0 1 0
+            Camera \"perspective\" \"float fov\" 45
+            WorldBegin
+            AttributeBegin
+                Translate 1 2 3
+                Shape \"sphere\" \"float radius\" 2
+            AttributeEnd
+            ObjectBegin \"ball\"
+                Shape \"sphere\"
+            ObjectEnd
+            Translate 0 0 5
+            ObjectInstance \"ball\"
+        ").unwrap();
+        assert_eq!(scene.get_object_count(), 2);
+        assert_eq!(scene.get_prototype_count(), 1);
+        assert!(matches!(scene.get_object(0).shape, RenderShape::Sphere(radius) if radius == 2.0));
+        assert_eq!(scene.get_object(0).transform.get_position(), Point::new(1.0, 2.0, 3.0));
+        assert_eq!(scene.get_object(1).transform.get_position(), Point::new(0.0, 0.0, 5.0));
+        // The image is mirrored, so its right side sees -x.
+        assert!(scene.camera.mirror);
+        let (_, ray_dir) = scene.camera.get_ray(Vector2::new(0.5, 0.0), 1.0, Eye::Center, 0.0).unwrap();
+        assert!(ray_dir.x < 0.0 && ray_dir.z > 0.0);
+    }
+
+    #[test]
+    fn unbalanced_blocks() {
+        let bad = [
+            "CoordSysTransform \"camra\"",
+            "TransformEnd",
+            "AttributeBegin AttributeEnd AttributeEnd",
+            "ObjectEnd",
+            "ObjectBegin \"a\" ObjectBegin \"b\" ObjectEnd ObjectEnd",
+            "ObjectBegin \"a\" ObjectEnd ObjectBegin \"b\" ObjectInstance \"a\" ObjectEnd",
+        ];
+        for (ind, text) in bad.iter().enumerate() {
+            let text = format!("Camera \"perspective\" WorldBegin {text}");
+            assert!(load_text(&format!("pbrt_bad_{ind}"), &text).is_err(), "{text}");
+        }
+        let err = load_text("pbrt_bad_name", "Camera \"perspective\" CoordSysTransform \"camra\"").unwrap_err();
+        assert_eq!(err.suggestion.as_deref(), Some("camera"));
+    }
+}
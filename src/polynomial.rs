@@ -0,0 +1,173 @@
+use std::f64::consts::TAU;
+
+const COEFFICIENT_EPSILON: f64 = 1e-12;
+const NEWTON_STEPS: usize = 4;
+
+// Real roots of a*x^2 + b*x + c, smallest first. Falls back to the linear solution when a is zero.
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    if a.abs() < COEFFICIENT_EPSILON {
+        if b.abs() < COEFFICIENT_EPSILON {
+            return Vec::new();
+        }
+        return vec![-c / b];
+    }
+
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return Vec::new();
+    }
+
+    // Avoids subtracting nearly equal numbers when b is much larger than the discriminant.
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    let mut roots = if q == 0.0 {
+        vec![0.0]
+    } else {
+        vec![q / a, c / q]
+    };
+    roots.sort_by(f64::total_cmp);
+    roots
+}
+
+// Real roots of x^3 + a*x^2 + b*x + c, in no particular order.
+pub fn solve_cubic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    // Substituting x = y - a/3 gives y^3 + p*y + q.
+    let p = b - a * a / 3.0;
+    let q = 2.0 * a * a * a / 27.0 - a * b / 3.0 + c;
+    let offset = -a / 3.0;
+
+    let discriminant = q * q / 4.0 + p * p * p / 27.0;
+    if discriminant > 0.0 {
+        let sqrt_disc = discriminant.sqrt();
+        vec![(-q / 2.0 + sqrt_disc).cbrt() + (-q / 2.0 - sqrt_disc).cbrt() + offset]
+    } else if p.abs() < COEFFICIENT_EPSILON {
+        vec![(-q).cbrt() + offset]
+    } else {
+        // Three real roots, found with the trigonometric method.
+        let r = (-p / 3.0).sqrt();
+        let phi = (-q / (2.0 * r * r * r)).clamp(-1.0, 1.0).acos();
+        (0..3).map(|k| 2.0 * r * ((phi + TAU * k as f64) / 3.0).cos() + offset).collect()
+    }
+}
+
+// Real roots of a*x^4 + b*x^3 + c*x^2 + d*x + e, smallest first, using Ferrari's method.
+// The roots are refined with Newton's method, since the closed form loses precision.
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64, e: f64) -> Vec<f64> {
+    if a.abs() < COEFFICIENT_EPSILON {
+        let mut roots = solve_cubic_general(b, c, d, e);
+        roots.sort_by(f64::total_cmp);
+        return roots;
+    }
+    let (b, c, d, e) = (b / a, c / a, d / a, e / a);
+
+    // Substituting x = y - b/4 gives y^4 + p*y^2 + q*y + r.
+    let p = c - 3.0 * b * b / 8.0;
+    let q = d - b * c / 2.0 + b * b * b / 8.0;
+    let r = e - b * d / 4.0 + b * b * c / 16.0 - 3.0 * b * b * b * b / 256.0;
+    let offset = -b / 4.0;
+
+    let mut roots = Vec::new();
+    if q.abs() < COEFFICIENT_EPSILON {
+        // Quadratic in y^2.
+        for z in solve_quadratic(1.0, p, r) {
+            if z >= 0.0 {
+                roots.push(z.sqrt() + offset);
+                roots.push(-z.sqrt() + offset);
+            }
+        }
+    } else {
+        // Any positive root m of the resolvent cubic splits the quartic into two quadratics.
+        let m = solve_cubic(p, p * p / 4.0 - r, -q * q / 8.0).into_iter().fold(0.0, f64::max);
+        if m > 0.0 {
+            let s = (2.0 * m).sqrt();
+            roots.extend(solve_quadratic(1.0, -s, p / 2.0 + m + q / (2.0 * s)).into_iter().map(|y| y + offset));
+            roots.extend(solve_quadratic(1.0, s, p / 2.0 + m - q / (2.0 * s)).into_iter().map(|y| y + offset));
+        }
+    }
+
+    for root in &mut roots {
+        for _i in 0..NEWTON_STEPS {
+            let value = (((*root + b) * *root + c) * *root + d) * *root + e;
+            let slope = ((4.0 * *root + 3.0 * b) * *root + 2.0 * c) * *root + d;
+            if slope.abs() < COEFFICIENT_EPSILON {
+                break;
+            }
+            *root -= value / slope;
+        }
+    }
+    roots.sort_by(f64::total_cmp);
+    roots
+}
+
+fn solve_cubic_general(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+    if a.abs() < COEFFICIENT_EPSILON {
+        return solve_quadratic(b, c, d);
+    }
+    solve_cubic(b / a, c / a, d / a)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_roots(roots: &[f64], expected: &[f64], tolerance: f64) {
+        assert_eq!(roots.len(), expected.len(), "found {roots:?}, expected {expected:?}");
+        for (root, expected_root) in roots.iter().zip(expected) {
+            assert!((root - expected_root).abs() < tolerance, "found {roots:?}, expected {expected:?}");
+        }
+    }
+
+    #[test]
+    fn quadratic_roots() {
+        assert_roots(&solve_quadratic(1.0, 1.0, -6.0), &[-3.0, 2.0], 1e-12);
+        assert_roots(&solve_quadratic(2.0, -4.0, 2.0), &[1.0, 1.0], 1e-12);
+        assert_roots(&solve_quadratic(0.0, 2.0, -1.0), &[0.5], 1e-12);
+        assert_roots(&solve_quadratic(1.0, 0.0, 1.0), &[], 0.0);
+        // The small root would cancel out to zero if found with the textbook formula.
+        assert_roots(&solve_quadratic(1.0, -1e8, 1.0), &[1e-8, 1e8], 1e-16);
+    }
+
+    #[test]
+    fn cubic_roots() {
+        let mut roots = solve_cubic(-6.0, 11.0, -6.0);
+        roots.sort_by(f64::total_cmp);
+        assert_roots(&roots, &[1.0, 2.0, 3.0], 1e-9);
+        assert_roots(&solve_cubic(0.0, 0.0, -1.0), &[1.0], 1e-12);
+        assert_roots(&solve_cubic(-6.0, 12.0, -8.0), &[2.0], 1e-9);
+    }
+
+    #[test]
+    fn quartic_roots() {
+        // (x - 1)(x - 2)(x - 3)(x - 4)
+        assert_roots(&solve_quartic(1.0, -10.0, 35.0, -50.0, 24.0), &[1.0, 2.0, 3.0, 4.0], 1e-9);
+        assert_roots(&solve_quartic(-2.0, 20.0, -70.0, 100.0, -48.0), &[1.0, 2.0, 3.0, 4.0], 1e-9);
+        assert_roots(&solve_quartic(1.0, 0.0, 0.0, 0.0, 1.0), &[], 0.0);
+        // With no x^4 term, solved as a cubic.
+        assert_roots(&solve_quartic(0.0, 1.0, -6.0, 11.0, -6.0), &[1.0, 2.0, 3.0], 1e-9);
+        // Only even powers, solved as a quadratic in x^2.
+        assert_roots(&solve_quartic(1.0, 0.0, -5.0, 0.0, 4.0), &[-2.0, -1.0, 1.0, 2.0], 1e-9);
+    }
+
+    #[test]
+    fn quartic_double_roots() {
+        // (x - 1)^2 (x - 3)^2
+        let roots = solve_quartic(1.0, -8.0, 22.0, -24.0, 9.0);
+        assert!(!roots.is_empty());
+        assert!(roots.iter().all(|root| (root - 1.0).abs() < 1e-6 || (root - 3.0).abs() < 1e-6), "found {roots:?}");
+        assert!(roots.iter().any(|root| (root - 1.0).abs() < 1e-6) && roots.iter().any(|root| (root - 3.0).abs() < 1e-6), "found {roots:?}");
+    }
+
+    #[test]
+    fn quartic_near_tangent_roots() {
+        // (x^2 - 2x + 1 - 1e-6)(x - 3)(x - 5), with roots 0.999 and 1.001 close to a double root.
+        let quadratic = [1.0, -2.0, 1.0 - 1e-6];
+        let coefficients = [
+            quadratic[0],
+            quadratic[1] - 8.0 * quadratic[0],
+            quadratic[2] - 8.0 * quadratic[1] + 15.0 * quadratic[0],
+            -8.0 * quadratic[2] + 15.0 * quadratic[1],
+            15.0 * quadratic[2],
+        ];
+        let [a, b, c, d, e] = coefficients;
+        assert_roots(&solve_quartic(a, b, c, d, e), &[0.999, 1.001, 3.0, 5.0], 1e-9);
+    }
+}
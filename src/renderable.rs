@@ -1,12 +1,41 @@
-use cgmath::{ElementWise, EuclideanSpace, InnerSpace};
+use std::f64::consts::{PI, TAU};
+use cgmath::{ElementWise, EuclideanSpace, InnerSpace, Vector2, Zero};
+use std::sync::Arc;
+use image::Rgb32FImage;
+use crate::csg::Csg;
+use crate::curve::Curves;
+use crate::heightfield::Heightfield;
 use crate::material::{PhysicalMaterial};
+use crate::medium::Medium;
+use crate::mesh::Mesh;
+use crate::polynomial::{solve_quadratic, solve_quartic};
+use crate::sdf::Sdf;
+use crate::texture::sample_texture;
 use crate::transform::*;
 
-#[derive(Debug, Copy, Clone)]
+// Minimum distance to a flat shape, so rays leaving its surface don't hit it again.
+const FLAT_EPSILON: f64 = 1e-9;
+// The same for curved shapes, whose roots are less precise.
+const CROSSING_EPSILON: f64 = 1e-6;
+// Distance from a surface at which sphere tracing counts as a hit, and the most steps it takes.
+const SDF_EPSILON: f64 = 1e-8;
+const MAX_SDF_STEPS: usize = 256;
+
+#[derive(Debug, Clone)]
 pub struct Renderable {
     pub transform: Transform,
     pub material: PhysicalMaterial,
+    // Name of the material in the scene file, so animations can find the objects using it.
+    pub material_name: Option<String>,
     pub shape: RenderShape,
+    pub medium: Option<Medium>,
+    pub name: Option<String>,
+    // Further transforms the object moves through while the shutter is open, for motion blur.
+    pub motion: Vec<Transform>,
+    // Combined transform of the groups the object was placed in, already applied to its transforms.
+    pub parent: Option<Transform>,
+    // Image tinting the diffuse color, mapped by the shape's texture coordinates.
+    pub texture: Option<Arc<Rgb32FImage>>,
 }
 
 impl Renderable {
@@ -14,7 +43,13 @@ impl Renderable {
         Self {
             transform,
             material,
+            material_name: None,
             shape,
+            medium: None,
+            name: None,
+            motion: Vec::new(),
+            parent: None,
+            texture: None,
         }
     }
     
@@ -22,7 +57,13 @@ impl Renderable {
         Self {
             transform,
             material,
+            material_name: None,
             shape: RenderShape::Sphere(radius),
+            medium: None,
+            name: None,
+            motion: Vec::new(),
+            parent: None,
+            texture: None,
         }
     }
 
@@ -30,30 +71,80 @@ impl Renderable {
         Self {
             transform,
             material,
+            material_name: None,
             shape: RenderShape::Box(bounds),
+            medium: None,
+            name: None,
+            motion: Vec::new(),
+            parent: None,
+            texture: None,
         }
     }
     
-    // Returns hit position and hit normal.
-    pub fn trace(&self, ray_orig: Point, ray_dir: Vector) -> Option<(Point, Vector)> {
-        let ray_orig = self.transform.to_local_point(ray_orig);
-        let ray_dir = self.transform.to_local_vector(ray_dir);
+    pub fn get_transform(&self, time: f64) -> Transform {
+        interpolate_motion(&self.transform, &self.motion, time)
+    }
+    
+    // Returns hit position, hit normal, and the shape's details of the hit.
+    pub fn trace(&self, ray_orig: Point, ray_dir: Vector, time: f64) -> Option<(Point, Vector, HitDetail)> {
+        let transform = self.get_transform(time);
+        let ray_orig = transform.to_local_point(ray_orig);
+        let ray_dir = transform.to_local_vector(ray_dir);
         
-        if let Some((hit_position, normal)) = self.shape.trace(ray_orig, ray_dir){
-            Some((self.transform.to_global_point(hit_position), self.transform.to_global_vector(normal)))
+        if let Some((hit_position, normal, detail)) = self.shape.trace(ray_orig, ray_dir){
+            Some((transform.to_global_point(hit_position), transform.to_global_normal(normal), detail))
+        } else {
+            None
+        }
+    }
+    
+    // Returns the point and normal where a ray starting inside the shape leaves it.
+    pub fn trace_exit(&self, ray_orig: Point, ray_dir: Vector, time: f64) -> Option<(Point, Vector)> {
+        let transform = self.get_transform(time);
+        let ray_orig = transform.to_local_point(ray_orig);
+        let ray_dir = transform.to_local_vector(ray_dir);
+
+        if let Some((hit_position, normal)) = self.shape.trace_exit(ray_orig, ray_dir){
+            Some((transform.to_global_point(hit_position), transform.to_global_normal(normal)))
         } else {
             None
         }
     }
+
+    pub fn contains(&self, point: Point, time: f64) -> bool {
+        self.shape.contains(self.get_transform(time).to_local_point(point))
+    }
+    
+    // Direction along the surface where it was hit, for shapes with one, such as the hairs of curves.
+    pub fn get_tangent(&self, detail: HitDetail, time: f64) -> Option<Vector> {
+        match detail {
+            HitDetail::Tangent(tangent) => Some(self.get_transform(time).to_global_vector(tangent)),
+            _ => None,
+        }
+    }
+    
+    // Color tinting the diffuse color at a point on the surface, from the object's texture if it has one,
+    // and otherwise from the shape's own vertex colors or texture.
+    pub fn get_color(&self, point: Point, detail: HitDetail, time: f64) -> Option<Vector> {
+        let point = self.get_transform(time).to_local_point(point);
+        match &self.texture {
+            Some(texture) => Some(sample_texture(texture, self.shape.get_uv(point, detail))),
+            None => self.shape.get_color(detail),
+        }
+    }
     
+    // Infinite planes have no bounding box, so they are traced separately from the acceleration structure.
+    pub fn is_bounded(&self) -> bool {
+        !matches!(self.shape, RenderShape::Plane)
+    }
+    
+    // Returns the center and half size of a box enclosing the object over the whole shutter interval.
     pub fn get_aabb(&self) -> (Point, Vector) {
         let mut min = Point::new(f64::MAX, f64::MAX, f64::MAX);
         let mut max = Point::new(f64::MIN, f64::MIN, f64::MIN);
         
         let points = self.shape.get_box_points();
-        
-        for p in points {
-            let p = self.transform.to_global_point(p);
+        let mut enclose = |p: Point| {
             min.x = min.x.min(p.x);
             min.y = min.y.min(p.y);
             min.z = min.z.min(p.z);
@@ -61,6 +152,34 @@ impl Renderable {
             max.x = max.x.max(p.x);
             max.y = max.y.max(p.y);
             max.z = max.z.max(p.z);
+        };
+        
+        if self.motion.is_empty() {
+            for p in points {
+                enclose(self.transform.to_global_point(p));
+            }
+        } else {
+            // Between keys with the same rotation every point moves in a straight line, so the keys' boxes enclose the path.
+            // While rotating, a point can swing anywhere within its distance of the object's position,
+            // and that distance only changes linearly with the scale.
+            let keys = (0..=self.motion.len()).map(|key| self.get_transform(key as f64 / self.motion.len() as f64)).collect::<Vec<_>>();
+            for key in &keys {
+                for p in points {
+                    enclose(key.to_global_point(p));
+                }
+            }
+            for segment in keys.windows(2) {
+                if segment[0].get_rotation() == segment[1].get_rotation() {
+                    continue;
+                }
+                let radius = segment.iter()
+                    .flat_map(|key| points.iter().map(|p| p.to_vec().mul_element_wise(key.get_scale()).magnitude()))
+                    .fold(0.0, f64::max);
+                for key in segment {
+                    enclose(key.get_position() - Vector::new(radius, radius, radius));
+                    enclose(key.get_position() + Vector::new(radius, radius, radius));
+                }
+            }
         }
 
         (max.add_element_wise(min) / 2.0, max.sub_element_wise(min).to_vec() / 2.0)
@@ -71,27 +190,205 @@ impl Renderable {
     }
 }
 
+// What a shape found out about a hit while tracing it, beyond the point and normal, so shading doesn't have to find it again.
 #[derive(Debug, Copy, Clone)]
+pub enum HitDetail {
+    None,
+    // The index of the mesh triangle hit, and the weights of its second and third vertices.
+    Triangle(usize, f64, f64),
+    // The direction along the curve hit.
+    Tangent(Vector),
+}
+
+#[derive(Debug, Clone)]
 pub enum RenderShape {
     None,
     Sphere(f64),
     Box(Vector),
+    // Infinite plane through the origin, facing along the y axis.
+    Plane,
+    // Rectangle in the xz plane, with half sizes along x and z.
+    Quad(Vector2<f64>),
+    // Circle in the xz plane, with a radius.
+    Disk(f64),
+    // Radius and half height along the y axis, and whether the ends are closed.
+    Cylinder(f64, f64, bool),
+    // Bottom radius, top radius, and half height along the y axis, and whether the ends are closed.
+    // A top radius of zero makes a cone, otherwise a frustum.
+    Cone(f64, f64, f64, bool),
+    // Radius, and half height of the straight section between the two hemispheres.
+    Capsule(f64, f64),
+    // Radius of the ring around the y axis, and radius of the tube.
+    Torus(f64, f64),
+    // Half sizes like a box, and the radius its edges and corners are rounded by.
+    RoundedBox(Vector, f64),
+    // Closed shapes combined by union, intersection, and difference.
+    Csg(Arc<Csg>),
+    // Distance field built from an expression tree, traced within its bounds.
+    Sdf(Arc<Sdf>),
+    // Terrain over the xz plane, rising along the y axis.
+    Heightfield(Arc<Heightfield>),
+    // Bezier curves for hair, fur, and grass.
+    Curves(Arc<Curves>),
+    // Triangles loaded from a PLY or STL file.
+    Mesh(Arc<Mesh>),
+    // A placement of the scene prototype with this index, and the min and max corners of the prototype's bounds.
+    // Instances are traced through the acceleration structure, which shares one hierarchy between all of them.
+    Instance(usize, Point, Point),
 }
 
 impl RenderShape {
-    pub fn trace(&self, ray_orig: Point, ray_dir: Vector) -> Option<(Point, Vector)> {
-        match self {
+    pub fn trace(&self, ray_orig: Point, ray_dir: Vector) -> Option<(Point, Vector, HitDetail)> {
+        let hit = match self {
             RenderShape::None => None,
             RenderShape::Sphere(radius) => RenderShape::trace_sphere(*radius, ray_orig, ray_dir),
             RenderShape::Box(bounds) => RenderShape::trace_box(*bounds, ray_orig, ray_dir),
+            RenderShape::Plane => RenderShape::trace_plane(ray_orig, ray_dir),
+            RenderShape::Quad(size) => RenderShape::trace_plane(ray_orig, ray_dir)
+                .filter(|(hit_point, _)| hit_point.x.abs() <= size.x && hit_point.z.abs() <= size.y),
+            RenderShape::Disk(radius) => RenderShape::trace_plane(ray_orig, ray_dir)
+                .filter(|(hit_point, _)| hit_point.x * hit_point.x + hit_point.z * hit_point.z <= radius * radius),
+            RenderShape::Cylinder(..) | RenderShape::Cone(..) | RenderShape::Capsule(..) | RenderShape::Torus(..) | RenderShape::RoundedBox(..) | RenderShape::Csg(_) | RenderShape::Sdf(_) => {
+                self.trace_crossings(ray_orig, ray_dir, false)
+            }
+            RenderShape::Heightfield(heightfield) => heightfield.trace(ray_orig, ray_dir),
+            RenderShape::Curves(curves) => return curves.trace(ray_orig, ray_dir),
+            RenderShape::Mesh(mesh) => return mesh.trace(ray_orig, ray_dir),
+            RenderShape::Instance(..) => None,
+        };
+        hit.map(|(hit_point, normal)| (hit_point, normal, HitDetail::None))
+    }
+    
+    pub fn trace_exit(&self, ray_orig: Point, ray_dir: Vector) -> Option<(Point, Vector)> {
+        match self {
+            RenderShape::None => None,
+            RenderShape::Sphere(radius) => RenderShape::exit_sphere(*radius, ray_orig, ray_dir),
+            RenderShape::Box(bounds) => RenderShape::exit_box(*bounds, ray_orig, ray_dir),
+            RenderShape::Plane | RenderShape::Quad(_) | RenderShape::Disk(_) | RenderShape::Heightfield(_) | RenderShape::Curves(_) | RenderShape::Mesh(_) => None,
+            RenderShape::Cylinder(..) | RenderShape::Cone(..) | RenderShape::Capsule(..) | RenderShape::Torus(..) | RenderShape::RoundedBox(..) | RenderShape::Csg(_) | RenderShape::Sdf(_) => {
+                self.trace_crossings(ray_orig, ray_dir, true)
+            }
+            RenderShape::Instance(..) => None,
+        }
+    }
+
+    pub fn contains(&self, point: Point) -> bool {
+        match self {
+            RenderShape::None => false,
+            RenderShape::Sphere(radius) => point.to_vec().magnitude2() < radius * radius,
+            RenderShape::Box(bounds) => point.x.abs() < bounds.x && point.y.abs() < bounds.y && point.z.abs() < bounds.z,
+            RenderShape::Plane | RenderShape::Quad(_) | RenderShape::Disk(_) | RenderShape::Heightfield(_) | RenderShape::Curves(_) | RenderShape::Mesh(_) => false,
+            RenderShape::Cylinder(radius, half_height, capped) => {
+                *capped && point.x * point.x + point.z * point.z < radius * radius && point.y.abs() < *half_height
+            }
+            RenderShape::Cone(bottom_radius, top_radius, half_height, capped) => {
+                let radius = cone_radius(*bottom_radius, *top_radius, *half_height, point.y);
+                *capped && point.x * point.x + point.z * point.z < radius * radius && point.y.abs() < *half_height
+            }
+            RenderShape::Capsule(radius, half_height) => {
+                (point - Point::new(0.0, point.y.clamp(-half_height, *half_height), 0.0)).magnitude2() < radius * radius
+            }
+            RenderShape::Torus(ring_radius, tube_radius) => {
+                let ring_dist = (point.x * point.x + point.z * point.z).sqrt() - ring_radius;
+                ring_dist * ring_dist + point.y * point.y < tube_radius * tube_radius
+            }
+            RenderShape::RoundedBox(bounds, radius) => rounded_box_distance(*bounds, *radius, point) < 0.0,
+            RenderShape::Csg(csg) => csg.contains(point),
+            RenderShape::Sdf(sdf) => sdf.contains(point),
+            RenderShape::Instance(..) => false,
+        }
+    }
+    
+    // Texture coordinates start at the top left of the image. Flat shapes put the top of the image at -z, as heightmaps do,
+    // and shapes around the y axis put it at the top. Planes repeat the image every unit along x and z,
+    // while quads and heightfields stretch it over their size, so it lines up with the heightmap.
+    // Disks are mapped by angle and distance from the center.
+    // Shapes around the y axis are mapped by angle around it, then from top to bottom, or by angle around the tube for tori.
+    // Meshes use the texture coordinates in their file, if it has them.
+    // Boxes and the remaining shapes don't have texture coordinates.
+    pub fn get_uv(&self, point: Point, detail: HitDetail) -> Vector2<f64> {
+        match self {
+            RenderShape::Plane => Vector2::new(point.x, point.z),
+            RenderShape::Quad(size) => Vector2::new((point.x / size.x + 1.0) / 2.0, (point.z / size.y + 1.0) / 2.0),
+            RenderShape::Heightfield(heightfield) => {
+                Vector2::new((point.x / heightfield.size.x + 1.0) / 2.0, (point.z / heightfield.size.y + 1.0) / 2.0)
+            }
+            RenderShape::Sphere(radius) => {
+                Vector2::new(get_angle_uv(point), 0.5 - (point.y / radius).clamp(-1.0, 1.0).asin() / PI)
+            }
+            RenderShape::Disk(radius) => Vector2::new(
+                point.z.atan2(point.x).rem_euclid(TAU) / TAU,
+                (point.x * point.x + point.z * point.z).sqrt() / radius,
+            ),
+            RenderShape::Cylinder(_, half_height, _) | RenderShape::Cone(_, _, half_height, _) => {
+                Vector2::new(get_angle_uv(point), (1.0 - point.y / half_height) / 2.0)
+            }
+            RenderShape::Capsule(radius, half_height) => {
+                Vector2::new(get_angle_uv(point), (1.0 - point.y / (half_height + radius)) / 2.0)
+            }
+            RenderShape::Torus(ring_radius, _) => {
+                let ring_dist = (point.x * point.x + point.z * point.z).sqrt() - ring_radius;
+                Vector2::new(get_angle_uv(point), point.y.atan2(ring_dist).rem_euclid(TAU) / TAU)
+            }
+            RenderShape::Mesh(mesh) => match detail {
+                HitDetail::Triangle(ind, u, v) => mesh.get_uv(ind, u, v).unwrap_or(Vector2::zero()),
+                _ => Vector2::zero(),
+            },
+            _ => Vector2::zero(),
+        }
+    }
+    
+    pub fn get_color(&self, detail: HitDetail) -> Option<Vector> {
+        match (self, detail) {
+            (RenderShape::Mesh(mesh), HitDetail::Triangle(ind, u, v)) => mesh.get_color(ind, u, v),
+            _ => None,
         }
     }
     
-    fn get_box_points(&self) -> [Point; 8] {
+    pub fn get_box_points(&self) -> [Point; 8] {
         match self {
             RenderShape::None => [Point::new(0.0, 0.0, 0.0); 8],
             RenderShape::Sphere(radius) => RenderShape::sphere_points(*radius),
             RenderShape::Box(size) => RenderShape::box_points(*size),
+            RenderShape::Plane => [Point::new(0.0, 0.0, 0.0); 8],
+            RenderShape::Quad(size) => RenderShape::box_points(Vector::new(size.x, 0.0, size.y)),
+            RenderShape::Disk(radius) => RenderShape::box_points(Vector::new(*radius, 0.0, *radius)),
+            RenderShape::Cylinder(radius, half_height, _) => RenderShape::box_points(Vector::new(*radius, *half_height, *radius)),
+            RenderShape::Cone(bottom_radius, top_radius, half_height, _) => {
+                let radius = bottom_radius.max(*top_radius);
+                RenderShape::box_points(Vector::new(radius, *half_height, radius))
+            }
+            RenderShape::Capsule(radius, half_height) => RenderShape::box_points(Vector::new(*radius, half_height + radius, *radius)),
+            RenderShape::Torus(ring_radius, tube_radius) => {
+                let radius = ring_radius + tube_radius;
+                RenderShape::box_points(Vector::new(radius, *tube_radius, radius))
+            }
+            RenderShape::RoundedBox(size, _) => RenderShape::box_points(*size),
+            RenderShape::Csg(csg) => {
+                let (min, max) = csg.get_bounds();
+                let center = min.midpoint(max);
+                RenderShape::box_points((max - min) / 2.0).map(|p| p + center.to_vec())
+            }
+            RenderShape::Sdf(sdf) => RenderShape::box_points(sdf.bounds),
+            RenderShape::Heightfield(heightfield) => {
+                let (min, max) = heightfield.get_height_range();
+                let center = Vector::new(0.0, (min + max) / 2.0, 0.0);
+                RenderShape::box_points(Vector::new(heightfield.size.x, (max - min) / 2.0, heightfield.size.y)).map(|p| p + center)
+            }
+            RenderShape::Curves(curves) => {
+                let (min, max) = curves.get_bounds();
+                let center = min.midpoint(max);
+                RenderShape::box_points((max - min) / 2.0).map(|p| p + center.to_vec())
+            }
+            RenderShape::Mesh(mesh) => {
+                let (min, max) = mesh.get_bounds();
+                let center = min.midpoint(max);
+                RenderShape::box_points((max - min) / 2.0).map(|p| p + center.to_vec())
+            }
+            RenderShape::Instance(_, min, max) => {
+                let center = min.midpoint(*max);
+                RenderShape::box_points((max - min) / 2.0).map(|p| p + center.to_vec())
+            }
         }
     }
     
@@ -156,6 +453,276 @@ impl RenderShape {
         Some((hit_position, normal))
     }
     
+    // Closed shapes can be entered and exited, while open ones are surfaces seen from both sides.
+    pub fn is_closed(&self) -> bool {
+        match self {
+            RenderShape::Cylinder(_, _, capped) | RenderShape::Cone(_, _, _, capped) => *capped,
+            RenderShape::Plane | RenderShape::Quad(_) | RenderShape::Disk(_) | RenderShape::Heightfield(_) | RenderShape::Curves(_) | RenderShape::Mesh(_) | RenderShape::None => false,
+            _ => true,
+        }
+    }
+    
+    // Returns the nearest crossing of the surface in front of the ray, or the nearest exit from a closed shape.
+    fn trace_crossings(&self, ray_orig: Point, ray_dir: Vector, exit: bool) -> Option<(Point, Vector)> {
+        let closed = self.is_closed();
+        if exit && !closed {
+            return None;
+        }
+        let (dist, normal) = self.get_crossings(ray_orig, ray_dir).into_iter()
+            .find(|(dist, normal)| *dist > CROSSING_EPSILON && (!exit || ray_dir.dot(*normal) > 0.0))?;
+        // Open surfaces face whichever side the ray came from.
+        let normal = if closed || ray_dir.dot(normal) < 0.0 { normal } else { -normal };
+        Some((ray_orig + ray_dir * dist, normal))
+    }
+    
+    // Every place the ray crosses the surface, as distances along the ray and outward normals, nearest first.
+    // Closed shapes alternate between entering and exiting, starting outside.
+    pub fn get_crossings(&self, ray_orig: Point, ray_dir: Vector) -> Vec<(f64, Vector)> {
+        let mut crossings = match self {
+            RenderShape::Sphere(radius) => {
+                let offset = ray_orig.to_vec();
+                solve_quadratic(ray_dir.dot(ray_dir), 2.0 * offset.dot(ray_dir), offset.dot(offset) - radius * radius)
+                    .into_iter()
+                    .map(|dist| (dist, (ray_orig + ray_dir * dist).to_vec().normalize()))
+                    .collect()
+            }
+            RenderShape::Box(bounds) => RenderShape::box_crossings(*bounds, ray_orig, ray_dir),
+            RenderShape::Csg(csg) => csg.get_spans(ray_orig, ray_dir).into_iter()
+                .flat_map(|span| [span.enter, span.exit])
+                .collect(),
+            RenderShape::Cylinder(radius, half_height, capped) => {
+                RenderShape::cone_crossings(*radius, *radius, *half_height, *capped, ray_orig, ray_dir)
+            }
+            RenderShape::Cone(bottom_radius, top_radius, half_height, capped) => {
+                RenderShape::cone_crossings(*bottom_radius, *top_radius, *half_height, *capped, ray_orig, ray_dir)
+            }
+            RenderShape::Capsule(radius, half_height) => RenderShape::capsule_crossings(*radius, *half_height, ray_orig, ray_dir),
+            RenderShape::Torus(ring_radius, tube_radius) => RenderShape::torus_crossings(*ring_radius, *tube_radius, ray_orig, ray_dir),
+            RenderShape::RoundedBox(bounds, radius) => RenderShape::rounded_box_crossings(*bounds, *radius, ray_orig, ray_dir),
+            RenderShape::Sdf(sdf) => sdf.get_crossings(ray_orig, ray_dir),
+            _ => Vec::new(),
+        };
+        crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+        crossings
+    }
+    
+    // Cylinders are cones with equal radii at both ends.
+    fn cone_crossings(bottom_radius: f64, top_radius: f64, half_height: f64, capped: bool, ray_orig: Point, ray_dir: Vector) -> Vec<(f64, Vector)> {
+        // The radius at height y is slope * y + middle, so the side is x^2 + z^2 = (slope * y + middle)^2.
+        let slope = (top_radius - bottom_radius) / (2.0 * half_height);
+        let middle = (top_radius + bottom_radius) / 2.0;
+        let radius_orig = slope * ray_orig.y + middle;
+        
+        let a = ray_dir.x * ray_dir.x + ray_dir.z * ray_dir.z - slope * slope * ray_dir.y * ray_dir.y;
+        let b = 2.0 * (ray_orig.x * ray_dir.x + ray_orig.z * ray_dir.z - slope * ray_dir.y * radius_orig);
+        let c = ray_orig.x * ray_orig.x + ray_orig.z * ray_orig.z - radius_orig * radius_orig;
+        
+        let mut crossings: Vec<(f64, Vector)> = solve_quadratic(a, b, c).into_iter()
+            .filter_map(|dist| {
+                let p = ray_orig + ray_dir * dist;
+                (p.y.abs() <= half_height).then(|| (dist, Vector::new(p.x, -slope * (slope * p.y + middle), p.z).normalize()))
+            })
+            .collect();
+        
+        if capped {
+            for (cap_y, cap_radius) in [(-half_height, bottom_radius), (half_height, top_radius)] {
+                let dist = (cap_y - ray_orig.y) / ray_dir.y;
+                let p = ray_orig + ray_dir * dist;
+                if dist.is_finite() && p.x * p.x + p.z * p.z <= cap_radius * cap_radius {
+                    crossings.push((dist, Vector::unit_y() * cap_y.signum()));
+                }
+            }
+        }
+        crossings
+    }
+    
+    fn capsule_crossings(radius: f64, half_height: f64, ray_orig: Point, ray_dir: Vector) -> Vec<(f64, Vector)> {
+        // Side crossings within the straight section, then each hemisphere beyond it.
+        let side = RenderShape::cone_crossings(radius, radius, half_height, false, ray_orig, ray_dir);
+        let mut crossings = side.clone();
+        for end in [-half_height, half_height] {
+            let center = Point::new(0.0, end, 0.0);
+            let offset = ray_orig - center;
+            for dist in solve_quadratic(ray_dir.dot(ray_dir), 2.0 * offset.dot(ray_dir), offset.dot(offset) - radius * radius) {
+                let p = ray_orig + ray_dir * dist;
+                // Crossings on the seam are found by both the side and the hemisphere, but only count once.
+                let on_side = side.iter().any(|(side_dist, _)| (side_dist - dist).abs() < CROSSING_EPSILON);
+                if (p.y - end) * end.signum() >= -CROSSING_EPSILON && !on_side {
+                    crossings.push((dist, (p - center).normalize()));
+                }
+            }
+        }
+        crossings
+    }
+    
+    fn torus_crossings(ring_radius: f64, tube_radius: f64, ray_orig: Point, ray_dir: Vector) -> Vec<(f64, Vector)> {
+        // Starting the ray near the torus keeps the quartic's coefficients small, so its roots stay precise.
+        let bounding_radius = ring_radius + tube_radius;
+        let offset = ray_orig.to_vec();
+        let start = solve_quadratic(ray_dir.dot(ray_dir), 2.0 * offset.dot(ray_dir), offset.dot(offset) - bounding_radius * bounding_radius);
+        let Some(exit) = start.last() else {
+            return Vec::new();
+        };
+        if *exit < 0.0 {
+            return Vec::new();
+        }
+        let shift = start[0].max(0.0);
+        let o = ray_orig + ray_dir * shift;
+        
+        // (|p|^2 + R^2 - r^2)^2 = 4 R^2 (x^2 + z^2), along the ray p = o + t * d.
+        let ring2 = ring_radius * ring_radius;
+        let dd = ray_dir.dot(ray_dir);
+        let od = o.to_vec().dot(ray_dir);
+        let k = o.to_vec().dot(o.to_vec()) + ring2 - tube_radius * tube_radius;
+        let roots = solve_quartic(
+            dd * dd,
+            4.0 * dd * od,
+            4.0 * od * od + 2.0 * dd * k - 4.0 * ring2 * (ray_dir.x * ray_dir.x + ray_dir.z * ray_dir.z),
+            4.0 * od * k - 8.0 * ring2 * (o.x * ray_dir.x + o.z * ray_dir.z),
+            k * k - 4.0 * ring2 * (o.x * o.x + o.z * o.z),
+        );
+        
+        roots.into_iter().map(|t| {
+            let p = o + ray_dir * t;
+            // The normal points away from the nearest point on the ring.
+            let ring_point = Vector::new(p.x, 0.0, p.z).normalize() * ring_radius;
+            (t + shift, (p.to_vec() - ring_point).normalize())
+        }).collect()
+    }
+    
+    fn box_crossings(bounds: Vector, ray_orig: Point, ray_dir: Vector) -> Vec<(f64, Vector)> {
+        let inv_dir = 1.0 / ray_dir;
+        let t0 = (Point::from_vec(-bounds) - ray_orig).mul_element_wise(inv_dir);
+        let t1 = (Point::from_vec(bounds) - ray_orig).mul_element_wise(inv_dir);
+        
+        // The ray enters through the last slab it reaches and leaves through the first one it leaves.
+        let mut enter = (f64::MIN, Vector::zero());
+        let mut exit = (f64::MAX, Vector::zero());
+        for axis in 0..3 {
+            let mut normal = Vector::zero();
+            normal[axis] = 1.0;
+            let (near, far, near_normal) = if t0[axis] < t1[axis] {
+                (t0[axis], t1[axis], -normal)
+            } else {
+                (t1[axis], t0[axis], normal)
+            };
+            if near > enter.0 {
+                enter = (near, near_normal);
+            }
+            if far < exit.0 {
+                exit = (far, -near_normal);
+            }
+        }
+        
+        if enter.0 > exit.0 || enter.0.is_nan() || exit.0.is_nan() {
+            return Vec::new();
+        }
+        vec![enter, exit]
+    }
+    
+    // Sphere traces towards the surface from both ends of the ray's span through the bounds, as the shape is convex.
+    fn rounded_box_crossings(bounds: Vector, radius: f64, ray_orig: Point, ray_dir: Vector) -> Vec<(f64, Vector)> {
+        let inv_dir = 1.0 / ray_dir;
+        let t0 = (Point::from_vec(-bounds) - ray_orig).mul_element_wise(inv_dir);
+        let t1 = (Point::from_vec(bounds) - ray_orig).mul_element_wise(inv_dir);
+        let t_min = t0.zip(t1, f64::min);
+        let t_max = t0.zip(t1, f64::max);
+        let near = t_min.x.max(t_min.y.max(t_min.z));
+        let far = t_max.x.min(t_max.y.min(t_max.z));
+        if far < near {
+            return Vec::new();
+        }
+        
+        let speed = ray_dir.magnitude();
+        let march = |mut dist: f64, step: f64| {
+            for _i in 0..MAX_SDF_STEPS {
+                let surface_dist = rounded_box_distance(bounds, radius, ray_orig + ray_dir * dist);
+                if surface_dist < SDF_EPSILON {
+                    return Some(dist);
+                }
+                dist += step * surface_dist / speed;
+                if dist < near || dist > far {
+                    return None;
+                }
+            }
+            None
+        };
+        
+        match (march(near, 1.0), march(far, -1.0)) {
+            (Some(enter), Some(exit)) => [enter, exit].into_iter()
+                .map(|dist| (dist, rounded_box_normal(bounds, radius, ray_orig + ray_dir * dist)))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+    
+    // Hits the xz plane from either side, with the normal facing back along the ray.
+    fn trace_plane(ray_orig: Point, ray_dir: Vector) -> Option<(Point, Vector)> {
+        let dist = -ray_orig.y / ray_dir.y;
+        if !dist.is_finite() || dist <= FLAT_EPSILON {
+            return None;
+        }
+        
+        let hit_point = ray_orig + ray_dir * dist;
+        let normal = if ray_dir.y > 0.0 { -Vector::unit_y() } else { Vector::unit_y() };
+        
+        Some((hit_point, normal))
+    }
+    
+    fn exit_sphere(radius: f64, ray_orig: Point, ray_dir: Vector) -> Option<(Point, Vector)> {
+        let a = ray_dir.dot(ray_dir);
+        let b = 2.0 * ray_orig.dot(ray_dir);
+        let c = ray_orig.dot(ray_orig.to_vec()) - radius * radius;
+        if b*b - 4.0*a*c < 0.0 {
+            return None;
+        }
+
+        let dist = (-b + (b*b-4.0*a*c).sqrt()) / (2.0*a);
+        if dist <= 0.0 {
+            return None;
+        }
+        let hit_point = ray_orig + ray_dir * dist;
+        let normal = hit_point.to_vec().normalize();
+
+        Some((hit_point, normal))
+    }
+
+    fn exit_box(bounds: Vector, ray_orig: Point, ray_dir: Vector) -> Option<(Point, Vector)> {
+        let b_min = Point::from_vec(-bounds);
+        let b_max = Point::from_vec(bounds);
+        let inv_dir = 1.0 / ray_dir;
+
+        let t0 = (b_min - ray_orig).mul_element_wise(inv_dir);
+        let t1 = (b_max - ray_orig).mul_element_wise(inv_dir);
+
+        let v_max = t0.zip(t1, |a, b| a.max(b));
+        let t_max = v_max.x.min(v_max.y.min(v_max.z));
+
+        if t_max <= 0.0 {
+            return None;
+        }
+
+        let hit_position = ray_orig + ray_dir * t_max;
+
+        let normal = {
+            if t_max == t0.x {
+                -Vector::unit_x()
+            } else if t_max == t1.x {
+                Vector::unit_x()
+            } else if t_max == t0.y {
+                -Vector::unit_y()
+            } else if t_max == t1.y {
+                Vector::unit_y()
+            } else if t_max == t0.z {
+                -Vector::unit_z()
+            } else {
+                Vector::unit_z()
+            }
+        };
+
+        Some((hit_position, normal))
+    }
+    
     fn sphere_points(radius: f64) -> [Point; 8] {
         //let radius = radius / 2.0;
         [
@@ -183,4 +750,78 @@ impl RenderShape {
             Point::new(size.x, size.y, size.z),
         ]
     }
-}
\ No newline at end of file
+}
+
+// Radius of a cone at a height along its axis.
+fn cone_radius(bottom_radius: f64, top_radius: f64, half_height: f64, y: f64) -> f64 {
+    bottom_radius + (top_radius - bottom_radius) * (y + half_height) / (2.0 * half_height)
+}
+
+// Angle around the y axis, from 0 to 1.
+fn get_angle_uv(point: Point) -> f64 {
+    point.z.atan2(point.x).rem_euclid(TAU) / TAU
+}
+
+// Signed distance to a box with rounded edges, negative inside.
+fn rounded_box_distance(bounds: Vector, radius: f64, point: Point) -> f64 {
+    let radius = radius.min(bounds.x.min(bounds.y.min(bounds.z)));
+    let q = point.to_vec().map(f64::abs) - bounds + Vector::new(radius, radius, radius);
+    q.map(|c| c.max(0.0)).magnitude() + q.x.max(q.y.max(q.z)).min(0.0) - radius
+}
+
+fn rounded_box_normal(bounds: Vector, radius: f64, point: Point) -> Vector {
+    let radius = radius.min(bounds.x.min(bounds.y.min(bounds.z)));
+    let q = point.to_vec().map(f64::abs) - bounds + Vector::new(radius, radius, radius);
+    // Away from the inner box on the rounded parts, and along the nearest axis on the flat faces.
+    let outside = q.map(|c| c.max(0.0));
+    let normal = if outside.magnitude2() > 0.0 {
+        outside.normalize()
+    } else if q.x > q.y && q.x > q.z {
+        Vector::unit_x()
+    } else if q.y > q.z {
+        Vector::unit_y()
+    } else {
+        Vector::unit_z()
+    };
+    normal.zip(point.to_vec(), |n, p| n * p.signum())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_distances(distances: &[f64], expected: &[f64], tolerance: f64) {
+        assert_eq!(distances.len(), expected.len(), "found {distances:?}, expected {expected:?}");
+        for (dist, expected_dist) in distances.iter().zip(expected) {
+            assert!((dist - expected_dist).abs() < tolerance, "found {distances:?}, expected {expected:?}");
+        }
+    }
+
+    #[test]
+    fn torus_crossings() {
+        let torus = RenderShape::Torus(2.0, 0.5);
+
+        // Straight through both sides of the tube, with a direction that isn't normalized.
+        let crossings = torus.get_crossings(Point::new(-5.0, 0.0, 0.0), Vector::new(2.0, 0.0, 0.0));
+        let distances = crossings.iter().map(|(dist, _)| *dist).collect::<Vec<_>>();
+        assert_distances(&distances, &[1.25, 1.75, 3.25, 3.75], 1e-9);
+        assert!((crossings[0].1 - Vector::new(-1.0, 0.0, 0.0)).magnitude() < 1e-9);
+        assert!((crossings[1].1 - Vector::new(1.0, 0.0, 0.0)).magnitude() < 1e-9);
+
+        // Along the z axis, and down through the hole in the middle.
+        let crossings = torus.get_crossings(Point::new(0.0, 0.0, 5.0), Vector::new(0.0, 0.0, -1.0));
+        let distances = crossings.iter().map(|(dist, _)| *dist).collect::<Vec<_>>();
+        assert_distances(&distances, &[2.5, 3.5, 6.5, 7.5], 1e-9);
+        assert!(torus.get_crossings(Point::new(0.0, 5.0, 0.0), Vector::new(0.0, -1.0, 0.0)).is_empty());
+
+        // Just under the top of the tube, crossing it twice close together on each side.
+        let height: f64 = 0.5 - 1e-6;
+        let half_chord = (0.25 - height * height).sqrt();
+        let crossings = torus.get_crossings(Point::new(-5.0, height, 0.0), Vector::new(1.0, 0.0, 0.0));
+        let distances = crossings.iter().map(|(dist, _)| *dist).collect::<Vec<_>>();
+        assert_distances(&distances, &[3.0 - half_chord, 3.0 + half_chord, 7.0 - half_chord, 7.0 + half_chord], 1e-6);
+
+        // Just over the top, missing it.
+        assert!(torus.get_crossings(Point::new(-5.0, 0.5 + 1e-6, 0.0), Vector::new(1.0, 0.0, 0.0)).is_empty());
+    }
+}
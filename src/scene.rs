@@ -1,14 +1,20 @@
+use std::f64::consts::TAU;
 use cgmath::{ElementWise, InnerSpace, MetricSpace, Vector2, VectorSpace, Zero};
+use rand::{Rng, thread_rng};
+use crate::animation::Animation;
 use crate::camera::*;
 use crate::material::{Material, PhysicalMaterial};
+use crate::medium::{Medium, orthonormal_basis};
 use crate::renderable::Renderable;
 use crate::transform::*;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Scene {
     pub camera: Camera,
     pub sky: Sky,
+    pub animation: Animation,
     objects: Vec<Renderable>,
+    prototypes: Vec<Prototype>,
 }
 
 impl Scene {
@@ -16,7 +22,9 @@ impl Scene {
         Self{
             camera,
             sky,
+            animation: Animation::new(),
             objects: Vec::new(),
+            prototypes: Vec::new(),
         }
     }
     
@@ -28,14 +36,18 @@ impl Scene {
         self.objects.len()
     }
     
-    pub fn trace_pixel(&self, x_coord: f64, y_coord: f64, num_bounces: usize) -> Vector {
-        let (mut ray_orig, mut ray_dir) = self.camera.get_ray(Vector2::new(x_coord, y_coord));
+    pub fn trace_pixel(&self, x_coord: f64, y_coord: f64, aspect: f64, eye: Eye, num_bounces: usize) -> Vector {
+        let time = self.camera.sample_time();
+        let (mut ray_orig, mut ray_dir) = match self.camera.get_ray(Vector2::new(x_coord, y_coord), aspect, eye, time) {
+            Some(ray) => ray,
+            None => return Vector::zero(),
+        };
         
         let mut diffuse = Vector::new(1.0, 1.0, 1.0);
         let mut lighting = Vector::new(0.0, 0.0, 0.0);
         
         for _i in 0..num_bounces {
-            if let Some((hit_point, normal, material)) = self.trace_scene(ray_orig, ray_dir) {
+            if let Some((hit_point, normal, material)) = self.trace_scene(ray_orig, ray_dir, time) {
                 //return normal;
                 let (hit_diffuse, hit_emissive) = material.hit_surface(&mut ray_orig, &mut ray_dir, hit_point, normal);
                 diffuse.mul_assign_element_wise(hit_diffuse);
@@ -49,19 +61,64 @@ impl Scene {
             }
         }
         
-        return lighting;
+        return lighting * self.camera.get_exposure();
+    }
+    
+    // Returns the indices of objects with an interior medium that contain the point, innermost last.
+    pub fn get_media_at(&self, point: Point, time: f64) -> Vec<usize> {
+        let mut media: Vec<usize> = (0..self.objects.len())
+            .filter(|i| self.objects[*i].medium.is_some() && self.objects[*i].contains(point, time))
+            .collect();
+        media.sort_by(|a, b| {
+            let size_a = self.objects[*a].get_aabb().1.magnitude2();
+            let size_b = self.objects[*b].get_aabb().1.magnitude2();
+            size_b.total_cmp(&size_a)
+        });
+        media
+    }
+    
+    pub fn find_object(&self, name: &str) -> Option<usize> {
+        self.objects.iter().position(|object| object.name.as_deref() == Some(name))
     }
     
     pub fn get_object(&self, ind: usize) -> &Renderable {
         &self.objects[ind]
     }
     
-    fn trace_scene(&self, ray_orig: Point, ray_dir: Vector) -> Option<(Point, Vector, &PhysicalMaterial)> {
+    pub fn get_object_mut(&mut self, ind: usize) -> &mut Renderable {
+        &mut self.objects[ind]
+    }
+    
+    pub fn add_prototype(&mut self, prototype: Prototype) -> usize {
+        self.prototypes.push(prototype);
+        self.prototypes.len() - 1
+    }
+    
+    pub fn get_prototype_count(&self) -> usize {
+        self.prototypes.len()
+    }
+    
+    pub fn find_prototype(&self, name: &str) -> Option<usize> {
+        self.prototypes.iter().position(|prototype| prototype.name == name)
+    }
+    
+    pub fn get_prototype(&self, ind: usize) -> &Prototype {
+        &self.prototypes[ind]
+    }
+    
+    // Returns a copy of the scene with its animation applied at the given frame.
+    pub fn at_frame(&self, frame: f64) -> Scene {
+        let mut scene = self.clone();
+        self.animation.apply(&mut scene, frame);
+        scene
+    }
+    
+    fn trace_scene(&self, ray_orig: Point, ray_dir: Vector, time: f64) -> Option<(Point, Vector, &PhysicalMaterial)> {
         let mut res = None;
         let mut closest = 0.0;
 
         for object in &self.objects {
-            if let Some((hit_point, normal)) = object.trace(ray_orig, ray_dir) {
+            if let Some((hit_point, normal, _)) = object.trace(ray_orig, ray_dir, time) {
                 let dist = hit_point.distance(ray_orig);
                 if res.is_none() || dist < closest {
                     res = Some((hit_point, normal, object.get_material()));
@@ -74,7 +131,35 @@ impl Scene {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+// A set of objects that can be placed many times by instance objects, which only store their own transforms.
+#[derive(Debug, Clone)]
+pub struct Prototype {
+    pub name: String,
+    pub objects: Vec<Renderable>,
+}
+
+impl Prototype {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            objects: Vec::new(),
+        }
+    }
+    
+    // Returns the min and max corners of a box enclosing every object in the prototype.
+    pub fn get_bounds(&self) -> (Point, Point) {
+        let mut min = Point::new(f64::MAX, f64::MAX, f64::MAX);
+        let mut max = Point::new(f64::MIN, f64::MIN, f64::MIN);
+        for object in &self.objects {
+            let (center, size) = object.get_aabb();
+            min = min.zip(center - size, f64::min);
+            max = max.zip(center + size, f64::max);
+        }
+        (min, max)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Sky {
     sun_dir: Vector,
     pub sun_size: f64,
@@ -82,6 +167,8 @@ pub struct Sky {
     pub ground_color: Vector,
     pub horizon_color: Vector,
     pub zenith_color: Vector,
+    pub atmosphere: Option<Medium>,
+    pub atmosphere_extent: f64,
 }
 
 impl Sky {
@@ -93,6 +180,8 @@ impl Sky {
             ground_color,
             horizon_color,
             zenith_color,
+            atmosphere: None,
+            atmosphere_extent: 100.0,
         }
     }
     
@@ -115,12 +204,35 @@ impl Sky {
         if ray_dir.dot(self.sun_dir) > (1.0 - self.sun_size) {
             return self.sun_color / self.sun_size;
         }
+        self.get_background_color(ray_dir)
+    }
+    
+    // Sky color without the sun, for rays whose sun contribution was already sampled directly.
+    pub fn get_background_color(&self, ray_dir: Vector) -> Vector {
         let sky_gradient_t = smoothstep(0.0, 0.4, ray_dir.y).powf(0.35);
         let sky_gradient = self.horizon_color.lerp(self.zenith_color, sky_gradient_t);
         let ground_to_sky_t = smoothstep(-0.01, 0.0, ray_dir.y);
         
         self.ground_color.lerp(sky_gradient, ground_to_sky_t)
     }
+    
+    // Picks a direction within the sun's disk. Returns the direction and the sun's radiance divided by the sample pdf.
+    pub fn sample_sun(&self) -> (Vector, Vector) {
+        if self.sun_size <= 0.0 {
+            return (self.sun_dir, Vector::zero());
+        }
+        let mut rng = thread_rng();
+        
+        let cos_theta = 1.0 - rng.gen::<f64>() * self.sun_size;
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = rng.gen::<f64>() * TAU;
+        
+        let (tangent, bitangent) = orthonormal_basis(self.sun_dir);
+        let dir = (tangent * (sin_theta * phi.cos()) + bitangent * (sin_theta * phi.sin()) + self.sun_dir * cos_theta).normalize();
+        
+        // The sun covers a solid angle of TAU * sun_size with radiance sun_color / sun_size.
+        (dir, self.sun_color * TAU)
+    }
 }
 
 impl Default for Sky {
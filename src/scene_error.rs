@@ -0,0 +1,122 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+// A problem in a scene file, placed at the line and column it was found at, both counting from one.
+// Warnings, such as for ignored keys, are reported the same way but don't stop the scene from loading.
+#[derive(Debug, Clone)]
+pub struct SceneError {
+    pub file: PathBuf,
+    // Problems with the whole file, such as one that can't be opened, have no line or column.
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+    // The text the problem was found at, empty when something is missing.
+    pub token: String,
+    // A known key or name close to a misspelled one.
+    pub suggestion: Option<String>,
+}
+
+impl SceneError {
+    pub fn in_file(file: &Path, message: String) -> Self {
+        Self {
+            file: file.to_path_buf(),
+            line: 0,
+            column: 0,
+            message,
+            token: String::new(),
+            suggestion: None,
+        }
+    }
+
+    // Suggests the closest of the options to the token, if it looks like a misspelling of one.
+    pub fn suggest<'a>(mut self, options: impl IntoIterator<Item = &'a str>) -> Self {
+        self.suggestion = suggest(self.token.trim(), options);
+        self
+    }
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        if self.line > 0 {
+            write!(f, ":{}:{}", self.line, self.column)?;
+        }
+        write!(f, ": {}", self.message)?;
+        if !self.token.is_empty() {
+            write!(f, " `{}`", self.token)?;
+        }
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, ", did you mean `{suggestion}`?")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+// Finds the option closest to a misspelled word, if one is close enough to be a likely typo.
+pub fn suggest<'a>(word: &str, options: impl IntoIterator<Item = &'a str>) -> Option<String> {
+    options.into_iter()
+        .map(|option| (edit_distance(word, option), option))
+        .filter(|&(distance, option)| distance > 0 && distance <= (word.len().max(option.len()) / 3).max(1))
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, option)| option.to_string())
+}
+
+// The fewest single character insertions, deletions, and substitutions turning one word into the other.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = (above + 1).min(row[j] + 1).min(diagonal + usize::from(a_char != *b_char));
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("radius", "radius"), 0);
+        assert_eq!(edit_distance("radus", "radius"), 1);
+        assert_eq!(edit_distance("raduis", "radius"), 2);
+        assert_eq!(edit_distance("", "box"), 3);
+        assert_eq!(edit_distance("sphere", ""), 6);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn suggestions() {
+        let options = ["sphere", "box", "plane", "displace", "material.red.diffuse"];
+        assert_eq!(suggest("sphre", options).as_deref(), Some("sphere"));
+        assert_eq!(suggest("displac", options).as_deref(), Some("displace"));
+        assert_eq!(suggest("materail.red.diffuse", options).as_deref(), Some("material.red.diffuse"));
+        // Short words allow a single typo.
+        assert_eq!(suggest("bx", options).as_deref(), Some("box"));
+        // Exact matches and words nothing like any option aren't suggested.
+        assert_eq!(suggest("plane", options), None);
+        assert_eq!(suggest("teapot", options), None);
+        assert_eq!(suggest("sphere", []), None);
+    }
+
+    #[test]
+    fn closest_suggestion() {
+        assert_eq!(suggest("roughnes", ["roughness", "toughness"]).as_deref(), Some("roughness"));
+        assert_eq!(suggest("fisheye_equisolit", ["fisheye_equidistant", "fisheye_equisolid"]).as_deref(), Some("fisheye_equisolid"));
+    }
+
+    #[test]
+    fn display() {
+        let error = SceneError { line: 3, column: 5, token: "sphre".to_string(), ..SceneError::in_file(Path::new("a.ypt"), "Unknown shape".to_string()) };
+        assert_eq!(error.suggest(["sphere"]).to_string(), "a.ypt:3:5: Unknown shape `sphre`, did you mean `sphere`?");
+        assert_eq!(SceneError::in_file(Path::new("a.ypt"), "Failed to open scene".to_string()).to_string(), "a.ypt: Failed to open scene");
+    }
+}
@@ -1,13 +1,117 @@
 use std::collections::{HashMap, VecDeque};
+use std::fmt::Display;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 use std::path::Path;
-use cgmath::Deg;
-use crate::camera::Camera;
+use std::str::FromStr;
+use std::sync::Arc;
+use cgmath::{Deg, Matrix, Matrix4, One, Vector2, Vector4, Zero};
+use crate::acceleration_structure::AccelerationStructure;
+use crate::animation::{AnimationTarget, Interpolation, Keyframe, MaterialProperty, from_transform};
+use crate::camera::{ApertureImage, Camera, FieldOfView, Projection};
+use crate::csg::{Csg, CsgOp};
+use crate::curve::{Curves, CurveType};
+use crate::gltf_loader;
+use crate::hair::Hair;
+use crate::heightfield::Heightfield;
 use crate::material::{PhysicalMaterial};
+use crate::medium::Medium;
+use crate::mesh::Mesh;
+use crate::pbrt_loader;
 use crate::renderable::{Renderable, RenderShape};
-use crate::scene::{Scene, Sky};
-use crate::transform::{Point, Rot, Transform, Vector};
+use crate::scene::{Prototype, Scene, Sky};
+use crate::scene_error::{self, SceneError};
+use crate::sdf::{Sdf, SdfNode, SdfOp};
+use crate::texture;
+use crate::transform::{Point, Quat, Rot, Transform, Vector, axis_angle_rotation, euler_rotation, euler_rotation_ordered};
+use crate::voxel_grid::VoxelGrid;
+
+// Names known to each part of a scene file, used to suggest corrections for misspelled ones.
+const SECTIONS: [&str; 5] = ["camera", "sky", "materials", "scene", "animation"];
+const CAMERA_KEYS: [&str; 31] = [
+    "transform", "matrix", "focal_length", "focal_plane", "f_stop", "fov", "vertical_fov", "horizontal_fov",
+    "focal_length_mm", "sensor_size", "f_number", "aperture_blades", "aperture_rotation", "aperture_image", "cat_eye",
+    "anamorphic_squeeze", "motion", "shutter_open", "shutter_close", "look_at", "up", "autofocus", "focus_target",
+    "shutter_speed", "iso", "projection", "ortho_width", "fisheye_fov", "interocular_distance", "convergence_distance",
+    "omnidirectional_stereo",
+];
+const SKY_KEYS: [&str; 8] = ["sun_dir", "sun_size", "sun_color", "ground_color", "horizon_color", "zenith_color", "atmosphere", "atmosphere_extent"];
+// Shapes in a CSG operation only keep these keys, besides their shape's own.
+const CSG_SHAPE_KEYS: [&str; 2] = ["transform", "matrix"];
+const OBJECT_KEYS: [&str; 11] = ["material", "texture", "transform", "matrix", "motion", "name", "file", "prototype", "medium", "density", "emission_grid"];
+const MATERIAL_SETTINGS: [&str; 7] = ["diffuse", "roughness", "metallic", "emissive", "hair", "melanin", "scale_angle"];
+const MEDIUM_SETTINGS: [&str; 4] = ["absorption", "scattering", "anisotropy", "emission"];
+const TRANSFORM_SETTINGS: [&str; 6] = ["position", "rotation", "euler", "quaternion", "axis_angle", "scale"];
+// Blocks in the scene section that aren't shapes or operations.
+const SCENE_BLOCKS: [&str; 4] = ["group", "prototype", "gltf", "volume"];
+
+type NewShape = fn() -> RenderShape;
+type ParseSetting<T> = fn(&mut VecDeque<&str>) -> LineResult<T>;
+
+// Names read by matching them against these tables, which also give the names suggested for misspelled ones.
+// Shapes start with a line such as `sphere(`. Heights are the full length along the y axis,
+// and instances and CSG objects get their shape once their prototype or operations are read.
+const SHAPES: [(&str, NewShape); 16] = [
+    ("sphere", || RenderShape::Sphere(1.0)),
+    ("box", || RenderShape::Box(Vector::new(1.0, 1.0, 1.0))),
+    ("plane", || RenderShape::Plane),
+    ("quad", || RenderShape::Quad(Vector2::new(1.0, 1.0))),
+    ("disk", || RenderShape::Disk(1.0)),
+    ("cylinder", || RenderShape::Cylinder(1.0, 1.0, true)),
+    ("cone", || RenderShape::Cone(1.0, 0.0, 1.0, true)),
+    ("capsule", || RenderShape::Capsule(1.0, 1.0)),
+    ("torus", || RenderShape::Torus(1.0, 0.25)),
+    ("rounded_box", || RenderShape::RoundedBox(Vector::new(1.0, 1.0, 1.0), 0.1)),
+    ("curves", || RenderShape::Curves(Arc::new(Curves::new(Vec::new(), CurveType::Cylinder)))),
+    ("mesh", || RenderShape::Mesh(Arc::new(Mesh::new(Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new())))),
+    ("heightfield", || RenderShape::Heightfield(Arc::new(Heightfield::new(0, 0, Vec::new())))),
+    ("sdf", || RenderShape::Sdf(Arc::new(Sdf::new(Vector::new(1.0, 1.0, 1.0))))),
+    ("instance", || RenderShape::None),
+    ("csg", || RenderShape::None),
+];
+// Operations in a CSG object, such as `difference(`, holding the shapes they combine.
+const CSG_OPS: [(&str, CsgOp); 3] = [("union", CsgOp::Union), ("intersection", CsgOp::Intersection), ("difference", CsgOp::Difference)];
+// Operations in a distance field shape, such as `twist 1.5(`, read from their settings before the bracket.
+const SDF_OPS: [(&str, ParseSetting<SdfOp>); 8] = [
+    ("union", |_| Ok(SdfOp::Union)),
+    ("smooth_union", |data| get_float(data).map(SdfOp::SmoothUnion)),
+    ("intersection", |_| Ok(SdfOp::Intersection)),
+    ("difference", |_| Ok(SdfOp::Difference)),
+    ("translate", |data| get_vec(data).map(SdfOp::Translate)),
+    ("repeat", |data| get_vec(data).map(SdfOp::Repeat)),
+    ("twist", |data| get_float(data).map(SdfOp::Twist)),
+    ("displace", |data| get_pair(data).map(|(amplitude, frequency)| SdfOp::Displace(amplitude, frequency))),
+];
+// Primitives in a distance field shape take one line, such as `torus 1 0.25`. Heights are the full length.
+const SDF_NODES: [(&str, ParseSetting<SdfNode>); 4] = [
+    ("sphere", |data| get_float(data).map(SdfNode::Sphere)),
+    ("box", |data| get_vec(data).map(SdfNode::Box)),
+    ("torus", |data| get_pair(data).map(|(ring_radius, tube_radius)| SdfNode::Torus(ring_radius, tube_radius))),
+    ("capsule", |data| get_pair(data).map(|(radius, height)| SdfNode::Capsule(radius, height / 2.0))),
+];
+const PROJECTIONS: [(&str, Projection); 7] = [
+    ("perspective", Projection::Perspective),
+    ("orthographic", Projection::Orthographic),
+    ("equirectangular", Projection::Equirectangular),
+    ("cubemap", Projection::CubeMap),
+    ("fisheye", Projection::FisheyeEquidistant),
+    ("fisheye_equidistant", Projection::FisheyeEquidistant),
+    ("fisheye_equisolid", Projection::FisheyeEquisolid),
+];
+const CURVE_TYPES: [(&str, CurveType); 2] = [("ribbon", CurveType::Ribbon), ("cylinder", CurveType::Cylinder)];
+// Animation targets other than materials and objects, which are named like `material.red.diffuse` and `object.ball.transform`.
+const ANIMATION_TARGETS: [(&str, AnimationTarget); 3] = [
+    ("camera.transform", AnimationTarget::CameraTransform),
+    ("camera.focal_plane", AnimationTarget::CameraFocalPlane),
+    ("sky.sun_dir", AnimationTarget::SunDir),
+];
+const INTERPOLATIONS: [(&str, Interpolation); 2] = [("linear", Interpolation::Linear), ("bezier", Interpolation::Bezier)];
+const MATERIAL_PROPERTIES: [(&str, MaterialProperty); 4] = [
+    ("diffuse", MaterialProperty::Diffuse),
+    ("roughness", MaterialProperty::Roughness),
+    ("metallic", MaterialProperty::Metallic),
+    ("emissive", MaterialProperty::Emissive),
+];
 
 enum LoadState{
     Main,
@@ -15,207 +119,924 @@ enum LoadState{
     Sky,
     Materials,
     Scene,
+    Animation,
 }
 
-pub fn load<P: AsRef<Path>>(path: P) -> Option<Scene> {
-    let file = File::open(path);
-    if let Ok(file) = file {
-        let mut scene = Scene::new(
-             Camera::default(),
-             Sky::black(),
-        );
-        let mut reader = BufReader::new(file);
-        
-        let mut load_state = LoadState::Main;
-        
-        let mut materials: HashMap<String, PhysicalMaterial> = HashMap::new();
-        
-        let mut renderable = Renderable::new(Transform::default(), PhysicalMaterial::default(), RenderShape::None);
-        
-        let mut line = "".to_string();
-        while reader.read_line(&mut line).unwrap() != 0 {
-            line = line.trim().to_string();
-            //println!("{line}");
-            
-            if line.starts_with('#') {
-                line.clear();
-                continue;
+// Names used before they may be defined, such as animated objects, checked once the whole file is read.
+enum NameUse {
+    Material(String),
+    Object(String),
+    FocusTarget(String),
+}
+
+// A problem in the line being read, placed in the file once the line is done.
+#[derive(Debug)]
+struct LineError {
+    message: String,
+    token: String,
+    suggestion: Option<String>,
+}
+
+type LineResult<T> = Result<T, LineError>;
+
+impl LineError {
+    fn new(message: impl Into<String>, token: &str) -> Self {
+        Self {
+            message: message.into(),
+            token: token.to_string(),
+            suggestion: None,
+        }
+    }
+
+    fn suggest<'a>(mut self, options: impl IntoIterator<Item = &'a str>) -> Self {
+        self.suggestion = scene_error::suggest(self.token.trim(), options);
+        self
+    }
+
+    // The column is where the token first appears in the line, or the end of the line when something is missing.
+    fn at(self, file: &Path, line: usize, text: &str) -> SceneError {
+        let offset = match self.token.is_empty() {
+            true => text.trim_end().len(),
+            false => text.find(&self.token).unwrap_or(0),
+        };
+        SceneError {
+            file: file.to_path_buf(),
+            line,
+            column: text[..offset].chars().count() + 1,
+            message: self.message,
+            token: self.token,
+            suggestion: self.suggestion,
+        }
+    }
+}
+
+// Loads a .ypt, glTF, or pbrt scene. Problems that don't stop the scene from loading, such as ignored keys,
+// are added to the warnings.
+pub fn load<P: AsRef<Path>>(path: P, warnings: &mut Vec<SceneError>) -> Result<Scene, SceneError> {
+    let path = path.as_ref();
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("gltf" | "glb") => {
+            return gltf_loader::load(path);
+        }
+        Some("pbrt") => {
+            return pbrt_loader::load(path, warnings);
+        }
+        _ => {}
+    }
+    // External files, such as voxel grids, are found relative to the scene file.
+    let scene_dir = path.parent().unwrap_or(Path::new(""));
+    let file = File::open(path).map_err(|err| SceneError::in_file(path, format!("Failed to open scene ({err})")))?;
+    let mut scene = Scene::new(
+         Camera::default(),
+         Sky::black(),
+    );
+    let reader = BufReader::new(file);
+
+    let mut load_state = LoadState::Main;
+
+    let mut materials: HashMap<String, PhysicalMaterial> = HashMap::new();
+
+    let mut renderable = Renderable::new(Transform::default(), PhysicalMaterial::default(), RenderShape::None);
+
+    // Transforms of the groups currently open, outermost first.
+    let mut groups: Vec<Transform> = Vec::new();
+    let mut in_object = false;
+    // The prototype being defined, and how many groups were open outside it.
+    let mut prototype: Option<(Prototype, usize)> = None;
+    // CSG operations open in the current object, innermost last, with the shapes read for each.
+    let mut csg_ops: Vec<(CsgOp, Vec<Csg>)> = Vec::new();
+    // The CSG object's own settings, kept aside while one of its shapes is read.
+    let mut csg_object: Option<Renderable> = None;
+    // The same for operations open in a distance field shape.
+    let mut sdf_ops: Vec<(SdfOp, Vec<SdfNode>)> = Vec::new();
+    // File of the glTF include being read, empty until its file is given.
+    let mut gltf_include: Option<String> = None;
+
+    // Camera aiming and focusing, applied once the whole scene is loaded.
+    let mut look_at = None;
+    let mut up = Vector::unit_y();
+    let mut autofocus = false;
+    // The focus target's name and the line it was given on, once it's known to exist.
+    let mut focus_target = None;
+
+    let mut read_line = |line: &str, warnings: &mut Vec<LineError>, name_uses: &mut Vec<NameUse>| -> LineResult<()> {
+        if line.starts_with('#') {
+            return Ok(());
+        }
+
+        if line.contains("}") {
+            load_state = LoadState::Main;
+        }
+        match load_state {
+            LoadState::Main => {
+                if line.starts_with("camera") {
+                    load_state = LoadState::Camera;
+                } else if line.starts_with("sky") {
+                    load_state = LoadState::Sky;
+                } else if line.starts_with("materials") {
+                    load_state = LoadState::Materials;
+                } else if line.starts_with("scene") {
+                    load_state = LoadState::Scene;
+                } else if line.starts_with("animation") {
+                    load_state = LoadState::Animation;
+                } else if let Some((section, _)) = line.split_once('{') {
+                    return Err(LineError::new("Unknown section", section.trim()).suggest(SECTIONS));
+                } else if !line.is_empty() && line != "}" {
+                    warnings.push(LineError::new("Ignored line", line));
+                }
+            }
+            LoadState::Camera => {
+                let split_line = line.split_once(':');
+                if let Some((name, cam_data)) = split_line {
+                    let cam_data = cam_data.trim();
+                    match name {
+                        "transform" => scene.camera.transform = parse_transform(cam_data, true)?,
+                        "matrix" => scene.camera.transform = parse_matrix(cam_data)?,
+                        "focal_length" => scene.camera.focal_length = parse_value(cam_data)?,
+                        "focal_plane" => scene.camera.focal_plane = parse_value(cam_data)?,
+                        "f_stop" => scene.camera.f_stop = parse_value(cam_data)?,
+                        "fov" | "vertical_fov" => scene.camera.field_of_view = Some(FieldOfView::Vertical(parse_value(cam_data)?)),
+                        "horizontal_fov" => scene.camera.field_of_view = Some(FieldOfView::Horizontal(parse_value(cam_data)?)),
+                        "focal_length_mm" => scene.camera.field_of_view = Some(FieldOfView::FocalLength(parse_value(cam_data)?)),
+                        "sensor_size" => {
+                            let mut sensor_data = cam_data.split_whitespace().collect::<VecDeque<_>>();
+                            (scene.camera.sensor_size.x, scene.camera.sensor_size.y) = get_pair(&mut sensor_data)?;
+                        }
+                        "f_number" => scene.camera.f_number = Some(parse_value(cam_data)?),
+                        "aperture_blades" => scene.camera.aperture_blades = parse_value(cam_data)?,
+                        "aperture_rotation" => scene.camera.aperture_rotation = parse_value(cam_data)?,
+                        "aperture_image" => {
+                            let aperture_image = load_file(ApertureImage::load(scene_dir.join(cam_data)), "aperture image", cam_data)?;
+                            scene.camera.aperture_image = Some(Arc::new(aperture_image));
+                        }
+                        "cat_eye" => scene.camera.cat_eye = parse_value(cam_data)?,
+                        "anamorphic_squeeze" => scene.camera.anamorphic_squeeze = parse_positive(cam_data)?,
+                        "motion" => scene.camera.motion.push(parse_transform(cam_data, true)?),
+                        "shutter_open" => scene.camera.shutter_open = parse_value(cam_data)?,
+                        "shutter_close" => scene.camera.shutter_close = parse_value(cam_data)?,
+                        "look_at" => look_at = Some(parse_point(cam_data)?),
+                        "up" => up = parse_vec(cam_data)?,
+                        "autofocus" => autofocus = parse_value(cam_data)?,
+                        "focus_target" => name_uses.push(NameUse::FocusTarget(cam_data.to_string())),
+                        "shutter_speed" => scene.camera.shutter_speed = Some(parse_fraction(cam_data)?),
+                        "iso" => scene.camera.iso = Some(parse_value(cam_data)?),
+                        "projection" => scene.camera.projection = find_named(&PROJECTIONS, cam_data, "projection")?,
+                        "ortho_width" => scene.camera.ortho_width = parse_value(cam_data)?,
+                        "fisheye_fov" => scene.camera.fisheye_fov = parse_value(cam_data)?,
+                        "interocular_distance" => scene.camera.interocular_distance = parse_value(cam_data)?,
+                        "convergence_distance" => scene.camera.convergence_distance = parse_value(cam_data)?,
+                        "omnidirectional_stereo" => scene.camera.omnidirectional_stereo = parse_value(cam_data)?,
+                        _ => warnings.push(LineError::new("Ignored key", name).suggest(CAMERA_KEYS)),
+                    }
+                } else if !line.is_empty() && !line.contains('}') {
+                    warnings.push(LineError::new("Ignored line", line));
+                }
+            }
+            LoadState::Sky => {
+                let split_line = line.split_once(':');
+                if let Some((name, sky_data)) = split_line {
+                    let sky_data = sky_data.trim();
+                    match name {
+                        "sun_dir" => scene.sky.set_sun_dir(parse_vec(sky_data)?),
+                        "sun_size" => scene.sky.sun_size = parse_value(sky_data)?,
+                        "sun_color" => scene.sky.sun_color = parse_vec(sky_data)?,
+                        "ground_color" => scene.sky.ground_color = parse_vec(sky_data)?,
+                        "horizon_color" => scene.sky.horizon_color = parse_vec(sky_data)?,
+                        "zenith_color" => scene.sky.zenith_color = parse_vec(sky_data)?,
+                        "atmosphere" => scene.sky.atmosphere = Some(parse_medium(sky_data)?),
+                        "atmosphere_extent" => scene.sky.atmosphere_extent = parse_value(sky_data)?,
+                        _ => warnings.push(LineError::new("Ignored key", name).suggest(SKY_KEYS)),
+                    }
+                } else if !line.is_empty() && !line.contains('}') {
+                    warnings.push(LineError::new("Ignored line", line));
+                }
             }
-            
-            if line.contains("}") {
-                load_state = LoadState::Main;
+            LoadState::Materials => {
+                let split_line = line.split_once(':');
+                if let Some((name, mat_data)) = split_line {
+                    let material = parse_material(mat_data)?;
+                    materials.insert(name.to_string(), material);
+                } else if !line.is_empty() && !line.contains('}') {
+                    warnings.push(LineError::new("Ignored line", line));
+                }
             }
-            match load_state {
-                LoadState::Main => {
-                    if line.starts_with("camera") {
-                        load_state = LoadState::Camera;
+            LoadState::Scene => {
+                if line.starts_with("group") {
+                    groups.push(Transform::default());
+                } else if line.starts_with("prototype") && !in_object {
+                    if prototype.is_some() {
+                        return Err(LineError::new("Prototypes can't be nested", "prototype"));
                     }
-                    if line.starts_with("sky") {
-                        load_state = LoadState::Sky;
+                    prototype = Some((Prototype::new(String::new()), groups.len()));
+                } else if let (false, Some((prototype, _)), Some(("name", proto_data))) = (in_object, &mut prototype, line.split_once(':')) {
+                    prototype.name = proto_data.trim().to_string();
+                } else if !in_object && (line.starts_with("transform") || line.starts_with("matrix")) {
+                    let (Some(group), Some((name, trans_data))) = (groups.last_mut(), line.split_once(':')) else {
+                        let name = line.split(':').next().unwrap_or(line).trim();
+                        return Err(LineError::new("Transforms can only be set inside a group or object, but found", name));
+                    };
+                    *group = if name == "matrix" { parse_matrix(trans_data.trim())? } else { parse_transform(trans_data.trim(), false)? };
+                } else if let (true, RenderShape::Sdf(_), Some(op)) = (in_object, &renderable.shape, parse_sdf_op(line)) {
+                    sdf_ops.push((op?, Vec::new()));
+                } else if let (true, RenderShape::Sdf(sdf), Some(node)) = (in_object, &mut renderable.shape, parse_sdf_node(line)) {
+                    match sdf_ops.last_mut() {
+                        Some((_, nodes)) => nodes.push(node?),
+                        None => Arc::make_mut(sdf).nodes.push(node?),
                     }
-                    if line.starts_with("materials") {
-                        load_state = LoadState::Materials;
+                } else if let (true, Some(op)) = (in_object, parse_csg_op(line)) {
+                    csg_ops.push((op, Vec::new()));
+                } else if let (false, Some(shape)) = (csg_ops.is_empty(), parse_shape(line)) {
+                    // CSG finds the inside of each shape, so open ones such as planes can't be combined.
+                    if !shape.is_closed() {
+                        return Err(LineError::new("CSG objects can only combine closed shapes, but found", line.split('(').next().unwrap_or(line).trim()));
                     }
-                    if line.starts_with("scene") {
-                        load_state = LoadState::Scene;
+                    // Shapes in a CSG operation are read like objects, but only their shape and transform are kept.
+                    csg_object = Some(std::mem::replace(&mut renderable, Renderable::new(Transform::default(), PhysicalMaterial::default(), shape)));
+                } else if let Some(shape) = parse_shape(line) {
+                    in_object = true;
+                    renderable.shape = shape;
+                    renderable.medium = None;
+                    renderable.name = None;
+                    renderable.texture = None;
+                    renderable.motion.clear();
+                } else if line.starts_with("gltf") {
+                    in_object = true;
+                    renderable.shape = RenderShape::None;
+                    renderable.transform = Transform::default();
+                    gltf_include = Some(String::new());
+                } else if line.starts_with("volume") {
+                    in_object = true;
+                    // Volumes fill the unit cube of their transform.
+                    renderable.shape = RenderShape::Box(Vector::new(1.0, 1.0, 1.0));
+                    renderable.medium = Some(Medium::new(Vector::zero(), Vector::new(1.0, 1.0, 1.0), 0.0));
+                    renderable.name = None;
+                    renderable.texture = None;
+                    renderable.motion.clear();
+                } else if let (false, Some((name, _))) = (in_object, line.split_once(':')) {
+                    return Err(LineError::new("Keys can only be set inside an object, but found", name));
+                } else if let Some((name, obj_data)) = line.split_once(':') {
+                    let own_keys = shape_keys(&renderable.shape);
+                    if csg_object.is_some() && !CSG_SHAPE_KEYS.contains(&name) && !own_keys.contains(&name) {
+                        let keys = CSG_SHAPE_KEYS.iter().chain(own_keys).copied();
+                        return Err(LineError::new("Shapes in a CSG object only keep their shape and transform, but found", name).suggest(keys));
                     }
-                }
-                LoadState::Camera => {
-                    let split_line = line.split_once(':');
-                    if let Some((name, cam_data)) = split_line {
-                        if name == "transform" {
-                            scene.camera.transform = parse_transform(cam_data.trim());
+                    let obj_data = obj_data.trim();
+                    match (name, &mut renderable.shape) {
+                        ("material", _) => {
+                            renderable.material = *materials.get(obj_data).ok_or_else(|| {
+                                LineError::new("Unknown material", obj_data).suggest(materials.keys().map(String::as_str))
+                            })?;
+                            renderable.material_name = Some(obj_data.to_string());
+                        }
+                        ("texture", _) => {
+                            let texture = load_file(texture::load_texture(scene_dir.join(obj_data)), "texture", obj_data)?;
+                            renderable.texture = Some(Arc::new(texture));
                         }
-                        if name == "focal_length" {
-                            scene.camera.focal_length = cam_data.trim().parse().unwrap();
+                        ("transform", _) => renderable.transform = parse_transform(obj_data, false)?,
+                        ("matrix", _) => renderable.transform = parse_matrix(obj_data)?,
+                        ("motion", _) => renderable.motion.push(parse_transform(obj_data, false)?),
+                        ("name", _) => renderable.name = Some(obj_data.to_string()),
+                        ("file", _) if gltf_include.is_some() => gltf_include = Some(obj_data.to_string()),
+                        // Prototypes are traced on their own, so they can't hold instances of other prototypes.
+                        ("prototype", _) if prototype.is_some() => return Err(LineError::new("Instances can't be placed inside a prototype", obj_data)),
+                        ("prototype", _) => {
+                            let ind = scene.find_prototype(obj_data).ok_or_else(|| {
+                                let names = (0..scene.get_prototype_count()).map(|ind| scene.get_prototype(ind).name.as_str());
+                                LineError::new("Unknown prototype", obj_data).suggest(names)
+                            })?;
+                            let (min, max) = scene.get_prototype(ind).get_bounds();
+                            renderable.shape = RenderShape::Instance(ind, min, max);
                         }
-                        if name == "focal_plane" {
-                            scene.camera.focal_plane = cam_data.trim().parse().unwrap();
+                        ("medium", _) => {
+                            let mut medium = parse_medium(obj_data)?;
+                            if let Some(old_medium) = &renderable.medium {
+                                medium.density = old_medium.density.clone();
+                                medium.emission_grid = old_medium.emission_grid.clone();
+                            }
+                            renderable.medium = Some(medium);
                         }
-                        if name == "f_stop" {
-                            scene.camera.f_stop = cam_data.trim().parse().unwrap();
+                        ("density", _) => {
+                            let grid = load_file(VoxelGrid::load(scene_dir.join(obj_data)), "density grid", obj_data)?;
+                            renderable.medium.get_or_insert_with(Medium::default).density = Some(Arc::new(grid));
                         }
-                    }
-                }
-                LoadState::Sky => {
-                    let split_line = line.split_once(':');
-                    if let Some((name, sky_data)) = split_line {
-                        if name == "sun_dir" {
-                            scene.sky.set_sun_dir(parse_vec(sky_data.trim()));
+                        ("emission_grid", _) => {
+                            let grid = load_file(VoxelGrid::load(scene_dir.join(obj_data)), "emission grid", obj_data)?;
+                            renderable.medium.get_or_insert_with(Medium::default).emission_grid = Some(Arc::new(grid));
                         }
-                        if name == "sun_size" {
-                            scene.sky.sun_size = sky_data.trim().parse().unwrap();
+                        ("radius", RenderShape::Sphere(radius) | RenderShape::Disk(radius)) => *radius = parse_value(obj_data)?,
+                        ("bounds", RenderShape::Box(bounds)) => *bounds = parse_vec(obj_data)?,
+                        ("size", RenderShape::Quad(size)) => {
+                            let mut size_data = obj_data.split_whitespace().collect::<VecDeque<_>>();
+                            (size.x, size.y) = get_pair(&mut size_data)?;
                         }
-                        if name == "sun_color" {
-                            scene.sky.sun_color = parse_vec(sky_data.trim());
+                        ("radius", RenderShape::Cylinder(radius, _, _)) => *radius = parse_value(obj_data)?,
+                        ("height", RenderShape::Cylinder(_, half_height, _)) => *half_height = parse_value::<f64>(obj_data)? / 2.0,
+                        ("capped", RenderShape::Cylinder(_, _, capped)) => *capped = parse_value(obj_data)?,
+                        ("radius", RenderShape::Cone(bottom_radius, _, _, _)) => *bottom_radius = parse_value(obj_data)?,
+                        ("top_radius", RenderShape::Cone(_, top_radius, _, _)) => *top_radius = parse_value(obj_data)?,
+                        ("height", RenderShape::Cone(_, _, half_height, _)) => *half_height = parse_value::<f64>(obj_data)? / 2.0,
+                        ("capped", RenderShape::Cone(_, _, _, capped)) => *capped = parse_value(obj_data)?,
+                        ("radius", RenderShape::Capsule(radius, _)) => *radius = parse_value(obj_data)?,
+                        ("height", RenderShape::Capsule(_, half_height)) => *half_height = parse_value::<f64>(obj_data)? / 2.0,
+                        ("radius", RenderShape::Torus(ring_radius, _)) => *ring_radius = parse_value(obj_data)?,
+                        ("inner_radius", RenderShape::Torus(_, tube_radius)) => *tube_radius = parse_value(obj_data)?,
+                        ("bounds", RenderShape::RoundedBox(bounds, _)) => *bounds = parse_vec(obj_data)?,
+                        ("bevel", RenderShape::RoundedBox(_, radius)) => *radius = parse_value(obj_data)?,
+                        ("file", RenderShape::Curves(curves)) => {
+                            let curve_type = curves.curve_type;
+                            *curves = Arc::new(load_file(Curves::load(scene_dir.join(obj_data), curve_type), "curves", obj_data)?);
                         }
-                        if name == "ground_color" {
-                            scene.sky.ground_color = parse_vec(sky_data.trim());
+                        ("type", RenderShape::Curves(curves)) => Arc::make_mut(curves).curve_type = find_named(&CURVE_TYPES, obj_data, "curve type")?,
+                        ("file", RenderShape::Mesh(mesh)) => {
+                            *mesh = Arc::new(load_file(Mesh::load(scene_dir.join(obj_data)), "mesh", obj_data)?);
                         }
-                        if name == "horizon_color" {
-                            scene.sky.horizon_color = parse_vec(sky_data.trim());
+                        // The terrain keeps its size and height when its heightmap is loaded after them.
+                        ("heightmap", RenderShape::Heightfield(heightfield)) => {
+                            let (size, height_scale) = (heightfield.size, heightfield.height_scale);
+                            let mut loaded = load_file(Heightfield::load(scene_dir.join(obj_data)), "heightmap", obj_data)?;
+                            loaded.size = size;
+                            loaded.height_scale = height_scale;
+                            *heightfield = Arc::new(loaded);
                         }
-                        if name == "zenith_color" {
-                            scene.sky.zenith_color = parse_vec(sky_data.trim());
+                        ("size", RenderShape::Heightfield(heightfield)) => {
+                            let mut size_data = obj_data.split_whitespace().collect::<VecDeque<_>>();
+                            let (width, depth) = get_pair(&mut size_data)?;
+                            if width <= 0.0 || depth <= 0.0 {
+                                return Err(LineError::new("Expected sizes above zero, but found", obj_data));
+                            }
+                            Arc::make_mut(heightfield).size = Vector2::new(width, depth);
+                        }
+                        ("height", RenderShape::Heightfield(heightfield)) => {
+                            Arc::make_mut(heightfield).height_scale = parse_positive(obj_data)?;
+                        }
+                        ("bounds", RenderShape::Sdf(sdf)) => Arc::make_mut(sdf).bounds = parse_vec(obj_data)?,
+                        (_, shape) => {
+                            let keys = OBJECT_KEYS.iter().chain(shape_keys(shape)).copied();
+                            warnings.push(LineError::new("Ignored key", name).suggest(keys));
                         }
                     }
+                } else if let Some((block, _)) = line.split_once('(') {
+                    // Operations have their settings before the bracket, so only the name is compared.
+                    let block = block.split_whitespace().next().unwrap_or_default();
+                    return Err(LineError::new("Unknown shape or operation", block).suggest(block_names()));
+                } else if !line.is_empty() && line != ")" && !line.contains('}') {
+                    warnings.push(LineError::new("Ignored line", line));
                 }
-                LoadState::Materials => {
-                    let split_line = line.split_once(':');
-                    if let Some((name, mat_data)) = split_line {
-                        let material = parse_material(mat_data);
-                        materials.insert(name.to_string(), material);
-                    }
-                }
-                LoadState::Scene => {
-                    if line.starts_with("sphere") {
-                        renderable.shape = RenderShape::Sphere(1.0);
-                    } else if line.starts_with("box") {
-                        renderable.shape = RenderShape::Box(Vector::new(1.0, 1.0, 1.0));
-                    } else {
-                        let split_line = line.split_once(':');
-                        if let Some((name, obj_data)) = split_line {
-                            if name == "material" {
-                                renderable.material = materials[obj_data.trim()];
-                            } else if name == "transform" {
-                                renderable.transform = parse_transform(obj_data.trim());
-                            } else {
-                                match &mut renderable.shape {
-                                    RenderShape::None => {}
-                                    RenderShape::Sphere(radius) => {
-                                        if name == "radius" {
-                                            *radius = obj_data.trim().parse().unwrap();
-                                        }
-                                    }
-                                    RenderShape::Box(bounds) => {
-                                        if name == "bounds" {
-                                            *bounds = parse_vec(obj_data.trim());
-                                        }
-                                    }
+                if line.contains(')') {
+                    if let Some((op, nodes)) = sdf_ops.pop() {
+                        let node = op.apply(nodes);
+                        match (sdf_ops.last_mut(), &mut renderable.shape) {
+                            (Some((_, parent_nodes)), _) => parent_nodes.push(node),
+                            (None, RenderShape::Sdf(sdf)) => Arc::make_mut(sdf).nodes.push(node),
+                            (None, _) => {}
+                        }
+                    } else if let Some(object) = csg_object.take() {
+                        let shape = std::mem::replace(&mut renderable, object);
+                        if !shape.shape.is_closed() {
+                            return Err(LineError::new("CSG objects can only combine closed shapes, but found an uncapped one ending at", ")"));
+                        }
+                        csg_ops.last_mut().unwrap().1.push(Csg::Shape(shape.shape, Box::new(shape.transform)));
+                    } else if let Some((op, shapes)) = csg_ops.pop() {
+                        let csg = Csg::combine(op, shapes).ok_or_else(|| LineError::new("CSG operations need at least one shape", ""))?;
+                        match csg_ops.last_mut() {
+                            Some((_, parent_shapes)) => parent_shapes.push(csg),
+                            None => renderable.shape = RenderShape::Csg(Arc::new(csg)),
+                        }
+                    } else if in_object {
+                        let mut object = renderable.clone();
+                        // Objects in a prototype are only placed by the groups inside the prototype.
+                        let first_group = prototype.as_ref().map_or(0, |(_, depth)| *depth);
+                        if let Some(parent) = groups[first_group..].iter().rev().copied().reduce(|child, parent| child.with_parent(&parent)) {
+                            object.transform = object.transform.with_parent(&parent);
+                            for key in &mut object.motion {
+                                *key = key.with_parent(&parent);
+                            }
+                            object.parent = Some(parent);
+                        }
+                        // Included glTF objects are placed inside the include's transform.
+                        let objects = match gltf_include.take() {
+                            Some(file) => {
+                                let contents = load_file(gltf_loader::import(scene_dir.join(&file)), "glTF file", &file)?;
+                                if let Some((sun_dir, sun_color)) = contents.sun {
+                                    scene.sky.set_sun_dir(object.transform.to_global_vector(sun_dir));
+                                    scene.sky.sun_color = sun_color;
                                 }
+                                contents.objects.into_iter().map(|mut part| {
+                                    part.transform = part.transform.with_parent(&object.transform);
+                                    part.parent = Some(object.transform);
+                                    part
+                                }).collect()
+                            }
+                            None => vec![object],
+                        };
+                        for object in objects {
+                            match &mut prototype {
+                                Some((prototype, _)) => prototype.objects.push(object),
+                                None => scene.add_object(object),
                             }
                         }
+                        in_object = false;
+                    } else if prototype.as_ref().is_some_and(|(_, depth)| *depth == groups.len()) {
+                        scene.add_prototype(prototype.take().unwrap().0);
+                    } else {
+                        groups.pop();
                     }
-                    if line.contains(')') {
-                        scene.add_object(renderable);
+                }
+            }
+            LoadState::Animation => {
+                let split_line = line.split_once(':');
+                if let Some((name, anim_data)) = split_line {
+                    if name == "frames" {
+                        let mut frame_data = anim_data.split_whitespace().collect::<VecDeque<_>>();
+                        let (start_frame, end_frame) = get_pair(&mut frame_data)?;
+                        scene.animation.start_frame = start_frame as usize;
+                        scene.animation.end_frame = end_frame as usize;
+                    } else if let Some((target, key)) = parse_keyframe(name, anim_data.trim())? {
+                        match &target {
+                            AnimationTarget::Material(material, _) => name_uses.push(NameUse::Material(material.clone())),
+                            AnimationTarget::ObjectTransform(object) => name_uses.push(NameUse::Object(object.clone())),
+                            _ => {}
+                        }
+                        scene.animation.add_key(target, key);
+                    } else {
+                        let target_name = name.split_whitespace().next().unwrap_or(name);
+                        let keys = animation_keys(&materials, &scene);
+                        warnings.push(LineError::new("Ignored key", target_name).suggest(keys.iter().map(String::as_str)));
                     }
+                } else if !line.is_empty() && !line.contains('}') {
+                    warnings.push(LineError::new("Ignored line", line));
                 }
             }
-            
-            line.clear();
         }
-        
-        return Some(scene);
+        Ok(())
+    };
+
+    // Names to check once the file is read, with the line they were used on.
+    let mut name_uses = Vec::new();
+    for (index, text) in reader.lines().enumerate() {
+        let text = text.map_err(|err| SceneError::in_file(path, format!("Failed to read scene ({err})")))?;
+        let mut line_warnings = Vec::new();
+        let mut line_names = Vec::new();
+        let result = read_line(text.trim(), &mut line_warnings, &mut line_names);
+        warnings.extend(line_warnings.into_iter().map(|warning| warning.at(path, index + 1, &text)));
+        result.map_err(|err| err.at(path, index + 1, &text))?;
+        name_uses.extend(line_names.into_iter().map(|name_use| (name_use, index + 1, text.clone())));
+    }
+
+    let material_names = materials.keys().map(String::as_str).collect::<Vec<_>>();
+    let object_names = (0..scene.get_object_count()).filter_map(|ind| scene.get_object(ind).name.as_deref()).collect::<Vec<_>>();
+    for (name_use, line, text) in &name_uses {
+        let (message, name, names) = match name_use {
+            NameUse::Material(name) => ("Unknown animated material", name, &material_names),
+            NameUse::Object(name) => ("Unknown animated object", name, &object_names),
+            NameUse::FocusTarget(name) => ("Unknown focus target", name, &object_names),
+        };
+        if !names.contains(&name.as_str()) {
+            return Err(LineError::new(message, name).suggest(names.iter().copied()).at(path, *line, text));
+        }
+        if let NameUse::FocusTarget(name) = name_use {
+            focus_target = Some((name, *line, text));
+        }
+    }
+
+    if let Some(target) = look_at {
+        scene.camera.transform.look_at(target, up);
+    }
+
+    if autofocus || focus_target.is_some() {
+        let mut acceleration_structure = AccelerationStructure::new(Arc::new(scene.clone()));
+        acceleration_structure.generate();
+        let focus_distance = match focus_target.and_then(|(name, line, text)| Some((scene.find_object(name)?, name, line, text))) {
+            Some((object_ind, name, line, text)) => Some(acceleration_structure.get_target_distance(object_ind).unwrap_or_else(|center_distance| {
+                let warning = LineError::new("Focused on the center of the target's box, since the focus ray missed", name);
+                warnings.push(warning.at(path, line, text));
+                center_distance
+            })),
+            None => acceleration_structure.get_focus_distance(),
+        };
+        if let Some(focus_distance) = focus_distance {
+            scene.camera.focal_plane = focus_distance;
+        }
+    }
+
+    Ok(scene)
+}
+
+// Keys read by each shape, besides the ones every object has.
+fn shape_keys(shape: &RenderShape) -> &'static [&'static str] {
+    match shape {
+        RenderShape::Sphere(_) | RenderShape::Disk(_) => &["radius"],
+        RenderShape::Box(_) | RenderShape::Sdf(_) => &["bounds"],
+        RenderShape::Quad(_) => &["size"],
+        RenderShape::Cylinder(..) => &["radius", "height", "capped"],
+        RenderShape::Cone(..) => &["radius", "top_radius", "height", "capped"],
+        RenderShape::Capsule(..) => &["radius", "height"],
+        RenderShape::Torus(..) => &["radius", "inner_radius"],
+        RenderShape::RoundedBox(..) => &["bounds", "bevel"],
+        RenderShape::Curves(_) => &["file", "type"],
+        RenderShape::Mesh(_) => &["file"],
+        RenderShape::Heightfield(_) => &["heightmap", "size", "height"],
+        RenderShape::None | RenderShape::Plane | RenderShape::Instance(..) | RenderShape::Csg(_) => &[],
+    }
+}
+
+fn parse_shape(line: &str) -> Option<RenderShape> {
+    let name = line.split('(').next()?.trim();
+    SHAPES.iter().find(|(shape, _)| *shape == name).map(|(_, new_shape)| new_shape())
+}
+
+fn parse_csg_op(line: &str) -> Option<CsgOp> {
+    let name = line.split('(').next()?.trim();
+    CSG_OPS.iter().find(|(op, _)| *op == name).map(|(_, op)| *op)
+}
+
+// Lines that aren't operations give None, and operations with bad settings give an error.
+fn parse_sdf_op(line: &str) -> Option<LineResult<SdfOp>> {
+    let (op_data, _) = line.split_once('(')?;
+    let mut op_data = op_data.trim().split_whitespace().collect::<VecDeque<_>>();
+    let name = op_data.pop_front()?;
+    let (_, parse_op) = SDF_OPS.iter().find(|(op, _)| *op == name)?;
+    Some(parse_op(&mut op_data))
+}
+
+fn parse_sdf_node(line: &str) -> Option<LineResult<SdfNode>> {
+    let mut node_data = line.trim().split_whitespace().collect::<VecDeque<_>>();
+    let name = node_data.pop_front()?;
+    let (_, parse_node) = SDF_NODES.iter().find(|(node, _)| *node == name)?;
+    Some(parse_node(&mut node_data))
+}
+
+// Every name that opens a block in the scene section.
+fn block_names() -> impl Iterator<Item = &'static str> {
+    let shapes = SHAPES.iter().map(|(name, _)| *name);
+    let csg_ops = CSG_OPS.iter().map(|(name, _)| *name);
+    let sdf_ops = SDF_OPS.iter().map(|(name, _)| *name);
+    SCENE_BLOCKS.into_iter().chain(shapes).chain(csg_ops).chain(sdf_ops)
+}
+
+// Every animation key, with the materials and named objects loaded so far.
+fn animation_keys(materials: &HashMap<String, PhysicalMaterial>, scene: &Scene) -> Vec<String> {
+    let mut keys = vec!["frames".to_string()];
+    keys.extend(ANIMATION_TARGETS.iter().map(|(name, _)| name.to_string()));
+    for material in materials.keys() {
+        keys.extend(MATERIAL_PROPERTIES.iter().map(|(property, _)| format!("material.{material}.{property}")));
     }
-    None
+    let objects = (0..scene.get_object_count()).filter_map(|ind| scene.get_object(ind).name.as_ref());
+    keys.extend(objects.map(|object| format!("object.{object}.transform")));
+    keys
 }
 
-fn parse_material(mat_data: &str) -> PhysicalMaterial {
+// Looks a name up in a table, suggesting the closest name in it when there's no match.
+fn find_named<T: Clone>(table: &[(&str, T)], name: &str, what: &str) -> LineResult<T> {
+    match table.iter().find(|(key, _)| *key == name) {
+        Some((_, value)) => Ok(value.clone()),
+        None => Err(LineError::new(format!("Unknown {what}"), name).suggest(table.iter().map(|(key, _)| *key))),
+    }
+}
+
+fn parse_material(mat_data: &str) -> LineResult<PhysicalMaterial> {
     let mut material = PhysicalMaterial::default();
     let mut mat_data = mat_data.trim().split_whitespace().collect::<VecDeque<_>>();
-    while !mat_data.is_empty() {
-        let val = mat_data.pop_front().unwrap().trim();
-        if val == "diffuse" {
-            material.diffuse = get_vec(&mut mat_data);
-        }
-        if val == "roughness" {
-            material.roughness = get_float(&mut mat_data);
+    while let Some(val) = mat_data.pop_front() {
+        match val {
+            "diffuse" => {
+                if mat_data.front() == Some(&"vertex") {
+                    mat_data.pop_front();
+                    material.vertex_colors = true;
+                } else {
+                    material.diffuse = get_vec(&mut mat_data)?;
+                }
+            }
+            "roughness" => material.roughness = get_float(&mut mat_data)?,
+            "metallic" => material.metallic = get_float(&mut mat_data)?,
+            "emissive" => material.emissive = get_float(&mut mat_data)?,
+            "hair" => {
+                material.hair.get_or_insert_with(Hair::default);
+            }
+            "melanin" => material.hair.get_or_insert_with(Hair::default).melanin = Some(get_pair(&mut mat_data)?),
+            "scale_angle" => material.hair.get_or_insert_with(Hair::default).scale_angle = get_float(&mut mat_data)?,
+            _ => return Err(LineError::new("Unknown material setting", val).suggest(MATERIAL_SETTINGS)),
         }
-        if val == "metallic" {
-            material.metallic = get_float(&mut mat_data);
+    }
+    Ok(material)
+}
+
+// Parses an animation keyframe such as `object.ball.transform @24 bezier: position 0 1 0 ...`.
+// Unknown targets give None, so they can be reported as ignored keys.
+fn parse_keyframe(name: &str, key_data: &str) -> LineResult<Option<(AnimationTarget, Keyframe)>> {
+    let mut name_data = name.split_whitespace();
+    let Some(target_name) = name_data.next() else {
+        return Ok(None);
+    };
+
+    let target = if let Some((_, target)) = ANIMATION_TARGETS.iter().find(|(name, _)| *name == target_name) {
+        target.clone()
+    } else if let Some((material_name, property)) = target_name.strip_prefix("material.").and_then(|material| material.rsplit_once('.')) {
+        AnimationTarget::Material(material_name.to_string(), find_named(&MATERIAL_PROPERTIES, property, "material property")?)
+    } else if let Some(object) = target_name.strip_prefix("object.").and_then(|object| object.strip_suffix(".transform")) {
+        AnimationTarget::ObjectTransform(object.to_string())
+    } else {
+        return Ok(None);
+    };
+
+    let frame = name_data.next().ok_or_else(|| LineError::new("Missing the keyframe's frame, such as @24, after", target_name))?;
+    let frame = parse_value(frame.trim_start_matches('@'))?;
+    let interpolation = match name_data.next() {
+        Some(interpolation) => find_named(&INTERPOLATIONS, interpolation, "interpolation")?,
+        None => Interpolation::Linear,
+    };
+
+    let values = match &target {
+        AnimationTarget::CameraTransform => from_transform(&parse_transform(key_data, true)?),
+        AnimationTarget::ObjectTransform(_) => from_transform(&parse_transform(key_data, false)?),
+        AnimationTarget::SunDir | AnimationTarget::Material(_, MaterialProperty::Diffuse) => {
+            let vec = parse_vec(key_data)?;
+            vec![vec.x, vec.y, vec.z]
         }
-        if val == "emissive" {
-            material.emissive = get_float(&mut mat_data);
+        _ => vec![parse_value(key_data)?],
+    };
+
+    Ok(Some((target, Keyframe { frame, values, interpolation })))
+}
+
+fn parse_medium(medium_data: &str) -> LineResult<Medium> {
+    let mut medium = Medium::default();
+    let mut medium_data = medium_data.trim().split_whitespace().collect::<VecDeque<_>>();
+    while let Some(val) = medium_data.pop_front() {
+        match val {
+            "absorption" => medium.absorption = get_vec(&mut medium_data)?,
+            "scattering" => medium.scattering = get_vec(&mut medium_data)?,
+            "anisotropy" => medium.anisotropy = get_float(&mut medium_data)?.clamp(-0.99, 0.99),
+            "emission" => medium.emission = get_vec(&mut medium_data)?,
+            _ => return Err(LineError::new("Unknown medium setting", val).suggest(MEDIUM_SETTINGS)),
         }
     }
-    material
+    Ok(medium)
 }
 
-fn parse_transform(trans_data: &str) -> Transform {
-    let mut transform = Transform::default();
+// Rotations can be given as `rotation x y z` Euler angles in the default order, `euler XYZ x y z` with an explicit order,
+// `quaternion w x y z`, or `axis_angle x y z angle`, with all angles in degrees.
+// The explicit forms turn local axes into global ones, for objects and cameras alike. `rotation` keeps its original meaning,
+// which turns cameras the same way but objects the opposite way, from global axes into local ones.
+fn parse_transform(trans_data: &str, camera: bool) -> LineResult<Transform> {
+    let mut position = Point::new(0.0, 0.0, 0.0);
+    let mut rotation = Quat::one();
+    let mut scale = Vector::new(1.0, 1.0, 1.0);
     let mut trans_data = trans_data.trim().split_whitespace().collect::<VecDeque<_>>();
-    while !trans_data.is_empty() {
-        let val = trans_data.pop_front().unwrap().trim();
-        if val == "position" {
-            transform.position = get_point(&mut trans_data);
-        }
-        if val == "rotation" {
-            transform.rotation = get_rot(&mut trans_data);
-        }
-        if val == "scale" {
-            transform.scale = get_vec(&mut trans_data);
+    while let Some(val) = trans_data.pop_front() {
+        match val {
+            "position" => position = get_point(&mut trans_data)?,
+            "rotation" => {
+                let euler = euler_rotation(get_rot(&mut trans_data)?);
+                rotation = if camera { euler } else { euler.conjugate() };
+            }
+            "euler" => {
+                let order = trans_data.pop_front().ok_or_else(|| LineError::new("Missing a rotation order, such as XYZ", ""))?;
+                let mut axes = order.to_ascii_uppercase().chars().collect::<Vec<_>>();
+                axes.sort_unstable();
+                if axes != ['X', 'Y', 'Z'] {
+                    return Err(LineError::new("Rotation orders need each of X, Y, and Z once, but found", order));
+                }
+                rotation = euler_rotation_ordered(get_rot(&mut trans_data)?, order);
+            }
+            "quaternion" => {
+                let w = get_float(&mut trans_data)?;
+                rotation = Quat::from_sv(w, get_vec(&mut trans_data)?);
+            }
+            "axis_angle" => {
+                let axis = get_vec(&mut trans_data)?;
+                rotation = axis_angle_rotation(axis, Deg(get_float(&mut trans_data)?));
+            }
+            "scale" => scale = get_vec(&mut trans_data)?,
+            _ => return Err(LineError::new("Unknown transform setting", val).suggest(TRANSFORM_SETTINGS)),
         }
     }
-    
-    transform
+
+    Ok(Transform::new(position, rotation, scale))
+}
+
+// Parses a local to global matrix written row by row. The bottom row of 0 0 0 1 may be left out.
+fn parse_matrix(matrix_data: &str) -> LineResult<Transform> {
+    let values = matrix_data.trim().split_whitespace().map(parse_float).collect::<LineResult<Vec<_>>>()?;
+    if values.len() != 12 && values.len() != 16 {
+        return Err(LineError::new(format!("A matrix needs 12 or 16 values, but found {}", values.len()), ""));
+    }
+    let row = |r: usize| Vector4::new(values[r * 4], values[r * 4 + 1], values[r * 4 + 2], values[r * 4 + 3]);
+    let bottom = if values.len() == 16 { row(3) } else { Vector4::unit_w() };
+    // cgmath matrices are built from columns, so the rows are transposed.
+    Ok(Transform::from_matrix(Matrix4::from_cols(row(0), row(1), row(2), bottom).transpose()))
 }
 
-fn parse_vec(vec_data: &str) -> Vector {
+fn parse_vec(vec_data: &str) -> LineResult<Vector> {
     let mut vec_data = vec_data.trim().split_whitespace().collect::<VecDeque<_>>();
     get_vec(&mut vec_data)
 }
 
-fn get_float(float_iter: &mut VecDeque<&str>) -> f64 {
-    float_iter.pop_front().unwrap().parse::<f64>().unwrap()
+// Parses plain numbers as well as fractions, such as a shutter speed of 1/125.
+fn parse_fraction(data: &str) -> LineResult<f64> {
+    if let Some((numerator, denominator)) = data.split_once('/') {
+        Ok(parse_float(numerator.trim())? / parse_float(denominator.trim())?)
+    } else {
+        parse_float(data)
+    }
+}
+
+fn parse_point(point_data: &str) -> LineResult<Point> {
+    let mut point_data = point_data.trim().split_whitespace().collect::<VecDeque<_>>();
+    get_point(&mut point_data)
+}
+
+fn parse_float(data: &str) -> LineResult<f64> {
+    data.parse().map_err(|_| LineError::new("Expected a number, but found", data))
+}
+
+// Sizes and scales that are divided by.
+fn parse_positive(data: &str) -> LineResult<f64> {
+    let value = parse_float(data)?;
+    if value <= 0.0 {
+        return Err(LineError::new("Expected a number above zero, but found", data));
+    }
+    Ok(value)
+}
+
+// Single values of any type, such as a count or true or false.
+fn parse_value<T: FromStr>(data: &str) -> LineResult<T> {
+    data.parse().map_err(|_| LineError::new("Invalid value", data))
+}
+
+// Files named by a key are reported at their name when they fail to load.
+fn load_file<T, E: Display>(result: Result<T, E>, what: &str, file: &str) -> LineResult<T> {
+    result.map_err(|err| LineError::new(format!("Failed to load {what} ({err})"), file))
+}
+
+fn get_float(float_iter: &mut VecDeque<&str>) -> LineResult<f64> {
+    let data = float_iter.pop_front().ok_or_else(|| LineError::new("Missing a number", ""))?;
+    parse_float(data)
 }
 
-fn get_point(point_iter: &mut VecDeque<&str>) -> Point {
-    let x = get_float(point_iter);
-    let y = get_float(point_iter);
-    let z = get_float(point_iter);
-    Point::new(x, y, z)
+fn get_pair(float_iter: &mut VecDeque<&str>) -> LineResult<(f64, f64)> {
+    let x = get_float(float_iter)?;
+    let y = get_float(float_iter)?;
+    Ok((x, y))
 }
 
-fn get_vec(vec_iter: &mut VecDeque<&str>) -> Vector {
-    let x = get_float(vec_iter);
-    let y = get_float(vec_iter);
-    let z = get_float(vec_iter);
-    Vector::new(x, y, z)
+fn get_point(point_iter: &mut VecDeque<&str>) -> LineResult<Point> {
+    let x = get_float(point_iter)?;
+    let y = get_float(point_iter)?;
+    let z = get_float(point_iter)?;
+    Ok(Point::new(x, y, z))
 }
 
-fn get_rot(rot_iter: &mut VecDeque<&str>) -> Rot {
-    let x = get_float(rot_iter);
-    let y = get_float(rot_iter);
-    let z = get_float(rot_iter);
-    Rot::new(Deg(x), Deg(y), Deg(z))
-}
\ No newline at end of file
+fn get_vec(vec_iter: &mut VecDeque<&str>) -> LineResult<Vector> {
+    let x = get_float(vec_iter)?;
+    let y = get_float(vec_iter)?;
+    let z = get_float(vec_iter)?;
+    Ok(Vector::new(x, y, z))
+}
+
+fn get_rot(rot_iter: &mut VecDeque<&str>) -> LineResult<Rot> {
+    let x = get_float(rot_iter)?;
+    let y = get_float(rot_iter)?;
+    let z = get_float(rot_iter)?;
+    Ok(Rot::new(Deg(x), Deg(y), Deg(z)))
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Basis3, EuclideanSpace, ElementWise, InnerSpace, Rotation, Rotation3};
+    use super::*;
+
+    // Loads scene text from a temporary file, so it can be read like any other scene.
+    fn load_text(name: &str, text: &str) -> Result<(Scene, Vec<SceneError>), SceneError> {
+        let path = std::env::temp_dir().join(format!("yarpt_test_{name}.ypt"));
+        std::fs::write(&path, text).unwrap();
+        let mut warnings = Vec::new();
+        let scene = load(&path, &mut warnings);
+        std::fs::remove_file(&path).unwrap();
+        scene.map(|scene| (scene, warnings))
+    }
+
+    fn assert_points(a: Point, b: Point) {
+        assert!((a - b).magnitude() < 1e-9, "found {a:?}, expected {b:?}");
+    }
+
+    #[test]
+    fn object_rotation_keeps_its_original_meaning() {
+        let transform = parse_transform("position 1 2 3 rotation 30 45 60 scale 1 2 3", false).unwrap();
+        // Objects used to go from global to local space by their Euler basis, then the scale.
+        let basis = Basis3::from_angle_y(Deg(45.0)) * Basis3::from_angle_x(Deg(30.0)) * Basis3::from_angle_z(Deg(60.0));
+        let point = Point::new(-1.0, 0.5, 4.0);
+        let expected = basis.rotate_point(point - Vector::new(1.0, 2.0, 3.0)).div_element_wise(Point::new(1.0, 2.0, 3.0));
+        assert_points(transform.to_local_point(point), expected);
+        assert_points(transform.to_global_point(expected), point);
+
+        // Cameras look along their basis.
+        let camera = parse_transform("rotation 30 45 60", true).unwrap();
+        let forward = camera.get_basis().rotate_vector(Vector::unit_z());
+        assert!((forward - basis.rotate_vector(Vector::unit_z())).magnitude() < 1e-9);
+    }
+
+    #[test]
+    fn explicit_rotations_match_for_objects_and_cameras() {
+        // A quarter turn about y takes the local z axis to global x.
+        for rotation in ["quaternion 0.7071067811865476 0 0.7071067811865476 0", "axis_angle 0 2 0 90", "euler XYZ 0 90 0"] {
+            let object = parse_transform(rotation, false).unwrap();
+            let camera = parse_transform(rotation, true).unwrap();
+            assert_points(object.to_global_point(Point::new(0.0, 0.0, 1.0)), Point::new(1.0, 0.0, 0.0));
+            assert_points(camera.to_global_point(Point::new(0.0, 0.0, 1.0)), Point::new(1.0, 0.0, 0.0));
+        }
+        // Orders multiply in sequence, so XYZ and ZYX differ.
+        let xyz = parse_transform("euler XYZ 90 90 0", false).unwrap().to_global_point(Point::new(0.0, 0.0, 1.0));
+        let zyx = parse_transform("euler ZYX 90 90 0", false).unwrap().to_global_point(Point::new(0.0, 0.0, 1.0));
+        assert!((xyz - zyx).magnitude() > 0.5);
+    }
+
+    #[test]
+    fn bad_rotations() {
+        assert_eq!(parse_transform("euler XYQ 0 0 0", false).unwrap_err().token, "XYQ");
+        assert_eq!(parse_transform("euler XXZ 0 0 0", false).unwrap_err().token, "XXZ");
+        assert!(parse_transform("quaternion 1 0 0", false).is_err());
+        assert_eq!(parse_transform("rotaton 0 0 0", false).unwrap_err().suggestion.as_deref(), Some("rotation"));
+    }
+
+    #[test]
+    fn matrix_rows() {
+        let transform = parse_matrix("1 0 0 5  0 2 0 6  0 0 3 7").unwrap();
+        assert_points(transform.to_global_point(Point::new(1.0, 1.0, 1.0)), Point::new(6.0, 8.0, 10.0));
+        assert_points(transform.to_local_point(Point::origin()), Point::new(-5.0, -3.0, -7.0 / 3.0));
+        assert!(parse_matrix("1 0 0").is_err());
+    }
+
+    #[test]
+    fn keyframes() {
+        let (target, key) = parse_keyframe("object.ball.transform @24 bezier", "position 0 1 0").unwrap().unwrap();
+        assert!(matches!(target, AnimationTarget::ObjectTransform(name) if name == "ball"));
+        assert_eq!((key.frame, key.interpolation), (24.0, Interpolation::Bezier));
+        let (target, key) = parse_keyframe("material.red.diffuse @2", "1 0 0").unwrap().unwrap();
+        assert!(matches!(target, AnimationTarget::Material(name, MaterialProperty::Diffuse) if name == "red"));
+        assert_eq!((key.values, key.interpolation), (vec![1.0, 0.0, 0.0], Interpolation::Linear));
+        assert!(parse_keyframe("camera.focal_plane @0 linear", "5").unwrap().is_some());
+        assert!(parse_keyframe("camera.focus @0", "5").unwrap().is_none());
+
+        assert_eq!(parse_keyframe("sky.sun_dir @0 bezeir", "0 1 0").unwrap_err().suggestion.as_deref(), Some("bezier"));
+        assert!(parse_keyframe("sky.sun_dir @0 smooth", "0 1 0").is_err());
+        assert!(parse_keyframe("material.red.shine @0", "1").is_err());
+        assert!(parse_keyframe("camera.transform", "position 0 0 0").is_err());
+    }
+
+    #[test]
+    fn animated_names_must_exist() {
+        let scene = "materials{\nred: diffuse 1 0 0\n}\nscene{\nsphere(\nname: ball\nmaterial: red\n)\n}\nanimation{\n";
+        let (_, warnings) = load_text("animated", &format!("{scene}object.ball.transform @0: position 0 1 0\nmaterial.red.roughness @0: 0.5\n}}\n")).unwrap();
+        assert!(warnings.is_empty());
+
+        let err = load_text("animated_object", &format!("{scene}object.bal.transform @0: position 0 1 0\n}}\n")).unwrap_err();
+        assert_eq!((err.line, err.token.as_str(), err.suggestion.as_deref()), (11, "bal", Some("ball")));
+        let err = load_text("animated_material", &format!("{scene}material.rad.roughness @0: 0.5\n}}\n")).unwrap_err();
+        assert_eq!((err.line, err.token.as_str(), err.suggestion.as_deref()), (11, "rad", Some("red")));
+    }
+
+    #[test]
+    fn focus_targets() {
+        let scene = "camera{\ntransform: position 0 0 -10 rotation 0 0 0 scale 1 1 1\nfocus_target: TARGET\n}\nscene{\n\
+            torus(\nname: ring\ntransform: position 0 0 0 rotation 90 0 0 scale 1 1 1\n)\n\
+            sphere(\nname: ball\ntransform: position 3 0 0 rotation 0 0 0 scale 1 1 1\n)\n}\n";
+        let (loaded, warnings) = load_text("focus_ball", &scene.replace("TARGET", "ball")).unwrap();
+        assert!((loaded.camera.focal_plane - (109.0f64.sqrt() - 1.0)).abs() < 1e-6);
+        assert!(warnings.is_empty());
+
+        // The ray to the torus goes through its hole.
+        let (loaded, warnings) = load_text("focus_ring", &scene.replace("TARGET", "ring")).unwrap();
+        assert!((loaded.camera.focal_plane - 10.0).abs() < 1e-6);
+        assert_eq!((warnings.len(), warnings[0].line), (1, 3));
+
+        let err = load_text("focus_missing", &scene.replace("TARGET", "bal")).unwrap_err();
+        assert_eq!((err.line, err.token.as_str(), err.suggestion.as_deref()), (3, "bal", Some("ball")));
+    }
+
+    #[test]
+    fn misplaced_keys() {
+        let err = load_text("loose_transform", "scene{\ntransform: position 0 1 0\nsphere(\n)\n}\n").unwrap_err();
+        assert_eq!((err.line, err.token.as_str()), (2, "transform"));
+        assert!(load_text("group_transform", "scene{\ngroup(\ntransform: position 0 1 0\nsphere(\n)\n)\n}\n").is_ok());
+
+        let csg = "scene{\ncsg(\nunion(\nsphere(\nradius: 2\nKEY\n)\nbox(\n)\n)\n)\n}\n";
+        assert!(load_text("csg_shape_keys", &csg.replace("KEY", "transform: position 1 0 0")).is_ok());
+        let err = load_text("csg_material", &csg.replace("KEY", "material: red")).unwrap_err();
+        assert_eq!((err.line, err.token.as_str()), (6, "material"));
+        let err = load_text("csg_misspelled", &csg.replace("KEY", "raduis: 1")).unwrap_err();
+        assert_eq!(err.suggestion.as_deref(), Some("radius"));
+    }
+
+    #[test]
+    fn csg_needs_closed_shapes() {
+        let csg = "scene{\ncsg(\ndifference(\nbox(\n)\nSHAPE\n)\n)\n)\n}\n";
+        assert!(load_text("csg_closed", &csg.replace("SHAPE", "cylinder(")).is_ok());
+        for shape in ["plane", "quad", "disk", "mesh", "heightfield", "instance"] {
+            let err = load_text("csg_open", &csg.replace("SHAPE", &format!("{shape}("))).unwrap_err();
+            assert_eq!((err.line, err.token.as_str()), (6, shape));
+        }
+        let err = load_text("csg_uncapped", &csg.replace("SHAPE", "cylinder(\ncapped: false")).unwrap_err();
+        assert_eq!(err.line, 8);
+    }
+}
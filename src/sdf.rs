@@ -0,0 +1,277 @@
+use cgmath::{ElementWise, EuclideanSpace, InnerSpace};
+use crate::transform::{Point, Vector};
+
+// Steps along the ray are at least this fraction of the bounds' size, so rays grazing the surface still move on.
+const MIN_STEP_FRACTION: f64 = 1e-4;
+const MAX_STEPS: usize = 1024;
+// Halvings used to pin down a crossing once the distance changes sign.
+const BISECTION_STEPS: usize = 32;
+const GRADIENT_EPSILON: f64 = 1e-6;
+// A generous bound on how fast the noise changes, so displaced surfaces aren't stepped through.
+const NOISE_SLOPE: f64 = 2.5;
+
+// Distance functions combined into a tree. Primitives are centered on the origin.
+#[derive(Debug, Clone)]
+pub enum SdfNode {
+    Sphere(f64),
+    // Half sizes along each axis.
+    Box(Vector),
+    // Radius of the ring around the y axis, and radius of the tube.
+    Torus(f64, f64),
+    // Radius, and half height of the straight section along the y axis.
+    Capsule(f64, f64),
+    Union(Vec<SdfNode>),
+    // Union with the seams blended over the given distance.
+    SmoothUnion(f64, Vec<SdfNode>),
+    Intersection(Vec<SdfNode>),
+    // The first node with all the others cut out of it.
+    Difference(Vec<SdfNode>),
+    Translate(Vector, Box<SdfNode>),
+    // Repeats the node on a grid with the given spacing. A spacing of zero leaves that axis alone.
+    Repeat(Vector, Box<SdfNode>),
+    // Twists the node around the y axis by the given radians per unit of height.
+    Twist(f64, Box<SdfNode>),
+    // Moves the surface in and out by noise with the given amplitude and frequency.
+    Displace(f64, f64, Box<SdfNode>),
+}
+
+// Operations that hold other nodes in the scene file, such as `smooth_union 0.2(`.
+#[derive(Debug, Copy, Clone)]
+pub enum SdfOp {
+    Union,
+    SmoothUnion(f64),
+    Intersection,
+    Difference,
+    Translate(Vector),
+    Repeat(Vector),
+    Twist(f64),
+    Displace(f64, f64),
+}
+
+// A distance field shape, clipped to a box that is also used for its place in the acceleration structure.
+#[derive(Debug, Clone)]
+pub struct Sdf {
+    // Half sizes of the bounding box.
+    pub bounds: Vector,
+    // Nodes at the top of the tree, joined by union.
+    pub nodes: Vec<SdfNode>,
+}
+
+impl SdfOp {
+    // Operations on a single node treat several as their union.
+    pub fn apply(self, nodes: Vec<SdfNode>) -> SdfNode {
+        let child = |mut nodes: Vec<SdfNode>| Box::new(if nodes.len() == 1 { nodes.pop().unwrap() } else { SdfNode::Union(nodes) });
+        match self {
+            SdfOp::Union => SdfNode::Union(nodes),
+            SdfOp::SmoothUnion(blend) => SdfNode::SmoothUnion(blend, nodes),
+            SdfOp::Intersection => SdfNode::Intersection(nodes),
+            SdfOp::Difference => SdfNode::Difference(nodes),
+            SdfOp::Translate(offset) => SdfNode::Translate(offset, child(nodes)),
+            SdfOp::Repeat(spacing) => SdfNode::Repeat(spacing, child(nodes)),
+            SdfOp::Twist(rate) => SdfNode::Twist(rate, child(nodes)),
+            SdfOp::Displace(amplitude, frequency) => SdfNode::Displace(amplitude, frequency, child(nodes)),
+        }
+    }
+}
+
+impl SdfNode {
+    pub fn distance(&self, point: Point) -> f64 {
+        match self {
+            SdfNode::Sphere(radius) => point.to_vec().magnitude() - radius,
+            SdfNode::Box(bounds) => {
+                let q = point.to_vec().map(f64::abs) - bounds;
+                q.map(|c| c.max(0.0)).magnitude() + q.x.max(q.y.max(q.z)).min(0.0)
+            }
+            SdfNode::Torus(ring_radius, tube_radius) => {
+                let ring_dist = (point.x * point.x + point.z * point.z).sqrt() - ring_radius;
+                (ring_dist * ring_dist + point.y * point.y).sqrt() - tube_radius
+            }
+            SdfNode::Capsule(radius, half_height) => {
+                (point - Point::new(0.0, point.y.clamp(-half_height, *half_height), 0.0)).magnitude() - radius
+            }
+            SdfNode::Union(nodes) => nodes.iter().map(|node| node.distance(point)).fold(f64::INFINITY, f64::min),
+            SdfNode::SmoothUnion(blend, nodes) => nodes.iter().map(|node| node.distance(point))
+                .reduce(|a, b| smooth_min(a, b, *blend))
+                .unwrap_or(f64::INFINITY),
+            SdfNode::Intersection(nodes) => nodes.iter().map(|node| node.distance(point)).fold(f64::NEG_INFINITY, f64::max),
+            SdfNode::Difference(nodes) => nodes.iter().enumerate()
+                .map(|(i, node)| if i == 0 { node.distance(point) } else { -node.distance(point) })
+                .fold(f64::NEG_INFINITY, f64::max),
+            SdfNode::Translate(offset, node) => node.distance(point - offset),
+            SdfNode::Repeat(spacing, node) => {
+                let cell = |p: f64, s: f64| if s > 0.0 { p - s * (p / s).round() } else { p };
+                node.distance(Point::new(cell(point.x, spacing.x), cell(point.y, spacing.y), cell(point.z, spacing.z)))
+            }
+            SdfNode::Twist(rate, node) => {
+                let (sin, cos) = (rate * point.y).sin_cos();
+                node.distance(Point::new(cos * point.x - sin * point.z, point.y, sin * point.x + cos * point.z))
+            }
+            SdfNode::Displace(amplitude, frequency, node) => {
+                node.distance(point) + amplitude * gradient_noise(point * *frequency)
+            }
+        }
+    }
+
+    // How much faster than the distance to the surface the field can change, within the given radius of the origin.
+    // Twists and displacements bend the field, so steps are shortened by this much to avoid passing through the surface.
+    fn slope(&self, radius: f64) -> f64 {
+        match self {
+            SdfNode::Union(nodes) | SdfNode::SmoothUnion(_, nodes) | SdfNode::Intersection(nodes) | SdfNode::Difference(nodes) => {
+                nodes.iter().map(|node| node.slope(radius)).fold(1.0, f64::max)
+            }
+            SdfNode::Translate(offset, node) => node.slope(radius + offset.magnitude()),
+            SdfNode::Repeat(_, node) => node.slope(radius),
+            SdfNode::Twist(rate, node) => (1.0 + (rate * radius).powi(2)).sqrt() * node.slope(radius),
+            SdfNode::Displace(amplitude, frequency, node) => node.slope(radius) + amplitude * frequency * NOISE_SLOPE,
+            _ => 1.0,
+        }
+    }
+}
+
+impl Sdf {
+    pub fn new(bounds: Vector) -> Self {
+        Self {
+            bounds,
+            nodes: Vec::new(),
+        }
+    }
+
+    pub fn distance(&self, point: Point) -> f64 {
+        self.nodes.iter().map(|node| node.distance(point)).fold(f64::INFINITY, f64::min)
+    }
+
+    pub fn contains(&self, point: Point) -> bool {
+        point.x.abs() < self.bounds.x && point.y.abs() < self.bounds.y && point.z.abs() < self.bounds.z && self.distance(point) < 0.0
+    }
+
+    // Estimated from the change in distance across a small step along each axis.
+    pub fn normal(&self, point: Point) -> Vector {
+        let h = GRADIENT_EPSILON * self.bounds.magnitude();
+        let gradient = Vector::new(
+            self.distance(point + Vector::unit_x() * h) - self.distance(point - Vector::unit_x() * h),
+            self.distance(point + Vector::unit_y() * h) - self.distance(point - Vector::unit_y() * h),
+            self.distance(point + Vector::unit_z() * h) - self.distance(point - Vector::unit_z() * h),
+        );
+        if gradient.magnitude2() > 0.0 { gradient.normalize() } else { Vector::unit_y() }
+    }
+
+    // Sphere traces through the whole span of the ray inside the bounds, so crossings behind the ray's origin are kept too.
+    // The shape is clipped by the bounds, which close it off where it reaches them.
+    pub fn get_crossings(&self, ray_orig: Point, ray_dir: Vector) -> Vec<(f64, Vector)> {
+        let inv_dir = 1.0 / ray_dir;
+        let t0 = (Point::from_vec(-self.bounds) - ray_orig).mul_element_wise(inv_dir);
+        let t1 = (Point::from_vec(self.bounds) - ray_orig).mul_element_wise(inv_dir);
+        let t_min = t0.zip(t1, f64::min);
+        let t_max = t0.zip(t1, f64::max);
+        let near = t_min.x.max(t_min.y.max(t_min.z));
+        let far = t_max.x.min(t_max.y.min(t_max.z));
+        if far < near {
+            return Vec::new();
+        }
+
+        let size = self.bounds.magnitude();
+        let slope = self.nodes.iter().map(|node| node.slope(size)).fold(1.0, f64::max);
+        let speed = ray_dir.magnitude();
+        let distance_at = |dist: f64| self.distance(ray_orig + ray_dir * dist);
+
+        let mut crossings = Vec::new();
+        let mut dist = near;
+        let mut surface_dist = distance_at(dist);
+        if surface_dist < 0.0 {
+            crossings.push((near, self.bounds_normal(ray_orig + ray_dir * near)));
+        }
+        for _i in 0..MAX_STEPS {
+            let step = (surface_dist.abs() / slope).max(MIN_STEP_FRACTION * size) / speed;
+            let next_dist = (dist + step).min(far);
+            let next_surface_dist = distance_at(next_dist);
+
+            if (surface_dist < 0.0) != (next_surface_dist < 0.0) {
+                // The surface is between the two points, so it is found by bisection.
+                let (mut lo, mut hi) = (dist, next_dist);
+                for _j in 0..BISECTION_STEPS {
+                    let mid = (lo + hi) / 2.0;
+                    if (distance_at(mid) < 0.0) == (surface_dist < 0.0) {
+                        lo = mid;
+                    } else {
+                        hi = mid;
+                    }
+                }
+                let crossing = (lo + hi) / 2.0;
+                crossings.push((crossing, self.normal(ray_orig + ray_dir * crossing)));
+            }
+
+            dist = next_dist;
+            surface_dist = next_surface_dist;
+            if dist >= far {
+                break;
+            }
+        }
+        // Rays that give up or leave the bounds while still inside exit there, to keep crossings in pairs.
+        if surface_dist < 0.0 {
+            crossings.push((dist, self.bounds_normal(ray_orig + ray_dir * dist)));
+        }
+        crossings
+    }
+
+    // Normal of the bounds' face nearest to a point on them.
+    fn bounds_normal(&self, point: Point) -> Vector {
+        let q = point.to_vec().map(f64::abs) - self.bounds;
+        let normal = if q.x > q.y && q.x > q.z {
+            Vector::unit_x()
+        } else if q.y > q.z {
+            Vector::unit_y()
+        } else {
+            Vector::unit_z()
+        };
+        normal.zip(point.to_vec(), |n, p| n * p.signum())
+    }
+}
+
+// Polynomial smooth minimum, which blends a and b where they are within the blend distance of each other.
+fn smooth_min(a: f64, b: f64, blend: f64) -> f64 {
+    if blend <= 0.0 {
+        return a.min(b);
+    }
+    let h = (0.5 + 0.5 * (b - a) / blend).clamp(0.0, 1.0);
+    b + (a - b) * h - blend * h * (1.0 - h)
+}
+
+// Perlin style gradient noise, from about -1 to 1.
+fn gradient_noise(point: Point) -> f64 {
+    let cell = point.map(f64::floor);
+    let offset = point - cell;
+    let fade = offset.map(|t| t * t * t * (t * (t * 6.0 - 15.0) + 10.0));
+
+    let mut value = 0.0;
+    for corner in 0..8 {
+        let corner = Vector::new((corner & 1) as f64, ((corner >> 1) & 1) as f64, ((corner >> 2) & 1) as f64);
+        let weight = corner.zip(fade, |c, f| if c > 0.0 { f } else { 1.0 - f });
+        let gradient = lattice_gradient(cell + corner);
+        value += weight.x * weight.y * weight.z * gradient.dot(offset - corner);
+    }
+    value
+}
+
+// One of the twelve edge directions of a cube, picked by hashing the lattice point.
+fn lattice_gradient(point: Point) -> Vector {
+    let mut hash = (point.x as i64 as u64).wrapping_mul(0x9E3779B97F4A7C15)
+        ^ (point.y as i64 as u64).wrapping_mul(0xC2B2AE3D27D4EB4F)
+        ^ (point.z as i64 as u64).wrapping_mul(0x165667B19E3779F9);
+    hash ^= hash >> 29;
+    hash = hash.wrapping_mul(0xBF58476D1CE4E5B9);
+    hash ^= hash >> 32;
+    match hash % 12 {
+        0 => Vector::new(1.0, 1.0, 0.0),
+        1 => Vector::new(-1.0, 1.0, 0.0),
+        2 => Vector::new(1.0, -1.0, 0.0),
+        3 => Vector::new(-1.0, -1.0, 0.0),
+        4 => Vector::new(1.0, 0.0, 1.0),
+        5 => Vector::new(-1.0, 0.0, 1.0),
+        6 => Vector::new(1.0, 0.0, -1.0),
+        7 => Vector::new(-1.0, 0.0, -1.0),
+        8 => Vector::new(0.0, 1.0, 1.0),
+        9 => Vector::new(0.0, -1.0, 1.0),
+        10 => Vector::new(0.0, 1.0, -1.0),
+        _ => Vector::new(0.0, -1.0, -1.0),
+    }
+}
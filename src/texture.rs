@@ -0,0 +1,41 @@
+use std::path::Path;
+use cgmath::Vector2;
+use image::{DynamicImage, ImageResult, Rgb32FImage};
+use crate::transform::Vector;
+
+// Loads an image as linear colors. Integer images are sRGB encoded, and float images, such as EXR, are already linear.
+pub fn load_texture<P: AsRef<Path>>(path: P) -> ImageResult<Rgb32FImage> {
+    let image = image::open(path)?;
+    let is_float = matches!(image, DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_));
+    let mut texture = image.into_rgb32f();
+    if !is_float {
+        for value in texture.iter_mut() {
+            *value = srgb_to_linear(*value);
+        }
+    }
+    Ok(texture)
+}
+
+// Bilinearly filtered color at texture coordinates, which start at the top left of the image and repeat past 0 and 1.
+pub fn sample_texture(texture: &Rgb32FImage, uv: Vector2<f64>) -> Vector {
+    let (width, height) = (texture.width() as i64, texture.height() as i64);
+    let (x, y) = (uv.x * width as f64 - 0.5, uv.y * height as f64 - 0.5);
+    let (x0, y0) = (x.floor(), y.floor());
+    let (tx, ty) = (x - x0, y - y0);
+    let pixel = |px: i64, py: i64| {
+        let [r, g, b] = texture.get_pixel(px.rem_euclid(width) as u32, py.rem_euclid(height) as u32).0;
+        Vector::new(r as f64, g as f64, b as f64)
+    };
+    let (x0, y0) = (x0 as i64, y0 as i64);
+    let top = pixel(x0, y0) * (1.0 - tx) + pixel(x0 + 1, y0) * tx;
+    let bottom = pixel(x0, y0 + 1) * (1.0 - tx) + pixel(x0 + 1, y0 + 1) * tx;
+    top * (1.0 - ty) + bottom * ty
+}
+
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
@@ -1,60 +1,168 @@
-use cgmath::{Point3, Vector3, Basis3, Euler, Deg, Rotation, ElementWise, InnerSpace, EuclideanSpace, Rotation3};
+use cgmath::{Point3, Vector3, Basis3, Euler, Deg, Rad, Rotation, InnerSpace, EuclideanSpace, Rotation3, Matrix, Matrix3, Matrix4, SquareMatrix, Transform as _, Quaternion, One};
 
 pub type Point = Point3<f64>;
 pub type Vector = Vector3<f64>;
 pub type Rot = Euler<Deg<f64>>;
+pub type Quat = Quaternion<f64>;
 pub type Basis = Basis3<f64>;
 
+// Position, rotation, and scale of an object or camera.
+// Rotations turn local axes into global ones for objects and cameras alike, so a quaternion or axis-angle pair
+// from another tool turns both the same way. The loader inverts the original `rotation` key for objects to keep old scenes working.
+// The local to global matrix and its inverse are computed once, rather than for every ray.
 #[derive(Debug, Copy, Clone)]
 pub struct Transform {
-    pub position: Point,
-    pub rotation: Rot,
-    pub scale: Vector,
+    position: Point,
+    rotation: Quat,
+    scale: Vector,
+    matrix: Matrix4<f64>,
+    inverse: Matrix4<f64>,
 }
 
 impl Transform {
-    pub fn new(position: Point, rotation: Rot, scale: Vector) -> Self {
+    pub fn new(position: Point, rotation: Quat, scale: Vector) -> Self {
+        let rotation = rotation.normalize();
+        let rotation_matrix = Matrix3::from(rotation);
+        let matrix = Matrix4::from_translation(position.to_vec())
+            * Matrix4::from(rotation_matrix)
+            * Matrix4::from_nonuniform_scale(scale.x, scale.y, scale.z);
         Self{
             position,
             rotation,
             scale,
+            matrix,
+            inverse: matrix.invert().unwrap_or(Matrix4::identity()),
         }
     }
     
+    // Uses an arbitrary local to global matrix, which may include shear.
+    // Position, rotation, and scale are estimated from it for cameras and animation.
+    pub fn from_matrix(matrix: Matrix4<f64>) -> Self {
+        let axes = Matrix3::from_cols(matrix.x.truncate(), matrix.y.truncate(), matrix.z.truncate());
+        let scale = Vector::new(axes.x.magnitude(), axes.y.magnitude(), axes.z.magnitude());
+        let basis = Matrix3::from_cols(axes.x / scale.x, axes.y / scale.y, axes.z / scale.z);
+        Self{
+            position: Point::from_vec(matrix.w.truncate()),
+            rotation: rotation_from_axes(basis.z, basis.y),
+            scale,
+            matrix,
+            inverse: matrix.invert().unwrap_or(Matrix4::identity()),
+        }
+    }
+    
+    pub fn get_position(&self) -> Point {
+        self.position
+    }
+    
+    pub fn get_rotation(&self) -> Quat {
+        self.rotation
+    }
+    
+    pub fn get_scale(&self) -> Vector {
+        self.scale
+    }
+    
+    // Rotates to face the target point, with the local y axis as close to up as possible.
+    pub fn look_at(&mut self, target: Point, up: Vector) {
+        *self = Transform::new(self.position, rotation_from_axes((target - self.position).normalize(), up), self.scale);
+    }
+    
+    // Places this transform inside a parent's space, as in a group of objects.
+    pub fn with_parent(&self, parent: &Transform) -> Transform {
+        Transform::from_matrix(parent.matrix * self.matrix)
+    }
+    
+    // Interpolates position, rotation, and scale separately, so any shear is lost between keyframes.
+    // Rotations take the shortest path at a constant speed.
+    pub fn lerp(&self, other: &Transform, t: f64) -> Transform {
+        Transform::new(
+            self.position + (other.position - self.position) * t,
+            self.rotation.slerp(other.rotation, t),
+            self.scale + (other.scale - self.scale) * t,
+        )
+    }
+    
     pub fn get_basis(&self) -> Basis {
-        Basis3::from_angle_y(self.rotation.y) * 
-            Basis3::from_angle_x(self.rotation.x) *
-            Basis3::from_angle_z(self.rotation.z)
+        Basis::from(self.rotation)
     }
     
     pub fn to_local_point(&self, point: Point) -> Point {
-        self.get_basis().rotate_point(point.sub_element_wise(self.position))
-            .div_element_wise(Point::from_vec(self.scale))
+        self.inverse.transform_point(point)
     }
     
     pub fn to_local_vector(&self, vector: Vector) -> Vector {
-        self.get_basis().rotate_vector(vector).div_element_wise(self.scale).normalize()
+        self.inverse.transform_vector(vector).normalize()
     }
     
     pub fn to_global_point(&self, point: Point) -> Point {
-        self.get_basis().invert().rotate_point(
-            point.mul_element_wise(
-                Point::from_vec(self.scale)
-            )
-        ).add_element_wise(self.position)
+        self.matrix.transform_point(point)
     }
     
     pub fn to_global_vector(&self, vector: Vector) -> Vector {
-        self.get_basis().invert().rotate_vector(vector.div_element_wise(self.scale)).normalize()
+        self.matrix.transform_vector(vector).normalize()
+    }
+    
+    // Normals stay perpendicular to scaled and sheared surfaces by using the inverse transpose.
+    pub fn to_global_normal(&self, normal: Vector) -> Vector {
+        self.inverse.transpose().transform_vector(normal).normalize()
     }
 }
 
 impl Default for Transform {
     fn default() -> Self {
-        Self{
-            position: Point::new(0.0, 0.0, 0.0),
-            rotation: Rot::new(Deg(0.0), Deg(0.0), Deg(0.0)),
-            scale: Vector::new(1.0, 1.0, 1.0),
+        Transform::new(
+            Point::new(0.0, 0.0, 0.0),
+            Quat::one(),
+            Vector::new(1.0, 1.0, 1.0),
+        )
+    }
+}
+
+// Rotation from Euler angles in the default order, rotating about y, then x, then z.
+pub fn euler_rotation(angles: Rot) -> Quat {
+    euler_rotation_ordered(angles, "YXZ")
+}
+
+// Rotation from Euler angles applied in the given order of axes, such as "XYZ" or "ZYX".
+// The rotations are multiplied in that order, matching `rotation` for "YXZ". Orders are checked when they're read.
+pub fn euler_rotation_ordered(angles: Rot, order: &str) -> Quat {
+    order.chars().fold(Quat::one(), |rotation, axis| {
+        rotation * match axis.to_ascii_uppercase() {
+            'X' => Quat::from_angle_x(angles.x),
+            'Y' => Quat::from_angle_y(angles.y),
+            'Z' => Quat::from_angle_z(angles.z),
+            _ => Quat::one(),
         }
+    })
+}
+
+// Rotation by an angle around an axis, which doesn't have to be normalized.
+pub fn axis_angle_rotation(axis: Vector, angle: Deg<f64>) -> Quat {
+    Quat::from_axis_angle(axis.normalize(), angle)
+}
+
+// Finds the rotation whose basis maps the z axis to forward, and the y axis as close to up as possible.
+fn rotation_from_axes(forward: Vector, up: Vector) -> Quat {
+    let pitch = -forward.y.clamp(-1.0, 1.0).asin();
+    let yaw = forward.x.atan2(forward.z);
+    
+    let mut rotation = Rot::new(Deg::from(Rad(pitch)), Deg::from(Rad(yaw)), Deg(0.0));
+    
+    let basis = Basis::from(euler_rotation(rotation));
+    let base_right = basis.rotate_vector(Vector::unit_x());
+    let base_up = basis.rotate_vector(Vector::unit_y());
+    let roll = (-up.dot(base_right)).atan2(up.dot(base_up));
+    rotation.z = Deg::from(Rad(roll));
+    euler_rotation(rotation)
+}
+
+// Interpolates a transform moving from start through evenly spaced motion keyframes, over times 0 to 1.
+pub fn interpolate_motion(start: &Transform, motion: &[Transform], time: f64) -> Transform {
+    if motion.is_empty() {
+        return *start;
     }
-}
\ No newline at end of file
+    let t = time.clamp(0.0, 1.0) * motion.len() as f64;
+    let segment = (t.floor() as usize).min(motion.len() - 1);
+    let from = if segment == 0 { start } else { &motion[segment - 1] };
+    from.lerp(&motion[segment], t - segment as f64)
+}
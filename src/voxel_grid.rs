@@ -0,0 +1,134 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use crate::transform::Point;
+
+// A dense grid of float values, such as smoke density or fire temperature.
+//
+// Grid files are raw little-endian binary: three u32 dimensions (x, y, z),
+// followed by x * y * z f32 values with x varying fastest, then y, then z.
+#[derive(Debug, Clone)]
+pub struct VoxelGrid {
+    size: [usize; 3],
+    data: Vec<f32>,
+    max_value: f64,
+}
+
+impl VoxelGrid {
+    pub fn new(size: [usize; 3], data: Vec<f32>) -> Self {
+        let max_value = data.iter().fold(0.0f32, |a, b| a.max(*b)) as f64;
+        Self {
+            size,
+            data,
+            max_value,
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+
+        let mut size = [0; 3];
+        let mut word = [0u8; 4];
+        for s in &mut size {
+            reader.read_exact(&mut word)?;
+            *s = u32::from_le_bytes(word) as usize;
+        }
+
+        // The dimensions are checked against the file before anything is allocated for them.
+        let count = size[0].checked_mul(size[1]).and_then(|count| count.checked_mul(size[2]));
+        let count = match count {
+            Some(0) => return Err(io::Error::new(io::ErrorKind::InvalidData, "Voxel grid has no cells.")),
+            Some(count) if count as u64 <= file_len.saturating_sub(12) / 4 => count,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Voxel grid is larger than its file.")),
+        };
+
+        let mut data = Vec::with_capacity(count);
+        for i in 0..count {
+            reader.read_exact(&mut word)?;
+            let value = f32::from_le_bytes(word);
+            // Densities and emission scale the medium's coefficients, so they can't be negative.
+            if !(value >= 0.0 && value.is_finite()) {
+                let (x, y, z) = (i % size[0], i / size[0] % size[1], i / (size[0] * size[1]));
+                let message = format!("Voxel grid has the invalid value {value} at cell ({x}, {y}, {z}), byte offset {}.", 12 + i * 4);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+            }
+            data.push(value);
+        }
+
+        Ok(Self::new(size, data))
+    }
+
+    pub fn get_max_value(&self) -> f64 {
+        self.max_value
+    }
+
+    // Trilinearly interpolated value at a point in the unit cube [0, 1]^3. Points outside the grid are zero.
+    pub fn lookup(&self, point: Point) -> f64 {
+        if point.x < 0.0 || point.y < 0.0 || point.z < 0.0 || point.x > 1.0 || point.y > 1.0 || point.z > 1.0 {
+            return 0.0;
+        }
+
+        let mut base = [0; 3];
+        let mut frac = [0.0; 3];
+        for axis in 0..3 {
+            let coord = (point[axis] * self.size[axis] as f64 - 0.5).clamp(0.0, (self.size[axis] - 1) as f64);
+            base[axis] = (coord.floor() as usize).min(self.size[axis].saturating_sub(2));
+            frac[axis] = coord - base[axis] as f64;
+        }
+
+        let mut value = 0.0;
+        for corner in 0..8 {
+            let mut weight = 1.0;
+            let mut ind = [0; 3];
+            for axis in 0..3 {
+                let upper = (corner >> axis) & 1 == 1;
+                ind[axis] = (base[axis] + upper as usize).min(self.size[axis] - 1);
+                weight *= if upper { frac[axis] } else { 1.0 - frac[axis] };
+            }
+            value += weight * self.get(ind) as f64;
+        }
+        value
+    }
+
+    fn get(&self, ind: [usize; 3]) -> f32 {
+        self.data[(ind[2] * self.size[1] + ind[1]) * self.size[0] + ind[0]]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_values(name: &str, size: [u32; 3], values: &[f32]) -> io::Result<VoxelGrid> {
+        let path = std::env::temp_dir().join(format!("yarpt_test_{name}.vox"));
+        let data = size.iter().flat_map(|s| s.to_le_bytes()).chain(values.iter().flat_map(|v| v.to_le_bytes())).collect::<Vec<_>>();
+        std::fs::write(&path, data).unwrap();
+        let grid = VoxelGrid::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        grid
+    }
+
+    #[test]
+    fn lookup_interpolates_between_cells() {
+        let grid = load_values("grid", [2, 1, 1], &[1.0, 3.0]).unwrap();
+        assert_eq!(grid.get_max_value(), 3.0);
+        // Cell centers sit at a quarter and three quarters of the way across.
+        assert_eq!(grid.lookup(Point::new(0.25, 0.5, 0.5)), 1.0);
+        assert_eq!(grid.lookup(Point::new(0.5, 0.5, 0.5)), 2.0);
+        assert_eq!(grid.lookup(Point::new(1.0, 0.5, 0.5)), 3.0);
+        assert_eq!(grid.lookup(Point::new(1.5, 0.5, 0.5)), 0.0);
+    }
+
+    #[test]
+    fn bad_grids() {
+        assert!(load_values("empty", [0, 1, 1], &[]).is_err());
+        assert!(load_values("short", [2, 2, 1], &[1.0, 1.0, 1.0]).is_err());
+        assert!(load_values("huge", [u32::MAX, u32::MAX, u32::MAX], &[1.0]).is_err());
+        let err = load_values("negative", [2, 2, 1], &[1.0, 1.0, -1.0, 1.0]).unwrap_err();
+        assert!(err.to_string().contains("(0, 1, 0), byte offset 20"), "{err}");
+        assert!(load_values("nan", [1, 1, 1], &[f32::NAN]).is_err());
+    }
+}